@@ -0,0 +1,68 @@
+//! Compares batches with unthrottled I/O against ones capped by
+//! `--io-threads`, to see what the cap costs on a fast local disk.
+//!
+//! Run with `cargo bench --bench io_threads`.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use rayon::prelude::*;
+use resize_image::{process, IoLimit, Options, OutputFormat, Overwrite};
+
+const FILES: usize = 16;
+
+/// Writes `FILES` PNGs into a fresh directory under the system temp dir.
+fn sources() -> Vec<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("resize-image-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let image = DynamicImage::ImageRgb8(RgbImage::from_fn(1200, 800, |x, y| {
+        Rgb([(x % 256) as u8, (y % 256) as u8, ((x * y) % 256) as u8])
+    }));
+    (0..FILES)
+        .map(|i| {
+            let path = dir.join(format!("{i}.png"));
+            image.save_with_format(&path, ImageFormat::Png).unwrap();
+            path
+        })
+        .collect()
+}
+
+fn io_threads(c: &mut Criterion) {
+    let paths = sources();
+    let dir = paths[0].parent().unwrap().to_path_buf();
+    let mut group = c.benchmark_group("io_threads");
+    group
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(10));
+    for permits in [None, Some(1), Some(4)] {
+        let options = Options {
+            target_size: 400,
+            format: Some(OutputFormat::Jpeg),
+            suffix: "_small".into(),
+            overwrite: Overwrite::Force,
+            io_limit: permits.map(|permits| Arc::new(IoLimit::new(permits))),
+            ..Options::default()
+        };
+        let label = permits.map_or("unthrottled".to_string(), |permits| permits.to_string());
+        group.bench_with_input(
+            BenchmarkId::from_parameter(label),
+            &options,
+            |b, options| {
+                b.iter(|| {
+                    paths.par_iter().for_each(|path| {
+                        process(path, &dir, options).unwrap();
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+    fs::remove_dir_all(dir).unwrap();
+}
+
+criterion_group!(benches, io_threads);
+criterion_main!(benches);
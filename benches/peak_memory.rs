@@ -0,0 +1,81 @@
+//! Reports the peak heap use of resizing and encoding one large image, for
+//! comparing changes to how intermediate images are held and freed.
+//!
+//! Run with `cargo bench --bench peak_memory`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::fs;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use image::{Rgb, RgbImage};
+use resize_image::{encode, resize_with, Options, OutputFormat};
+
+/// Counts the bytes allocated through it and the most that were ever live.
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let live = LIVE.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(live, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+const WIDTH: u32 = 6000;
+const HEIGHT: u32 = 4000;
+
+fn main() {
+    let source = RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        Rgb([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8])
+    });
+    let path = env::temp_dir().join(format!("resize-image-peak-memory-{}.png", process::id()));
+    source.save(&path).unwrap();
+    drop(source);
+
+    let options = Options {
+        target_size: 2000,
+        sharpen: Some(1.0),
+        ..Options::default()
+    };
+    // Only the work below counts, not building the source.
+    let baseline = LIVE.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+    let start = Instant::now();
+    let resized = resize_with(&path, &options).unwrap();
+    let jpeg = encode(&resized, OutputFormat::Jpeg, &options).unwrap();
+    let elapsed = start.elapsed();
+    drop(resized);
+    fs::remove_file(&path).unwrap();
+
+    let peak = PEAK.load(Ordering::SeqCst) - baseline;
+    let decoded = WIDTH as usize * HEIGHT as usize * 3;
+    println!(
+        "{}x{} PNG -> {} byte JPEG in {:.2?}",
+        WIDTH,
+        HEIGHT,
+        jpeg.len(),
+        elapsed
+    );
+    println!(
+        "peak heap: {:.1} MB, {:.2}x the decoded source",
+        peak as f64 / (1 << 20) as f64,
+        peak as f64 / decoded as f64
+    );
+}
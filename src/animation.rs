@@ -0,0 +1,58 @@
+use std::io::Cursor;
+
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::{AnimationDecoder, DynamicImage, Frame, RgbaImage};
+
+use crate::encode::{binarize_alpha, dither_to_palette};
+use crate::{Error, Options};
+
+/// Quantization speed for re-encoded frames. 1 is the slowest and best; 10
+/// keeps large animations tractable with little visible difference.
+const GIF_SPEED: i32 = 10;
+
+/// Resizes every frame of an encoded GIF and re-encodes it as an animated
+/// GIF, keeping frame delays and the loop count. Returns the encoded data and
+/// the output width and height.
+pub fn resize_gif(data: &[u8], options: &Options) -> Result<(Vec<u8>, usize, usize), Error> {
+    let repeat = read_repeat(data);
+    let decoder = GifDecoder::new(Cursor::new(data))?;
+
+    let mut frames = Vec::new();
+    let (mut width, mut height) = (0, 0);
+    for frame in decoder.into_frames() {
+        let frame = frame?;
+        let delay = frame.delay();
+        let resized = crate::scale(DynamicImage::ImageRgba8(frame.into_buffer()), options);
+        width = resized.width;
+        height = resized.height;
+        let mut buffer = RgbaImage::from_raw(width as u32, height as u32, resized.into_rgba())
+            .ok_or_else(|| Error::Compress("frame buffer size mismatch".to_string()))?;
+        if options.dither {
+            binarize_alpha(&mut buffer);
+            dither_to_palette(&mut buffer, GIF_SPEED);
+        }
+        frames.push(Frame::from_parts(buffer, 0, 0, delay));
+    }
+
+    let mut buf = Vec::new();
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut buf, GIF_SPEED);
+        if let Some(repeat) = repeat {
+            encoder.set_repeat(repeat)?;
+        }
+        encoder.encode_frames(frames)?;
+    }
+    Ok((buf, width, height))
+}
+
+/// Reads the loop count from the NETSCAPE2.0 application extension. `None`
+/// means the extension is absent and the animation plays once.
+fn read_repeat(data: &[u8]) -> Option<Repeat> {
+    const NETSCAPE: &[u8] = b"NETSCAPE2.0\x03\x01";
+    let pos = data.windows(NETSCAPE.len()).position(|w| w == NETSCAPE)?;
+    let count = data.get(pos + NETSCAPE.len()..pos + NETSCAPE.len() + 2)?;
+    match u16::from_le_bytes([count[0], count[1]]) {
+        0 => Some(Repeat::Infinite),
+        n => Some(Repeat::Finite(n)),
+    }
+}
@@ -0,0 +1,61 @@
+use std::str::FromStr;
+
+/// Sorts outputs into subfolders by their longest edge, from a list like
+/// `sm=640,md=1280,lg`: outputs up to 640 pixels go into `sm`, those up to
+/// 1280 into `md` and the rest into `lg`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SizeBuckets {
+    /// In ascending order of their limits, with the open-ended one last.
+    buckets: Vec<(String, Option<u32>)>,
+}
+
+impl SizeBuckets {
+    /// The subfolder an output of `width` by `height` goes into.
+    pub fn bucket_for(&self, width: u32, height: u32) -> &str {
+        let edge = width.max(height);
+        self.buckets
+            .iter()
+            .find(|(_, limit)| limit.is_none_or(|limit| edge <= limit))
+            .map_or("", |(name, _)| name)
+    }
+}
+
+impl FromStr for SizeBuckets {
+    type Err = String;
+
+    /// Every bucket but the last needs a limit above the one before it, and
+    /// the last takes none, since it holds everything larger.
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut buckets = Vec::new();
+        for part in s.split(',') {
+            let (name, limit) = match part.split_once('=') {
+                Some((name, limit)) => {
+                    let limit = limit
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|&limit| limit > 0)
+                        .ok_or_else(|| format!("{} is not a positive edge length", limit))?;
+                    (name, Some(limit))
+                }
+                None => (part, None),
+            };
+            if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+                return Err(format!("{:?} is not a folder name", name));
+            }
+            match buckets.last() {
+                Some((_, None)) => return Err("only the last bucket can be open-ended".to_string()),
+                Some((_, Some(previous))) if limit.is_some_and(|limit| limit <= *previous) => {
+                    return Err("limits must increase from one bucket to the next".to_string())
+                }
+                _ => {}
+            }
+            buckets.push((name.to_string(), limit));
+        }
+        if buckets.last().is_some_and(|(_, limit)| limit.is_some()) {
+            return Err(
+                "the last bucket takes no limit, as it holds everything larger".to_string(),
+            );
+        }
+        Ok(SizeBuckets { buckets })
+    }
+}
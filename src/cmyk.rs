@@ -0,0 +1,146 @@
+use image::error::{DecodingError, ImageFormatHint};
+use image::{DynamicImage, ImageError, ImageFormat, ImageResult, RgbImage};
+use jpeg_decoder::{Decoder, PixelFormat};
+
+/// Starts the APP14 segment Adobe software writes into CMYK JPEGs.
+const ADOBE_SIGNATURE: &[u8] = b"Adobe";
+
+/// Decodes a CMYK or YCCK JPEG to RGB, or returns `None` for any other
+/// JPEG. Adobe's marker means the ink values are stored inverted, as
+/// Photoshop writes them; without it they are stored as they are. `image`
+/// always assumes the former, which turns the latter into a negative.
+pub(crate) fn decode_cmyk_jpeg(data: &[u8]) -> Option<ImageResult<DynamicImage>> {
+    let mut decoder = Decoder::new(data);
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    if info.pixel_format != PixelFormat::CMYK32 {
+        return None;
+    }
+    let decoded = decoder.decode().map_err(|e| {
+        ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Jpeg),
+            e,
+        ))
+    });
+    let cmyk = match decoded {
+        Ok(cmyk) => cmyk,
+        Err(e) => return Some(Err(e)),
+    };
+    // The decoder hands back the stored values inverted, so without the
+    // marker they are already the amounts of paper left uncovered.
+    let inverted = has_adobe_marker(data);
+    let uncovered = |v: u8| if inverted { 255 - v as u32 } else { v as u32 };
+    let mut rgb = Vec::with_capacity(cmyk.len() / 4 * 3);
+    for px in cmyk.chunks_exact(4) {
+        let k = uncovered(px[3]);
+        rgb.extend(
+            px[..3]
+                .iter()
+                .map(|&v| ((uncovered(v) * k + 127) / 255) as u8),
+        );
+    }
+    let img = RgbImage::from_raw(info.width as u32, info.height as u32, rgb)?;
+    Some(Ok(DynamicImage::ImageRgb8(img)))
+}
+
+/// Looks for Adobe's APP14 segment among those before the first scan.
+fn has_adobe_marker(data: &[u8]) -> bool {
+    let mut pos = 2;
+    while let Some(&[0xFF, marker, hi, lo]) = data.get(pos..pos + 4) {
+        match marker {
+            // Start of scan or end of image: no more metadata follows.
+            0xDA | 0xD9 => break,
+            // Fill bytes before a marker.
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            _ => {}
+        }
+        let len = u16::from_be_bytes([hi, lo]) as usize;
+        if marker == 0xEE && data[pos + 4..].starts_with(ADOBE_SIGNATURE) {
+            return true;
+        }
+        pos += 2 + len;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use mozjpeg::{ColorSpace, Compress};
+
+    use super::*;
+    use crate::{resize_bytes_with, Options};
+
+    /// The inks, from 0 to 255, that print [`RED`].
+    const INK: [u8; 4] = [55, 215, 215, 0];
+    const RED: [u8; 3] = [200, 40, 40];
+
+    /// A flat 16x16 CMYK JPEG of `INK`. Adobe software stores the inks
+    /// inverted and says so with its marker; other print software stores
+    /// them as they are and writes no marker.
+    fn cmyk_jpeg(adobe: bool) -> Vec<u8> {
+        let stored = INK.map(|v| if adobe { 255 - v } else { v });
+        let mut comp = Compress::new(ColorSpace::JCS_CMYK);
+        comp.set_size(16, 16);
+        comp.set_quality(95.0);
+        comp.set_mem_dest();
+        comp.start_compress();
+        comp.write_scanlines(&stored.repeat(16 * 16));
+        comp.finish_compress();
+        let jpeg = comp.data_to_vec().unwrap();
+        if adobe {
+            return jpeg;
+        }
+        // mozjpeg always writes the marker, so it is cut out again.
+        let pos = jpeg
+            .windows(9)
+            .position(|w| w[..2] == [0xFF, 0xEE] && &w[4..] == ADOBE_SIGNATURE)
+            .unwrap();
+        let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        [&jpeg[..pos], &jpeg[pos + 2 + len..]].concat()
+    }
+
+    fn center(jpeg: &[u8], strip_to_baseline: bool) -> [u8; 3] {
+        let options = Options {
+            strip_to_baseline,
+            ..Options::default()
+        };
+        let resized = resize_bytes_with(jpeg, &options).unwrap();
+        let i = (8 * resized.width + 8) * 3;
+        resized.data[i..i + 3].try_into().unwrap()
+    }
+
+    fn close(a: [u8; 3], b: [u8; 3]) -> bool {
+        a.iter().zip(b).all(|(&a, b)| a.abs_diff(b) <= 8)
+    }
+
+    #[test]
+    fn adobe_cmyk_decodes_to_its_colors() {
+        let jpeg = cmyk_jpeg(true);
+        assert!(has_adobe_marker(&jpeg));
+        for strip_to_baseline in [true, false] {
+            let rgb = center(&jpeg, strip_to_baseline);
+            assert!(close(rgb, RED), "{:?}", rgb);
+        }
+    }
+
+    #[test]
+    fn cmyk_without_the_marker_needs_the_flag() {
+        let jpeg = cmyk_jpeg(false);
+        assert!(!has_adobe_marker(&jpeg));
+        let rgb = center(&jpeg, true);
+        assert!(close(rgb, RED), "{:?}", rgb);
+        // Read as inverted, it comes out a negative.
+        let rgb = center(&jpeg, false);
+        assert!(!close(rgb, RED), "{:?}", rgb);
+    }
+
+    #[test]
+    fn other_jpegs_are_left_to_image() {
+        let jpeg = crate::compress(&RED.repeat(4 * 4), 4, 4, 90.0).unwrap();
+        assert!(decode_cmyk_jpeg(&jpeg).is_none());
+        assert!(decode_cmyk_jpeg(b"not a jpeg").is_none());
+    }
+}
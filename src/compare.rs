@@ -0,0 +1,115 @@
+use image::{DynamicImage, GrayImage, ImageFormat, Pixel, RgbImage, RgbaImage};
+
+use crate::pixels::flatten;
+use crate::{OutputFormat, ResizedImage};
+
+/// Windows are this many pixels square and start every half window.
+const WINDOW: u32 = 8;
+const STRIDE: u32 = WINDOW / 2;
+/// The usual stabilizing constants, for 8-bit samples.
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// How close an output came to the resized image it was encoded from,
+/// measured on luma.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Comparison {
+    /// Mean SSIM over the image: 1 for identical images, lower the more
+    /// structure was lost.
+    pub ssim: f64,
+    /// Peak signal-to-noise ratio in decibels; infinite when identical.
+    pub psnr: f64,
+}
+
+/// Decodes `encoded` and compares its luma with that of `reference`.
+/// Returns `None` for output formats that can't be decoded back.
+///
+/// When the output has no alpha channel, the reference is flattened onto
+/// `background` first, as it was for encoding. Otherwise both are weighted
+/// by the reference's alpha, since lossy codecs may change the color of
+/// transparent pixels.
+pub(crate) fn compare(
+    reference: &ResizedImage,
+    encoded: &[u8],
+    format: OutputFormat,
+    background: [u8; 3],
+) -> Option<Comparison> {
+    let format = ImageFormat::from_extension(format.extension())?;
+    let output = image::load_from_memory_with_format(encoded, format).ok()?;
+    let mut reference = to_dynamic(reference)?;
+    if output.width() != reference.width() || output.height() != reference.height() {
+        return None;
+    }
+    if let (DynamicImage::ImageRgba8(rgba), false) = (&reference, output.color().has_alpha()) {
+        let rgb = flatten(rgba.as_raw(), background);
+        reference =
+            RgbImage::from_raw(rgba.width(), rgba.height(), rgb).map(DynamicImage::ImageRgb8)?;
+    }
+    let alpha = reference.to_luma_alpha8();
+    let weighted = |img: &DynamicImage| {
+        let mut luma = img.to_luma8();
+        for (px, a) in luma.pixels_mut().zip(alpha.pixels()) {
+            px[0] = (px[0] as u32 * a.channels()[1] as u32 / 255) as u8;
+        }
+        luma
+    };
+    let (reference, output) = (weighted(&reference), weighted(&output));
+    Some(Comparison {
+        ssim: ssim(&reference, &output),
+        psnr: psnr(&reference, &output),
+    })
+}
+
+fn to_dynamic(img: &ResizedImage) -> Option<DynamicImage> {
+    let narrowed = img.to_8bit();
+    let img = narrowed.as_ref().unwrap_or(img);
+    let (width, height, data) = (img.width as u32, img.height as u32, img.data.clone());
+    match img.color.channel_count() {
+        1 => GrayImage::from_raw(width, height, data).map(DynamicImage::ImageLuma8),
+        3 => RgbImage::from_raw(width, height, data).map(DynamicImage::ImageRgb8),
+        _ => RgbaImage::from_raw(width, height, data).map(DynamicImage::ImageRgba8),
+    }
+}
+
+fn ssim(a: &GrayImage, b: &GrayImage) -> f64 {
+    let (width, height) = a.dimensions();
+    // Images smaller than a window are compared as a single one.
+    let (window_width, window_height) = (WINDOW.min(width), WINDOW.min(height));
+    let starts = |len: u32, window: u32| (0..=len - window).step_by(STRIDE as usize);
+    let (mut total, mut windows) = (0.0, 0);
+    for y in starts(height, window_height) {
+        for x in starts(width, window_width) {
+            let samples = (y..y + window_height).flat_map(|y| {
+                (x..x + window_width)
+                    .map(move |x| (a.get_pixel(x, y)[0] as f64, b.get_pixel(x, y)[0] as f64))
+            });
+            total += window_ssim(samples, (window_width * window_height) as f64);
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+fn window_ssim(samples: impl Iterator<Item = (f64, f64)> + Clone, n: f64) -> f64 {
+    let (sum_a, sum_b) = samples
+        .clone()
+        .fold((0.0, 0.0), |(sa, sb), (a, b)| (sa + a, sb + b));
+    let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+    let (var_a, var_b, covariance) = samples.fold((0.0, 0.0, 0.0), |(va, vb, c), (a, b)| {
+        let (da, db) = (a - mean_a, b - mean_b);
+        (va + da * da, vb + db * db, c + da * db)
+    });
+    let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+    ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+}
+
+fn psnr(a: &GrayImage, b: &GrayImage) -> f64 {
+    let squared = a
+        .pixels()
+        .zip(b.pixels())
+        .map(|(a, b)| (a[0] as f64 - b[0] as f64).powi(2))
+        .sum::<f64>();
+    let mse = squared / (a.width() as f64 * a.height() as f64);
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
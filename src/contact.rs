@@ -0,0 +1,188 @@
+use image::imageops;
+use image::{Rgba, RgbaImage};
+
+/// Space around and between the cells.
+const PADDING: u32 = 8;
+const BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const CAPTION_COLOR: Rgba<u8> = Rgba([48, 48, 48, 255]);
+/// Glyphs are 5 pixels wide with one pixel of spacing, and 7 high.
+const GLYPH_WIDTH: u32 = 6;
+const GLYPH_HEIGHT: u32 = 7;
+
+/// Tiles `cells` into a grid of `columns` columns. Each image is centered in
+/// a `cell_size` square with its caption below it, cut short with `..` when
+/// it is wider than the cell. Captions are drawn in a built-in ASCII font,
+/// with `?` for any other character.
+pub fn contact_sheet(cells: &[(String, &RgbaImage)], columns: u32, cell_size: u32) -> RgbaImage {
+    let columns = columns.clamp(1, cells.len().max(1) as u32);
+    let rows = (cells.len() as u32).div_ceil(columns);
+    // Larger cells get proportionally larger text.
+    let scale = (cell_size / 160).max(1);
+    let caption_height = GLYPH_HEIGHT * scale + PADDING / 2;
+    let (cell_width, cell_height) = (cell_size + PADDING, cell_size + caption_height + PADDING);
+    let mut sheet = RgbaImage::from_pixel(
+        columns * cell_width + PADDING,
+        rows * cell_height + PADDING,
+        BACKGROUND,
+    );
+    for (i, (caption, img)) in cells.iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        let (x, y) = (PADDING + column * cell_width, PADDING + row * cell_height);
+        let (free_x, free_y) = (
+            cell_size.saturating_sub(img.width()),
+            cell_size.saturating_sub(img.height()),
+        );
+        imageops::overlay(
+            &mut sheet,
+            *img,
+            (x + free_x / 2) as i64,
+            (y + free_y / 2) as i64,
+        );
+        let fits = (cell_size / (GLYPH_WIDTH * scale)) as usize;
+        let text = fit_caption(caption, fits);
+        let text_width = text.chars().count() as u32 * GLYPH_WIDTH * scale;
+        let text_x = x + cell_size.saturating_sub(text_width) / 2;
+        draw_text(
+            &mut sheet,
+            &text,
+            text_x,
+            y + cell_size + PADDING / 2,
+            scale,
+        );
+    }
+    sheet
+}
+
+/// Shortens `caption` to at most `max_chars`, marking the cut with `..`.
+fn fit_caption(caption: &str, max_chars: usize) -> String {
+    if caption.chars().count() <= max_chars {
+        return caption.to_string();
+    }
+    let kept = max_chars.saturating_sub(2);
+    caption.chars().take(kept).chain("..".chars()).collect()
+}
+
+fn draw_text(img: &mut RgbaImage, text: &str, x: u32, y: u32, scale: u32) {
+    for (i, c) in text.chars().enumerate() {
+        let index = match c {
+            ' '..='~' => c as usize - ' ' as usize,
+            _ => '?' as usize - ' ' as usize,
+        };
+        let left = x + i as u32 * GLYPH_WIDTH * scale;
+        for (column, bits) in FONT[index].iter().enumerate() {
+            for row in 0..GLYPH_HEIGHT {
+                if bits & (1 << row) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let (px, py) = (left + column as u32 * scale + dx, y + row * scale + dy);
+                        if px < img.width() && py < img.height() {
+                            img.put_pixel(px, py, CAPTION_COLOR);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The printable ASCII characters from space to `~`, one byte per column
+/// with the top row in the lowest bit.
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x5f, 0x00, 0x00],
+    [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7f, 0x14, 0x7f, 0x14],
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12],
+    [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x55, 0x22, 0x50],
+    [0x00, 0x05, 0x03, 0x00, 0x00],
+    [0x00, 0x1c, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1c, 0x00],
+    [0x08, 0x2a, 0x1c, 0x2a, 0x08],
+    [0x08, 0x08, 0x3e, 0x08, 0x08],
+    [0x00, 0x50, 0x30, 0x00, 0x00],
+    [0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x60, 0x60, 0x00, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3e, 0x51, 0x49, 0x45, 0x3e],
+    [0x00, 0x42, 0x7f, 0x40, 0x00],
+    [0x42, 0x61, 0x51, 0x49, 0x46],
+    [0x21, 0x41, 0x45, 0x4b, 0x31],
+    [0x18, 0x14, 0x12, 0x7f, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39],
+    [0x3c, 0x4a, 0x49, 0x49, 0x30],
+    [0x01, 0x71, 0x09, 0x05, 0x03],
+    [0x36, 0x49, 0x49, 0x49, 0x36],
+    [0x06, 0x49, 0x49, 0x29, 0x1e],
+    [0x00, 0x36, 0x36, 0x00, 0x00],
+    [0x00, 0x56, 0x36, 0x00, 0x00],
+    [0x08, 0x14, 0x22, 0x41, 0x00],
+    [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08],
+    [0x02, 0x01, 0x51, 0x09, 0x06],
+    [0x32, 0x49, 0x79, 0x41, 0x3e],
+    [0x7e, 0x11, 0x11, 0x11, 0x7e],
+    [0x7f, 0x49, 0x49, 0x49, 0x36],
+    [0x3e, 0x41, 0x41, 0x41, 0x22],
+    [0x7f, 0x41, 0x41, 0x22, 0x1c],
+    [0x7f, 0x49, 0x49, 0x49, 0x41],
+    [0x7f, 0x09, 0x09, 0x09, 0x01],
+    [0x3e, 0x41, 0x49, 0x49, 0x7a],
+    [0x7f, 0x08, 0x08, 0x08, 0x7f],
+    [0x00, 0x41, 0x7f, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3f, 0x01],
+    [0x7f, 0x08, 0x14, 0x22, 0x41],
+    [0x7f, 0x40, 0x40, 0x40, 0x40],
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f],
+    [0x7f, 0x04, 0x08, 0x10, 0x7f],
+    [0x3e, 0x41, 0x41, 0x41, 0x3e],
+    [0x7f, 0x09, 0x09, 0x09, 0x06],
+    [0x3e, 0x41, 0x51, 0x21, 0x5e],
+    [0x7f, 0x09, 0x19, 0x29, 0x46],
+    [0x46, 0x49, 0x49, 0x49, 0x31],
+    [0x01, 0x01, 0x7f, 0x01, 0x01],
+    [0x3f, 0x40, 0x40, 0x40, 0x3f],
+    [0x1f, 0x20, 0x40, 0x20, 0x1f],
+    [0x3f, 0x40, 0x38, 0x40, 0x3f],
+    [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x07, 0x08, 0x70, 0x08, 0x07],
+    [0x61, 0x51, 0x49, 0x45, 0x43],
+    [0x00, 0x7f, 0x41, 0x41, 0x00],
+    [0x02, 0x04, 0x08, 0x10, 0x20],
+    [0x00, 0x41, 0x41, 0x7f, 0x00],
+    [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x01, 0x02, 0x04, 0x00],
+    [0x20, 0x54, 0x54, 0x54, 0x78],
+    [0x7f, 0x48, 0x44, 0x44, 0x38],
+    [0x38, 0x44, 0x44, 0x44, 0x20],
+    [0x38, 0x44, 0x44, 0x48, 0x7f],
+    [0x38, 0x54, 0x54, 0x54, 0x18],
+    [0x08, 0x7e, 0x09, 0x01, 0x02],
+    [0x0c, 0x52, 0x52, 0x52, 0x3e],
+    [0x7f, 0x08, 0x04, 0x04, 0x78],
+    [0x00, 0x44, 0x7d, 0x40, 0x00],
+    [0x20, 0x40, 0x44, 0x3d, 0x00],
+    [0x7f, 0x10, 0x28, 0x44, 0x00],
+    [0x00, 0x41, 0x7f, 0x40, 0x00],
+    [0x7c, 0x04, 0x18, 0x04, 0x78],
+    [0x7c, 0x08, 0x04, 0x04, 0x78],
+    [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0x7c, 0x14, 0x14, 0x14, 0x08],
+    [0x08, 0x14, 0x14, 0x18, 0x7c],
+    [0x7c, 0x08, 0x04, 0x04, 0x08],
+    [0x48, 0x54, 0x54, 0x54, 0x20],
+    [0x04, 0x3f, 0x44, 0x40, 0x20],
+    [0x3c, 0x40, 0x40, 0x20, 0x7c],
+    [0x1c, 0x20, 0x40, 0x20, 0x1c],
+    [0x3c, 0x40, 0x30, 0x40, 0x3c],
+    [0x44, 0x28, 0x10, 0x28, 0x44],
+    [0x0c, 0x50, 0x50, 0x50, 0x3c],
+    [0x44, 0x64, 0x54, 0x4c, 0x44],
+    [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x7f, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00],
+    [0x08, 0x04, 0x08, 0x10, 0x08],
+];
@@ -0,0 +1,27 @@
+use crate::ResizedImage;
+
+/// Share of horizontally adjacent pixel pairs that must be identical for an
+/// image to count as a graphic. Sensor noise keeps photos, even of clear
+/// skies, far below this, while screenshots, diagrams and logos are mostly
+/// flat areas.
+const FLAT_SHARE: f64 = 0.5;
+
+/// Returns `true` if `img` looks like a screenshot, diagram or other flat
+/// graphic rather than a photo, judged by how many neighboring pixels are
+/// exactly equal. Only the pixel data is used, so the same image always
+/// gets the same answer.
+pub fn looks_graphic(img: &ResizedImage) -> bool {
+    let bpp = img.color.bytes_per_pixel() as usize;
+    if img.width < 2 {
+        return false;
+    }
+    let (mut equal, mut pairs) = (0u64, 0u64);
+    for row in img.data.chunks_exact(img.width * bpp) {
+        let pixels = row.chunks_exact(bpp);
+        for (a, b) in pixels.clone().zip(pixels.skip(1)) {
+            pairs += 1;
+            equal += (a == b) as u64;
+        }
+    }
+    pairs > 0 && equal as f64 >= pairs as f64 * FLAT_SHARE
+}
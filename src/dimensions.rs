@@ -0,0 +1,298 @@
+use crate::{FitEdge, Options, ResizePolicy, Rotation};
+
+/// The size a `width` x `height` source is written at, once its EXIF
+/// orientation (when `exif_swapped` says it exchanges the edges) and
+/// `options.rotate` are applied.
+pub(crate) fn expected_dimensions(
+    (width, height): (u32, u32),
+    exif_swapped: bool,
+    options: &Options,
+) -> (u32, u32) {
+    if exif_swapped != options.rotate.is_some_and(Rotation::swaps_dimensions) {
+        output_dimensions(height, width, options)
+    } else {
+        output_dimensions(width, height, options)
+    }
+}
+
+/// Computes the size an image of `width` x `height` is resized to under
+/// `options`, without touching any pixels.
+pub fn output_dimensions(width: u32, height: u32, options: &Options) -> (u32, u32) {
+    if let Some(crop) = options.crop {
+        return crop;
+    }
+
+    let width = width as usize;
+    let height = height as usize;
+
+    let (target_width, target_height) = if let Some(percent) = options.scale {
+        (
+            (width as f32 * percent / 100.0) as usize,
+            (height as f32 * percent / 100.0) as usize,
+        )
+    } else if let Some(megapixels) = options.max_megapixels {
+        let ratio = (megapixels * 1e6 / (width as f64 * height as f64)).sqrt();
+        if ratio >= 1.0 && options.resize_policy == ResizePolicy::OnlyLarger {
+            (width, height)
+        } else {
+            // Truncating both edges keeps the product within the budget.
+            (
+                (width as f64 * ratio) as usize,
+                (height as f64 * ratio) as usize,
+            )
+        }
+    } else if options.max_width.is_some() || options.max_height.is_some() {
+        let fit =
+            |len: usize, max: Option<u32>| max.map_or(f32::INFINITY, |m| m as f32 / len as f32);
+        let ratio = fit(width, options.max_width).min(fit(height, options.max_height));
+        if ratio >= 1.0 && options.resize_policy == ResizePolicy::OnlyLarger {
+            (width, height)
+        } else {
+            // The limiting edge is set exactly rather than through the
+            // ratio, which can land a pixel short.
+            let edge = |len: usize, max: Option<u32>| match max {
+                Some(m) if fit(len, max) == ratio => m as usize,
+                _ => (len as f32 * ratio) as usize,
+            };
+            (
+                edge(width, options.max_width),
+                edge(height, options.max_height),
+            )
+        }
+    } else {
+        let edge = match options.fit_edge {
+            FitEdge::Longest => width.max(height),
+            FitEdge::Shortest => width.min(height),
+        };
+        if options.resize_policy == ResizePolicy::Always || edge > options.target_size {
+            let (width, height) = fit_dimensions(
+                width as u32,
+                height as u32,
+                options.target_size,
+                options.fit_edge,
+            );
+            (width as usize, height as usize)
+        } else {
+            (width, height)
+        }
+    };
+    let dimensions = (target_width.max(1) as u32, target_height.max(1) as u32);
+    match options.round_dimensions {
+        Some(multiple) if multiple > 1 => snap_dimensions(dimensions, multiple),
+        _ => dimensions,
+    }
+}
+
+/// Rounds `width` x `height` down to multiples of `multiple`, but never below
+/// one multiple. The longer edge is rounded down, and the shorter one gets
+/// the multiple that keeps the aspect ratio closest without growing.
+fn snap_dimensions((width, height): (u32, u32), multiple: u32) -> (u32, u32) {
+    let down = |len: u32| (len / multiple * multiple).max(multiple);
+    let (long, short) = (width.max(height), width.min(height));
+    let snapped_long = down(long);
+    let kept = short as f64 * snapped_long as f64 / long as f64;
+    let snapped_short = ((kept / multiple as f64).round() as u32 * multiple)
+        .min(down(short))
+        .max(multiple);
+    if width >= height {
+        (snapped_long, snapped_short)
+    } else {
+        (snapped_short, snapped_long)
+    }
+}
+
+/// Scales `width` x `height` so that `edge` is exactly `target`, enlarging
+/// or shrinking as needed. Either edge of a square counts as its width. The
+/// other edge is truncated, not rounded, and never drops below 1.
+pub fn fit_dimensions(width: u32, height: u32, target: usize, edge: FitEdge) -> (u32, u32) {
+    let (width, height) = (width as usize, height as usize);
+    let fits_width = match edge {
+        FitEdge::Longest => width >= height,
+        FitEdge::Shortest => width <= height,
+    };
+    let (width, height) = if fits_width {
+        let ratio: f32 = target as f32 / width as f32;
+        (target, (height as f32 * ratio) as usize)
+    } else {
+        let ratio: f32 = target as f32 / height as f32;
+        ((width as f32 * ratio) as usize, target)
+    };
+    (width.max(1) as u32, height.max(1) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orientation::{apply_orientation, apply_transform};
+    use image::{DynamicImage, GenericImageView};
+
+    #[test]
+    fn output_fits_the_longest_edge_and_leaves_small_images_alone() {
+        let options = Options::default();
+        assert_eq!(output_dimensions(4000, 3000, &options), (1280, 960));
+        assert_eq!(output_dimensions(3000, 4000, &options), (960, 1280));
+        assert_eq!(output_dimensions(800, 600, &options), (800, 600));
+        let options = Options {
+            resize_policy: ResizePolicy::Always,
+            ..Options::default()
+        };
+        assert_eq!(output_dimensions(640, 480, &options), (1280, 960));
+    }
+
+    #[test]
+    fn output_follows_scale_megapixels_and_edge_limits() {
+        let scale = Options {
+            scale: Some(50.0),
+            ..Options::default()
+        };
+        assert_eq!(output_dimensions(4000, 3000, &scale), (2000, 1500));
+        assert_eq!(output_dimensions(1, 1, &scale), (1, 1));
+
+        let megapixels = Options {
+            max_megapixels: Some(1.0),
+            ..Options::default()
+        };
+        assert_eq!(output_dimensions(4000, 3000, &megapixels), (1154, 866));
+        assert_eq!(output_dimensions(800, 600, &megapixels), (800, 600));
+
+        let width = Options {
+            max_width: Some(1000),
+            ..Options::default()
+        };
+        assert_eq!(output_dimensions(4000, 3000, &width), (1000, 750));
+        let both = Options {
+            max_width: Some(1000),
+            max_height: Some(500),
+            ..Options::default()
+        };
+        assert_eq!(output_dimensions(4000, 3000, &both), (666, 500));
+        assert_eq!(output_dimensions(900, 400, &both), (900, 400));
+    }
+
+    #[test]
+    fn output_is_the_crop_when_one_is_set() {
+        let options = Options {
+            crop: Some((300, 200)),
+            scale: Some(10.0),
+            ..Options::default()
+        };
+        assert_eq!(output_dimensions(4000, 3000, &options), (300, 200));
+    }
+
+    #[test]
+    fn output_rounds_to_a_multiple_after_resizing() {
+        let rounded = |multiple| Options {
+            round_dimensions: Some(multiple),
+            ..Options::default()
+        };
+        assert_eq!(output_dimensions(3000, 2000, &rounded(16)), (1280, 848));
+        assert_eq!(output_dimensions(2000, 3000, &rounded(16)), (848, 1280));
+        assert_eq!(output_dimensions(3000, 2000, &rounded(1)), (1280, 853));
+        assert_eq!(output_dimensions(10, 5, &rounded(8)), (8, 8));
+    }
+
+    #[test]
+    fn expected_matches_the_oriented_and_rotated_pixels() {
+        let rotations = [
+            None,
+            Some(Rotation::R90),
+            Some(Rotation::R180),
+            Some(Rotation::R270),
+        ];
+        for orientation in 1..=8 {
+            for rotate in rotations {
+                let options = Options {
+                    target_size: 10,
+                    resize_policy: ResizePolicy::Always,
+                    rotate,
+                    ..Options::default()
+                };
+                let img = apply_orientation(DynamicImage::new_rgb8(4, 2), orientation);
+                let (width, height) = apply_transform(img, rotate, None).dimensions();
+                assert_eq!(
+                    expected_dimensions((4, 2), orientation >= 5, &options),
+                    output_dimensions(width, height, &options),
+                    "orientation {orientation}, rotate {rotate:?}",
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn expected_cancels_a_swapped_orientation_with_a_quarter_turn() {
+        let options = Options::default();
+        assert_eq!(
+            expected_dimensions((4000, 2000), false, &options),
+            (1280, 640)
+        );
+        assert_eq!(
+            expected_dimensions((4000, 2000), true, &options),
+            (640, 1280)
+        );
+        let options = Options {
+            rotate: Some(Rotation::R90),
+            ..Options::default()
+        };
+        assert_eq!(
+            expected_dimensions((4000, 2000), true, &options),
+            (1280, 640)
+        );
+    }
+
+    #[test]
+    fn snap_rounds_the_long_edge_down_and_keeps_the_aspect_ratio() {
+        assert_eq!(snap_dimensions((1920, 1080), 16), (1920, 1072));
+        assert_eq!(snap_dimensions((1080, 1920), 16), (1072, 1920));
+        assert_eq!(snap_dimensions((100, 100), 16), (96, 96));
+        // The kept ratio would round the short edge up past its own floor.
+        assert_eq!(snap_dimensions((1000, 333), 16), (992, 320));
+        assert_eq!(snap_dimensions((333, 1000), 16), (320, 992));
+    }
+
+    #[test]
+    fn snap_never_goes_below_one_multiple() {
+        assert_eq!(snap_dimensions((10, 5), 16), (16, 16));
+        assert_eq!(snap_dimensions((3, 2), 8), (8, 8));
+        assert_eq!(snap_dimensions((1, 1), 4), (4, 4));
+        assert_eq!(snap_dimensions((4000, 1), 16), (4000, 16));
+    }
+
+    #[test]
+    fn snap_to_one_changes_nothing() {
+        for dimensions in [(123, 45), (1, 1), (7, 9000)] {
+            assert_eq!(snap_dimensions(dimensions, 1), dimensions);
+        }
+    }
+
+    #[test]
+    fn fit_sets_the_chosen_edge_exactly() {
+        assert_eq!(fit_dimensions(400, 200, 100, FitEdge::Longest), (100, 50));
+        assert_eq!(fit_dimensions(200, 400, 100, FitEdge::Longest), (50, 100));
+        assert_eq!(fit_dimensions(400, 200, 100, FitEdge::Shortest), (200, 100));
+        assert_eq!(fit_dimensions(200, 400, 100, FitEdge::Shortest), (100, 200));
+        // Already at the target, and enlarging.
+        assert_eq!(fit_dimensions(100, 50, 100, FitEdge::Longest), (100, 50));
+        assert_eq!(fit_dimensions(10, 5, 100, FitEdge::Longest), (100, 50));
+    }
+
+    #[test]
+    fn fit_treats_a_square_like_either_edge() {
+        for edge in [FitEdge::Longest, FitEdge::Shortest] {
+            assert_eq!(fit_dimensions(500, 500, 100, edge), (100, 100));
+        }
+    }
+
+    #[test]
+    fn fit_truncates_the_other_edge() {
+        assert_eq!(fit_dimensions(3, 2, 100, FitEdge::Longest), (100, 66));
+        assert_eq!(fit_dimensions(1000, 667, 500, FitEdge::Longest), (500, 333));
+        assert_eq!(fit_dimensions(667, 1000, 500, FitEdge::Longest), (333, 500));
+    }
+
+    #[test]
+    fn fit_keeps_extreme_aspect_ratios_at_least_a_pixel_wide() {
+        assert_eq!(fit_dimensions(10_000, 1, 100, FitEdge::Longest), (100, 1));
+        assert_eq!(fit_dimensions(1, 10_000, 100, FitEdge::Longest), (1, 100));
+        assert_eq!(fit_dimensions(1, 100, 10, FitEdge::Shortest), (10, 1000));
+    }
+}
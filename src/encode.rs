@@ -0,0 +1,1087 @@
+use std::borrow::{Borrow, Cow};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::io::{Cursor, Seek, Write};
+
+use color_quant::NeuQuant;
+use image::codecs::bmp::BmpEncoder;
+use image::codecs::gif::GifEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::{WebPEncoder, WebPQuality};
+use image::imageops::{self, ColorMap};
+use image::{ColorType, ImageEncoder, ImageFormat, RgbaImage};
+use mozjpeg::{ColorSpace, Compress, Marker, ScanMode};
+use tiff::encoder::compression::{Compression, Deflate, Lzw, Uncompressed};
+use tiff::encoder::{colortype, Rational, TiffEncoder, TiffValue};
+use tiff::tags::ResolutionUnit;
+use zune_core::bit_depth::BitDepth;
+use zune_core::colorspace::ColorSpace as JxlColorSpace;
+use zune_core::options::EncoderOptions;
+use zune_jpegxl::JxlSimpleEncoder;
+
+use crate::pixels::{flatten, gray_to_rgb, to_inverted_cmyk};
+use crate::{compare, icc, strip, Error, Options, ResizedImage};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    WebP,
+    Gif,
+    Avif,
+    Tiff,
+    Bmp,
+    /// Always lossless; there is no pure-Rust lossy JPEG XL encoder.
+    Jxl,
+}
+
+impl OutputFormat {
+    /// Picks the encoder for a file extension. Anything that isn't PNG, WebP,
+    /// GIF, AVIF, TIFF, BMP or JPEG XL is re-encoded as JPEG.
+    pub fn from_extension(ext: &OsStr) -> Self {
+        match ext.to_string_lossy().to_ascii_lowercase().as_str() {
+            "png" => OutputFormat::Png,
+            "webp" => OutputFormat::WebP,
+            "gif" => OutputFormat::Gif,
+            "avif" => OutputFormat::Avif,
+            "tif" | "tiff" => OutputFormat::Tiff,
+            "bmp" => OutputFormat::Bmp,
+            "jxl" => OutputFormat::Jxl,
+            _ => OutputFormat::Jpeg,
+        }
+    }
+
+    /// The format with the same encoding as a decoded image, if there is an
+    /// encoder for it.
+    pub fn from_image_format(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Jpeg => Some(OutputFormat::Jpeg),
+            ImageFormat::Png => Some(OutputFormat::Png),
+            ImageFormat::WebP => Some(OutputFormat::WebP),
+            ImageFormat::Gif => Some(OutputFormat::Gif),
+            ImageFormat::Avif => Some(OutputFormat::Avif),
+            ImageFormat::Tiff => Some(OutputFormat::Tiff),
+            ImageFormat::Bmp => Some(OutputFormat::Bmp),
+            _ => None,
+        }
+    }
+
+    /// The default output for a source: the same format, or JPEG when it
+    /// can't be written back.
+    pub fn for_input(format: ImageFormat) -> Self {
+        Self::from_image_format(format).unwrap_or(OutputFormat::Jpeg)
+    }
+
+    /// Returns `true` if `ext` is a valid extension for this format.
+    pub fn matches_extension(self, ext: &OsStr) -> bool {
+        let ext = ext.to_string_lossy().to_ascii_lowercase();
+        match self {
+            OutputFormat::Jpeg => ext == "jpg" || ext == "jpeg",
+            OutputFormat::Png => ext == "png",
+            OutputFormat::WebP => ext == "webp",
+            OutputFormat::Gif => ext == "gif",
+            OutputFormat::Avif => ext == "avif",
+            OutputFormat::Tiff => ext == "tif" || ext == "tiff",
+            OutputFormat::Bmp => ext == "bmp",
+            OutputFormat::Jxl => ext == "jxl",
+        }
+    }
+
+    /// Returns `true` if the encoder's output size depends on the quality.
+    pub fn has_quality(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Jpeg | OutputFormat::WebP | OutputFormat::Avif
+        )
+    }
+
+    /// A lowercase name for the format, as written in sidecar files.
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpeg",
+            OutputFormat::Png => "png",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Gif => "gif",
+            OutputFormat::Avif => "avif",
+            OutputFormat::Tiff => "tiff",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Jxl => "jxl",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png => "png",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Gif => "gif",
+            OutputFormat::Avif => "avif",
+            OutputFormat::Tiff => "tiff",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Jxl => "jxl",
+        }
+    }
+}
+
+/// How TIFF strips are compressed. All of them are lossless.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+}
+
+/// JPEG chroma subsampling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subsampling {
+    /// Chroma at quarter resolution. mozjpeg's default.
+    S420,
+    /// Chroma at half horizontal resolution.
+    S422,
+    /// Full-resolution chroma, best for sharp colored text.
+    S444,
+}
+
+impl Subsampling {
+    /// The size of one chroma sample in luma pixels, as mozjpeg expects it.
+    fn chroma_pixel_size(self) -> (u8, u8) {
+        match self {
+            Subsampling::S420 => (2, 2),
+            Subsampling::S422 => (2, 1),
+            Subsampling::S444 => (1, 1),
+        }
+    }
+}
+
+/// The color space JPEG output is written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JpegColorSpace {
+    /// YCbCr, or plain luminance for grayscale, as every viewer expects.
+    Rgb,
+    /// CMYK, converted naively from RGB with black as the darkest channel,
+    /// for print shops that want it. There is no color management, so
+    /// colors only come out approximately right.
+    Cmyk,
+    /// CMYK stored as YCbCr plus black, which compresses better.
+    Ycck,
+}
+
+/// Encodes a resized image into `format` using the quality and encoder
+/// settings from `options`. Transparent images are composited onto
+/// `options.background` when the format has no alpha channel, and 16-bit
+/// images are reduced to 8 bits for every format but PNG and TIFF.
+pub fn encode(
+    img: &ResizedImage,
+    format: OutputFormat,
+    options: &Options,
+) -> Result<Vec<u8>, Error> {
+    let quality = options.quality_for(format);
+    let narrowed = match format {
+        OutputFormat::Png | OutputFormat::Tiff => None,
+        _ => img.to_8bit(),
+    };
+    let img = narrowed.as_ref().unwrap_or(img);
+    match format {
+        OutputFormat::Jpeg => {
+            let icc_profile = img
+                .icc_profile
+                .as_deref()
+                .filter(|_| !options.strip && !options.strip_icc)
+                .or_else(|| options.assume_srgb.then(icc::srgb_profile));
+            let exif = img.exif.as_deref().filter(|_| !options.strip);
+            let mut data = match img.color {
+                ColorType::Rgba8 => {
+                    let rgb = flatten(&img.data, options.background);
+                    let (w, h) = (img.width, img.height);
+                    let stride = w * 3;
+                    compress_with(
+                        &rgb,
+                        w,
+                        h,
+                        stride,
+                        ColorType::Rgb8,
+                        icc_profile,
+                        exif,
+                        options,
+                    )
+                }
+                color => compress_with(
+                    &img.data,
+                    img.width,
+                    img.height,
+                    img.width * color.channel_count() as usize,
+                    color,
+                    icc_profile,
+                    exif,
+                    options,
+                ),
+            }?;
+            if let Some(dpi) = options.dpi {
+                set_density(&mut data, dpi);
+            }
+            // mozjpeg writes nothing beyond the JFIF header on its own, but
+            // stripping is promised, so it is enforced on the output itself.
+            if options.strip {
+                Ok(strip::strip_jpeg(&data))
+            } else {
+                Ok(data)
+            }
+        }
+        OutputFormat::Png => {
+            let mut buf = Vec::new();
+            if options.dither {
+                let narrowed = img.to_8bit();
+                let img = narrowed.as_ref().unwrap_or(img);
+                let mut rgba = RgbaImage::from_raw(
+                    img.width as u32,
+                    img.height as u32,
+                    img.clone().into_rgba(),
+                )
+                .ok_or_else(|| Error::Compress("image buffer size mismatch".to_string()))?;
+                dither_to_palette(&mut rgba, 1);
+                buf = encode_indexed_png(&rgba)?;
+            } else {
+                PngEncoder::new(&mut buf).write_image(
+                    &img.data,
+                    img.width as u32,
+                    img.height as u32,
+                    img.color,
+                )?;
+            }
+            if options.png_level > 0 || options.png_interlace {
+                // The encoder only writes sequential rows, so interlacing is
+                // left to oxipng, even at level 0.
+                let mut png_options = oxipng::Options::from_preset(options.png_level);
+                png_options.interlace = Some(options.png_interlace);
+                buf = oxipng::optimize_from_memory(&buf, &png_options)
+                    .map_err(|e| Error::Compress(format!("PNG optimization failed: {}", e)))?;
+            }
+            if options.assume_srgb {
+                buf = icc::tag_png_srgb(&buf);
+            }
+            Ok(buf)
+        }
+        OutputFormat::WebP => {
+            let webp_quality = if options.webp_lossless {
+                WebPQuality::lossless()
+            } else {
+                WebPQuality::lossy(quality.round() as u8)
+            };
+            // libwebp only takes RGB or RGBA input.
+            let (data, color) = match img.color {
+                ColorType::L8 => (gray_to_rgb(&img.data).into(), ColorType::Rgb8),
+                color => (Cow::from(&img.data[..]), color),
+            };
+            let mut buf = Vec::new();
+            WebPEncoder::new_with_quality(&mut buf, webp_quality).encode(
+                &data,
+                img.width as u32,
+                img.height as u32,
+                color,
+            )?;
+            if options.assume_srgb {
+                let (width, height) = (img.width as u32, img.height as u32);
+                let alpha = color.has_alpha();
+                buf = icc::embed_webp(&buf, icc::srgb_profile(), width, height, alpha);
+            }
+            Ok(buf)
+        }
+        OutputFormat::Gif => {
+            let (data, color) = match img.color {
+                ColorType::L8 => (gray_to_rgb(&img.data).into(), ColorType::Rgb8),
+                color => (Cow::from(&img.data[..]), color),
+            };
+            let (data, color) = if options.dither {
+                let mut rgba = RgbaImage::from_raw(
+                    img.width as u32,
+                    img.height as u32,
+                    img.clone().into_rgba(),
+                )
+                .ok_or_else(|| Error::Compress("image buffer size mismatch".to_string()))?;
+                binarize_alpha(&mut rgba);
+                // The encoder's own speed.
+                dither_to_palette(&mut rgba, 1);
+                (Cow::from(rgba.into_raw()), ColorType::Rgba8)
+            } else {
+                (data, color)
+            };
+            let mut buf = Vec::new();
+            GifEncoder::new(&mut buf).encode(&data, img.width as u32, img.height as u32, color)?;
+            Ok(buf)
+        }
+        OutputFormat::Avif => encode_avif(img, quality, options.estimate),
+        OutputFormat::Tiff => encode_tiff_pages([Ok((img, None))], options.tiff_compression),
+        OutputFormat::Bmp => {
+            let mut buf = Vec::new();
+            BmpEncoder::new(&mut buf).write_image(
+                &img.data,
+                img.width as u32,
+                img.height as u32,
+                img.color,
+            )?;
+            Ok(buf)
+        }
+        OutputFormat::Jxl => encode_jxl(img),
+    }
+}
+
+/// AVIF encoding is far slower than the other formats. rav1e schedules its
+/// work on the global rayon pool, so a large AVIF shares the batch's worker
+/// threads instead of stalling them, and speed 6 keeps each file reasonably
+/// quick at a small cost in size. Estimates use the fastest speed.
+fn encode_avif(img: &ResizedImage, quality: f32, estimate: bool) -> Result<Vec<u8>, Error> {
+    let encoder = ravif::Encoder::new()
+        .with_quality(quality.clamp(1.0, 100.0))
+        .with_speed(if estimate { 10 } else { 6 });
+    let encoded = match img.color {
+        ColorType::Rgba8 => {
+            let pixels = img
+                .data
+                .chunks_exact(4)
+                .map(|px| ravif::RGBA8::new(px[0], px[1], px[2], px[3]))
+                .collect::<Vec<_>>();
+            encoder.encode_rgba(ravif::Img::new(&pixels[..], img.width, img.height))
+        }
+        _ => {
+            let rgb = match img.color {
+                ColorType::L8 => gray_to_rgb(&img.data).into(),
+                _ => Cow::from(&img.data[..]),
+            };
+            let pixels = rgb
+                .chunks_exact(3)
+                .map(|px| ravif::RGB8::new(px[0], px[1], px[2]))
+                .collect::<Vec<_>>();
+            encoder.encode_rgb(ravif::Img::new(&pixels[..], img.width, img.height))
+        }
+    };
+    encoded
+        .map(|avif| avif.avif_file)
+        .map_err(|e| Error::Compress(e.to_string()))
+}
+
+/// The encoder is built without its own threads, so that each image is
+/// encoded on the worker that resized it like the other formats.
+fn encode_jxl(img: &ResizedImage) -> Result<Vec<u8>, Error> {
+    let colorspace = match img.color {
+        ColorType::L8 => JxlColorSpace::Luma,
+        ColorType::La8 => JxlColorSpace::LumaA,
+        ColorType::Rgb8 => JxlColorSpace::RGB,
+        ColorType::Rgba8 => JxlColorSpace::RGBA,
+        color => {
+            return Err(Error::Compress(format!(
+                "JPEG XL encoding failed: unsupported color type {:?}",
+                color
+            )))
+        }
+    };
+    let options = EncoderOptions::new(img.width, img.height, colorspace, BitDepth::Eight);
+    let mut buf = Vec::new();
+    JxlSimpleEncoder::new(&img.data, options)
+        .encode(&mut buf)
+        .map_err(|e| {
+            Error::Compress(format!(
+                "JPEG XL encoding failed: {}",
+                e.to_string().trim_end()
+            ))
+        })?;
+    Ok(buf)
+}
+
+/// The resolution of a TIFF page, in pixels per `unit`, which is one of
+/// TIFF's `ResolutionUnit` values.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TiffResolution {
+    pub(crate) x: f64,
+    pub(crate) y: f64,
+    pub(crate) unit: u16,
+}
+
+/// Encodes each of `pages` as one page of a TIFF, with its resolution tags
+/// when it has a resolution. An error from `pages` stops the encoding.
+pub(crate) fn encode_tiff_pages<I>(
+    pages: impl IntoIterator<Item = Result<(I, Option<TiffResolution>), Error>>,
+    compression: TiffCompression,
+) -> Result<Vec<u8>, Error>
+where
+    I: Borrow<ResizedImage>,
+{
+    match compression {
+        TiffCompression::None => write_tiff(pages, Uncompressed),
+        TiffCompression::Lzw => write_tiff(pages, Lzw),
+        TiffCompression::Deflate => write_tiff(pages, Deflate::default()),
+    }
+}
+
+fn write_tiff<I: Borrow<ResizedImage>>(
+    pages: impl IntoIterator<Item = Result<(I, Option<TiffResolution>), Error>>,
+    compression: impl Compression + Clone,
+) -> Result<Vec<u8>, Error> {
+    let tiff_error = |e: tiff::TiffError| Error::Compress(format!("TIFF encoding failed: {}", e));
+    let mut buf = Vec::new();
+    let mut encoder = TiffEncoder::new(Cursor::new(&mut buf)).map_err(tiff_error)?;
+    for page in pages {
+        let (img, resolution) = page?;
+        let img = img.borrow();
+        let size = (img.width as u32, img.height as u32);
+        let compression = compression.clone();
+        let samples = || {
+            img.data
+                .chunks_exact(2)
+                .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+                .collect::<Vec<_>>()
+        };
+        match img.color {
+            ColorType::L8 => write_tiff_page::<colortype::Gray8, _, _>(
+                &mut encoder,
+                size,
+                compression,
+                resolution,
+                &img.data,
+            ),
+            ColorType::Rgb8 => write_tiff_page::<colortype::RGB8, _, _>(
+                &mut encoder,
+                size,
+                compression,
+                resolution,
+                &img.data,
+            ),
+            ColorType::Rgba8 => write_tiff_page::<colortype::RGBA8, _, _>(
+                &mut encoder,
+                size,
+                compression,
+                resolution,
+                &img.data,
+            ),
+            ColorType::L16 => write_tiff_page::<colortype::Gray16, _, _>(
+                &mut encoder,
+                size,
+                compression,
+                resolution,
+                &samples(),
+            ),
+            ColorType::Rgb16 => write_tiff_page::<colortype::RGB16, _, _>(
+                &mut encoder,
+                size,
+                compression,
+                resolution,
+                &samples(),
+            ),
+            ColorType::Rgba16 => write_tiff_page::<colortype::RGBA16, _, _>(
+                &mut encoder,
+                size,
+                compression,
+                resolution,
+                &samples(),
+            ),
+            color => {
+                return Err(Error::Compress(format!(
+                    "TIFF output of {:?} is not supported",
+                    color
+                )))
+            }
+        }
+        .map_err(tiff_error)?;
+    }
+    Ok(buf)
+}
+
+fn write_tiff_page<C, W, D>(
+    encoder: &mut TiffEncoder<W>,
+    (width, height): (u32, u32),
+    compression: D,
+    resolution: Option<TiffResolution>,
+    data: &[C::Inner],
+) -> tiff::TiffResult<()>
+where
+    C: colortype::ColorType,
+    W: Write + Seek,
+    D: Compression,
+    [C::Inner]: TiffValue,
+{
+    let mut page = encoder.new_image_with_compression::<C, D>(width, height, compression)?;
+    if let Some(resolution) = resolution {
+        // Hundredths are as precise as resolutions are ever given.
+        let rational = |v: f64| Rational {
+            n: (v * 100.0).round() as u32,
+            d: 100,
+        };
+        page.resolution_unit(
+            ResolutionUnit::from_u16(resolution.unit).unwrap_or(ResolutionUnit::Inch),
+        );
+        page.x_resolution(rational(resolution.x));
+        page.y_resolution(rational(resolution.y));
+    }
+    page.write_data(data)
+}
+
+/// Overwrites the density fields of the JFIF header that mozjpeg writes at
+/// the start of every file.
+fn set_density(jpeg: &mut [u8], dpi: u16) {
+    // SOI, then the APP0 marker, its length, "JFIF\0" and the version.
+    if jpeg.len() >= 18 && jpeg[2..4] == [0xFF, 0xE0] && &jpeg[6..11] == b"JFIF\0" {
+        // Units of 1 mean dots per inch.
+        jpeg[13] = 1;
+        jpeg[14..16].copy_from_slice(&dpi.to_be_bytes());
+        jpeg[16..18].copy_from_slice(&dpi.to_be_bytes());
+    }
+}
+
+/// Makes every pixel that isn't fully transparent opaque, since GIF
+/// transparency is all or nothing.
+pub(crate) fn binarize_alpha(img: &mut RgbaImage) {
+    for px in img.pixels_mut() {
+        if px[3] != 0 {
+            px[3] = 255;
+        }
+    }
+}
+
+/// Reduces `img` to the 256 colors a GIF frame or indexed PNG holds with
+/// Floyd–Steinberg dithering, so that the encoder keeps exactly these colors
+/// instead of quantizing them again without it. Images that already fit in
+/// a palette, such as pixel art, are left as they are. `speed` is
+/// NeuQuant's, from 1 (best) to 30.
+pub(crate) fn dither_to_palette(img: &mut RgbaImage, speed: i32) {
+    let mut colors = HashSet::new();
+    if img.pixels().all(|px| {
+        colors.insert(px.0);
+        colors.len() <= 256
+    }) {
+        return;
+    }
+    let palette = NeuQuant::new(speed, 256, img.as_raw());
+    // `dither` diffuses into the neighbouring row and column, which a single
+    // row or column doesn't have.
+    if img.width() < 2 || img.height() < 2 {
+        img.pixels_mut().for_each(|px| palette.map_color(px));
+    } else {
+        imageops::dither(img, &palette);
+    }
+}
+
+/// Writes `img`, which must have at most 256 colors, as an 8-bit indexed PNG,
+/// with a `tRNS` chunk when any of them is transparent.
+fn encode_indexed_png(img: &RgbaImage) -> Result<Vec<u8>, Error> {
+    let mut palette = Vec::new();
+    let mut indices = HashMap::new();
+    let mut data = Vec::with_capacity(img.width() as usize * img.height() as usize);
+    for px in img.pixels() {
+        let next = indices.len();
+        let index = *indices.entry(px.0).or_insert_with(|| {
+            palette.push(px.0);
+            next
+        });
+        data.push(
+            u8::try_from(index)
+                .map_err(|_| Error::Compress("more than 256 colors for a palette".to_string()))?,
+        );
+    }
+    let mut buf = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buf, img.width(), img.height());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(
+        palette
+            .iter()
+            .flat_map(|c| &c[..3])
+            .copied()
+            .collect::<Vec<_>>(),
+    );
+    if palette.iter().any(|c| c[3] != 255) {
+        encoder.set_trns(palette.iter().map(|c| c[3]).collect::<Vec<_>>());
+    }
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| Error::Compress(format!("PNG encoding failed: {}", e)))?;
+    writer
+        .write_image_data(&data)
+        .map_err(|e| Error::Compress(format!("PNG encoding failed: {}", e)))?;
+    writer
+        .finish()
+        .map_err(|e| Error::Compress(format!("PNG encoding failed: {}", e)))?;
+    Ok(buf)
+}
+
+const MAX_SEARCH_QUALITY: u32 = 95;
+const MAX_SEARCH_STEPS: usize = 8;
+
+/// Encodes at the highest quality whose output fits in `max_bytes`, found by
+/// binary search between [`Options::min_quality`] and 95. When even the floor
+/// is too large, the result at the floor is returned anyway, so the budget
+/// can't degrade the image any further. Returns the encoded data and the
+/// quality it was encoded at.
+pub fn encode_within(
+    img: &ResizedImage,
+    format: OutputFormat,
+    max_bytes: usize,
+    options: &Options,
+) -> Result<(Vec<u8>, f32), Error> {
+    let encode_at = |quality| encode_at_quality(img, format, quality, options);
+    let floor = (options.min_quality.ceil() as u32).min(MAX_SEARCH_QUALITY);
+    let mut low = floor;
+    let mut high = MAX_SEARCH_QUALITY;
+    let mut best = None;
+    for _ in 0..MAX_SEARCH_STEPS {
+        if low > high {
+            break;
+        }
+        let quality = (low + high) / 2;
+        let data = encode_at(quality)?;
+        if data.len() <= max_bytes {
+            best = Some((data, quality as f32));
+            low = quality + 1;
+        } else {
+            high = quality.saturating_sub(1);
+            if quality == floor {
+                break;
+            }
+        }
+    }
+
+    match best {
+        Some(v) => Ok(v),
+        None => {
+            let data = encode_at(floor)?;
+            Ok((data, floor as f32))
+        }
+    }
+}
+
+/// Encodes at the lowest quality, from the one `options` gives for `format`
+/// up to 95, whose output keeps an SSIM of `min_ssim` against `img`, found
+/// by binary search. When even 95 falls short, that result is returned
+/// anyway. Formats that can't be decoded back are encoded as `options` say.
+/// Returns the encoded data and the quality it was encoded at.
+pub(crate) fn encode_above(
+    img: &ResizedImage,
+    format: OutputFormat,
+    min_ssim: f64,
+    options: &Options,
+) -> Result<(Vec<u8>, f32), Error> {
+    let meets = |data: &[u8]| {
+        compare::compare(img, data, format, options.background)
+            .is_none_or(|comparison| comparison.ssim >= min_ssim)
+    };
+    let start = options.quality_for(format);
+    let data = encode(img, format, options)?;
+    if start.round() as u32 >= MAX_SEARCH_QUALITY || meets(&data) {
+        return Ok((data, start));
+    }
+
+    let encode_at = |quality| encode_at_quality(img, format, quality, options);
+    let mut low = start.round() as u32 + 1;
+    let mut high = MAX_SEARCH_QUALITY;
+    let mut best = None;
+    for _ in 0..MAX_SEARCH_STEPS {
+        if low > high {
+            break;
+        }
+        let quality = (low + high) / 2;
+        let data = encode_at(quality)?;
+        if meets(&data) {
+            best = Some((data, quality as f32));
+            high = quality - 1;
+        } else {
+            low = quality + 1;
+        }
+    }
+
+    match best {
+        Some(v) => Ok(v),
+        None => {
+            let data = encode_at(MAX_SEARCH_QUALITY)?;
+            Ok((data, MAX_SEARCH_QUALITY as f32))
+        }
+    }
+}
+
+/// Encodes at `quality`, which replaces any format-specific one.
+fn encode_at_quality(
+    img: &ResizedImage,
+    format: OutputFormat,
+    quality: u32,
+    options: &Options,
+) -> Result<Vec<u8>, Error> {
+    let options = Options {
+        quality: quality as f32,
+        jpeg_quality: None,
+        webp_quality: None,
+        avif_quality: None,
+        ..options.clone()
+    };
+    encode(img, format, &options)
+}
+
+/// Encodes RGB pixel data as a JPEG with mozjpeg. Rows are tightly packed,
+/// `width * 3` bytes apart.
+pub fn compress(data: &[u8], width: usize, height: usize, quality: f32) -> Result<Vec<u8>, Error> {
+    compress_strided(data, width, height, width * 3, quality)
+}
+
+/// Like [`compress`], but for buffers whose rows start `stride` bytes apart,
+/// with any padding after each row's `width * 3` bytes of pixels skipped.
+/// The last row needn't be padded.
+pub fn compress_strided(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    quality: f32,
+) -> Result<Vec<u8>, Error> {
+    let options = Options {
+        quality,
+        ..Options::default()
+    };
+    compress_with(
+        data,
+        width,
+        height,
+        stride,
+        ColorType::Rgb8,
+        None,
+        None,
+        &options,
+    )
+}
+
+/// Calls `row` with each scanline of `img` from top to bottom, for feeding an
+/// encoder other than mozjpeg. Every scanline is row-major RGB, 3 bytes per
+/// pixel and `img.width * 3` bytes in all, the layout [`compress`] takes:
+/// grayscale is expanded, transparency is composited onto `background` and
+/// 16-bit samples are rounded to 8 bits. The slice is reused between calls.
+pub fn for_each_scanline(img: &ResizedImage, background: [u8; 3], mut row: impl FnMut(&[u8])) {
+    let narrowed = img.to_8bit();
+    let img = narrowed.as_ref().unwrap_or(img);
+    let channels = match img.color {
+        ColorType::L8 => 1,
+        ColorType::Rgba8 => 4,
+        _ => 3,
+    };
+    if img.width == 0 {
+        return;
+    }
+    let mut rgb = Vec::with_capacity(img.width * 3);
+    for line in img.data.chunks_exact(img.width * channels) {
+        match channels {
+            3 => row(line),
+            1 => {
+                rgb.clear();
+                rgb.extend(line.iter().flat_map(|&v| [v, v, v]));
+                row(&rgb);
+            }
+            _ => {
+                rgb.clear();
+                rgb.extend(flatten(line, background));
+                row(&rgb);
+            }
+        }
+    }
+}
+
+/// Encodes RGB or 8-bit grayscale pixel data as a JPEG, each row `stride`
+/// bytes after the one before. Grayscale input is written as a
+/// single-component JPEG, which is a third of the RGB size.
+#[allow(clippy::too_many_arguments)]
+fn compress_with(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    color: ColorType,
+    icc_profile: Option<&[u8]>,
+    exif: Option<&[u8]>,
+    options: &Options,
+) -> Result<Vec<u8>, Error> {
+    let (color_space, channels) = match color {
+        ColorType::Rgb8 => (ColorSpace::JCS_RGB, 3),
+        ColorType::L8 => (ColorSpace::JCS_GRAYSCALE, 1),
+        other => {
+            return Err(Error::Compress(format!(
+                "unsupported color type for JPEG: {:?}",
+                other
+            )))
+        }
+    };
+    // The scanline loop below slices `data` by these dimensions, so a buffer
+    // that disagrees with them must be rejected rather than panic.
+    let row = width * channels;
+    if width == 0
+        || height == 0
+        || stride < row
+        || data.len() < stride * (height - 1) + row
+        || data.len() > stride * height
+    {
+        return Err(Error::Compress(format!(
+            "buffer of {} bytes does not match {}x{} with {} channel(s) and rows {} bytes apart",
+            data.len(),
+            width,
+            height,
+            channels,
+            stride
+        )));
+    }
+
+    let cmyk;
+    let (data, stride, color_space, channels, jpeg_color_space) = match options.jpeg_color_space {
+        JpegColorSpace::Rgb => (data, stride, color_space, channels, None),
+        space => {
+            cmyk = data
+                .chunks(stride)
+                .flat_map(|line| to_inverted_cmyk(&line[..row], channels))
+                .collect::<Vec<_>>();
+            let jpeg_color_space = match space {
+                JpegColorSpace::Ycck => ColorSpace::JCS_YCCK,
+                _ => ColorSpace::JCS_CMYK,
+            };
+            let stride = width * 4;
+            (
+                &cmyk[..],
+                stride,
+                ColorSpace::JCS_CMYK,
+                4,
+                Some(jpeg_color_space),
+            )
+        }
+    };
+
+    let mut comp = Compress::new(color_space);
+    comp.set_scan_optimization_mode(ScanMode::AllComponentsTogether);
+    comp.set_quality(options.quality_for(OutputFormat::Jpeg));
+    // mozjpeg's defaults already produce progressive scans; baseline output
+    // needs the scan script cleared.
+    if !options.progressive {
+        comp.set_optimize_scans(false);
+    }
+    comp.set_optimize_coding(options.optimize_coding);
+    if options.smoothing > 0 {
+        comp.set_smoothing_factor(options.smoothing);
+    }
+
+    comp.set_size(width, height);
+    if let Some(jpeg_color_space) = jpeg_color_space {
+        comp.set_color_space(jpeg_color_space);
+    }
+    // CMYK has no chroma channels to subsample.
+    let chroma = color == ColorType::Rgb8 && jpeg_color_space != Some(ColorSpace::JCS_CMYK);
+    if let (Some(subsampling), true) = (options.subsampling, chroma) {
+        let px = subsampling.chroma_pixel_size();
+        comp.set_chroma_sampling_pixel_sizes(px, px);
+    }
+
+    comp.set_mem_dest();
+    comp.start_compress();
+    // Markers must be written after the header and before any scanlines.
+    if let Some(exif) = exif {
+        comp.write_marker(Marker::APP(1), exif);
+    }
+    if let Some(profile) = icc_profile.filter(|p| icc::matches_channels(p, channels)) {
+        for segment in icc::jpeg_segments(profile) {
+            comp.write_marker(Marker::APP(2), &segment);
+        }
+    }
+
+    for line in data.chunks(stride) {
+        comp.write_scanlines(&line[..width * channels]);
+    }
+    comp.finish_compress();
+
+    let compressed = comp
+        .data_to_vec()
+        .map_err(|_| Error::Compress("data_to_vec failed".to_string()))?;
+    Ok(compressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_rejects_a_buffer_that_disagrees_with_its_dimensions() {
+        let data = vec![0; 10 * 10 * 3];
+        for (width, height) in [(10, 11), (11, 10), (9, 10)] {
+            let result = compress(&data, width, height, 80.0);
+            assert!(
+                matches!(result, Err(Error::Compress(_))),
+                "{}x{}",
+                width,
+                height
+            );
+        }
+        assert!(matches!(
+            compress(&data, 0, 10, 80.0),
+            Err(Error::Compress(_))
+        ));
+        assert!(matches!(
+            compress(&[], 10, 0, 80.0),
+            Err(Error::Compress(_))
+        ));
+        assert!(compress(&data, 10, 10, 80.0).is_ok());
+    }
+
+    /// Row-major RGB pixels of a `width` x `height` pattern, each row followed
+    /// by `padding` bytes of 0xAB except the last when `pad_last` is unset.
+    fn padded(width: usize, height: usize, padding: usize, pad_last: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                data.extend([(x * 30) as u8, (y * 40) as u8, ((x + y) * 10) as u8]);
+            }
+            if pad_last || y + 1 < height {
+                data.resize(data.len() + padding, 0xAB);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn compress_strided_skips_row_padding() {
+        let (width, height) = (8, 6);
+        let tight = compress(&padded(width, height, 0, false), width, height, 80.0).unwrap();
+        for pad_last in [true, false] {
+            let data = padded(width, height, 5, pad_last);
+            let jpeg = compress_strided(&data, width, height, width * 3 + 5, 80.0).unwrap();
+            assert_eq!(jpeg, tight, "pad_last {}", pad_last);
+        }
+        let img = image::load_from_memory(&tight).unwrap();
+        assert_eq!((img.width(), img.height()), (8, 6));
+    }
+
+    #[test]
+    fn compress_strided_with_a_tight_stride_is_compress() {
+        let (width, height) = (8, 6);
+        let data = padded(width, height, 0, false);
+        assert_eq!(
+            compress_strided(&data, width, height, width * 3, 80.0).unwrap(),
+            compress(&data, width, height, 80.0).unwrap()
+        );
+    }
+
+    #[test]
+    fn compress_strided_rejects_a_short_stride_or_buffer() {
+        let (width, height) = (8, 6);
+        let data = padded(width, height, 5, true);
+        let stride = width * 3 + 5;
+        for (data, stride) in [
+            // Rows would overlap.
+            (&data[..], width * 3 - 1),
+            (&data[..], 0),
+            // The last row is cut short.
+            (&data[..stride * (height - 1) + width * 3 - 1], stride),
+            (&data[..stride * (height - 1)], stride),
+            // More rows than the height.
+            (&data[..], stride - 1),
+        ] {
+            assert!(
+                matches!(
+                    compress_strided(data, width, height, stride, 80.0),
+                    Err(Error::Compress(_))
+                ),
+                "{} bytes, stride {}",
+                data.len(),
+                stride
+            );
+        }
+        assert!(compress_strided(
+            &data[..stride * (height - 1) + width * 3],
+            width,
+            height,
+            stride,
+            80.0
+        )
+        .is_ok());
+    }
+
+    /// A gradient with far more than 256 colors, half of it translucent.
+    fn gradient() -> ResizedImage {
+        let img = RgbaImage::from_fn(64, 64, |x, y| {
+            let alpha = if x < 32 { 128 } else { 255 };
+            image::Rgba([x as u8 * 4, y as u8 * 4, (x + y) as u8 * 2, alpha])
+        });
+        ResizedImage {
+            data: img.into_raw(),
+            width: 64,
+            height: 64,
+            color: ColorType::Rgba8,
+            icc_profile: None,
+            exif: None,
+        }
+    }
+
+    fn colors(png: &[u8]) -> usize {
+        let img = image::load_from_memory(png).unwrap().into_rgba8();
+        img.pixels().map(|px| px.0).collect::<HashSet<_>>().len()
+    }
+
+    #[test]
+    fn dither_writes_indexed_png() {
+        let options = Options {
+            dither: true,
+            png_level: 0,
+            ..Options::default()
+        };
+        let png = encode(&gradient(), OutputFormat::Png, &options).unwrap();
+        // The IHDR's color type.
+        assert_eq!(png[25], 3);
+        assert!(colors(&png) <= 256);
+        let decoded = image::load_from_memory(&png).unwrap().into_rgba8();
+        assert_eq!(decoded.get_pixel(0, 0)[3], 128);
+        assert_eq!(decoded.get_pixel(63, 0)[3], 255);
+
+        // Optimizing keeps it within the palette.
+        let options = Options {
+            png_level: 2,
+            ..options
+        };
+        let png = encode(&gradient(), OutputFormat::Png, &options).unwrap();
+        assert!(colors(&png) <= 256);
+    }
+
+    #[test]
+    fn png_keeps_every_color_without_dither() {
+        let options = Options {
+            png_level: 0,
+            ..Options::default()
+        };
+        let png = encode(&gradient(), OutputFormat::Png, &options).unwrap();
+        assert_eq!(png[25], 6);
+        assert!(colors(&png) > 256);
+    }
+
+    /// The JFIF header's density unit and horizontal and vertical density.
+    fn density(jpeg: &[u8]) -> (u8, u16, u16) {
+        assert_eq!(&jpeg[6..11], b"JFIF\0");
+        let x = u16::from_be_bytes([jpeg[14], jpeg[15]]);
+        let y = u16::from_be_bytes([jpeg[16], jpeg[17]]);
+        (jpeg[13], x, y)
+    }
+
+    #[test]
+    fn dpi_is_written_into_the_jfif_header() {
+        let source = compress(&[200; 8 * 8 * 3], 8, 8, 90.0).unwrap();
+        let options = Options {
+            target_size: 4,
+            dpi: Some(300),
+            ..Options::default()
+        };
+        let jpeg = crate::process_bytes(&source, &options).unwrap();
+        assert_eq!(density(&jpeg), (1, 300, 300));
+        assert!(image::load_from_memory(&jpeg).is_ok());
+
+        let options = Options {
+            dpi: None,
+            ..options
+        };
+        let jpeg = crate::process_bytes(&source, &options).unwrap();
+        assert_ne!(density(&jpeg), (1, 300, 300));
+    }
+
+    #[test]
+    fn set_density_ignores_data_without_a_jfif_header() {
+        let mut data = vec![0xFF, 0xD8, 0xFF, 0xE1, 0, 16];
+        data.resize(20, 0);
+        let before = data.clone();
+        set_density(&mut data, 72);
+        assert_eq!(data, before);
+        set_density(&mut [0xFF, 0xD8], 72);
+    }
+}
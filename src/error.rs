@@ -0,0 +1,195 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use image::ImageError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The file isn't an image, or is in a format that can't be decoded.
+    Unsupported(ImageError),
+    /// The file looks like a supported image but its data is damaged or cut
+    /// short.
+    Corrupt(ImageError),
+    Image(ImageError),
+    Io(io::Error),
+    Compress(String),
+    /// The image's header declares more pixels than the limit allows.
+    TooLarge {
+        width: u32,
+        height: u32,
+        max_pixels: u64,
+    },
+    /// The image is estimated to need more memory than the whole of
+    /// [`crate::Options::memory_limit`].
+    OverMemoryLimit {
+        needed: u64,
+        limit: u64,
+    },
+    /// Processing the file took longer than [`crate::Options::timeout`].
+    TimedOut(Duration),
+    /// The batch was cancelled before this file was started.
+    Cancelled,
+    /// The output already exists and [`crate::Overwrite::Error`] is set.
+    Exists(PathBuf),
+    /// The written output doesn't decode, or decodes to other dimensions
+    /// than it was encoded at.
+    Invalid(String),
+    /// Downloading an input URL failed, or its server answered with an
+    /// error status.
+    Network(String),
+    /// Another error, labelled with the stage of processing it happened in.
+    At(Stage, Box<Error>),
+}
+
+/// A step of processing a file, named in the errors it fails with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Read,
+    Decode,
+    Resize,
+    Encode,
+    Write,
+    Validate,
+    /// Running [`crate::Options::post_command`] on the output.
+    PostCommand,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Read => "read",
+            Stage::Decode => "decode",
+            Stage::Resize => "resize",
+            Stage::Encode => "encode",
+            Stage::Write => "write",
+            Stage::Validate => "validate",
+            Stage::PostCommand => "post-command",
+        }
+    }
+}
+
+impl Error {
+    /// A short, stable name for the kind of failure.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Unsupported(_) => "unsupported",
+            Error::Corrupt(_) => "corrupt",
+            Error::Image(_) => "image",
+            Error::Io(_) => "io",
+            Error::Compress(_) => "compress",
+            Error::TooLarge { .. } => "too_large",
+            Error::OverMemoryLimit { .. } => "over_memory_limit",
+            Error::TimedOut(_) => "timeout",
+            Error::Cancelled => "cancelled",
+            Error::Exists(_) => "exists",
+            Error::Invalid(_) => "invalid",
+            Error::Network(_) => "network",
+            Error::At(_, e) => e.kind(),
+        }
+    }
+
+    /// The stage the error happened in, if it is labelled with one.
+    pub fn stage(&self) -> Option<Stage> {
+        match self {
+            Error::At(stage, _) => Some(*stage),
+            _ => None,
+        }
+    }
+
+    /// Labels the error with `stage`, unless it already has one.
+    pub(crate) fn at(self, stage: Stage) -> Self {
+        match self {
+            Error::At(..) => self,
+            e => Error::At(stage, Box::new(e)),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unsupported(e) => write!(
+                f,
+                "unsupported format ({}); convert it to JPEG, PNG or WebP first",
+                e
+            ),
+            Error::Corrupt(e) => write!(
+                f,
+                "corrupt or truncated file ({}); try obtaining it again",
+                e
+            ),
+            Error::Image(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Compress(msg) => write!(f, "{}", msg),
+            Error::TooLarge {
+                width,
+                height,
+                max_pixels,
+            } => write!(
+                f,
+                "image is {}x{}, more than the limit of {} pixels",
+                width, height, max_pixels
+            ),
+            Error::OverMemoryLimit { needed, limit } => write!(
+                f,
+                "image needs about {} MB to process, more than the memory limit of {} MB",
+                needed.div_ceil(1 << 20),
+                limit >> 20
+            ),
+            Error::TimedOut(timeout) => {
+                write!(f, "timed out after {} s", timeout.as_secs_f64())
+            }
+            Error::Cancelled => write!(f, "cancelled"),
+            Error::Exists(path) => write!(f, "{} already exists", path.display()),
+            Error::Invalid(reason) => write!(f, "output is invalid: {}", reason),
+            Error::Network(reason) => write!(
+                f,
+                "download failed ({}); check the URL and the connection",
+                reason
+            ),
+            Error::At(stage, e) => write!(f, "{}: {}", stage.name(), e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Unsupported(e) | Error::Corrupt(e) | Error::Image(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::At(_, e) => Some(e.as_ref()),
+            Error::Compress(_)
+            | Error::TooLarge { .. }
+            | Error::OverMemoryLimit { .. }
+            | Error::TimedOut(_)
+            | Error::Cancelled
+            | Error::Exists(_)
+            | Error::Invalid(_)
+            | Error::Network(_) => None,
+        }
+    }
+}
+
+impl From<ImageError> for Error {
+    /// Decoders report a file that ends early as an I/O error, so that case
+    /// is counted as corruption rather than a failure to read the disk.
+    fn from(e: ImageError) -> Self {
+        match e {
+            ImageError::Unsupported(_) => Error::Unsupported(e),
+            ImageError::Decoding(_) => Error::Corrupt(e),
+            ImageError::IoError(ref io) if io.kind() == io::ErrorKind::UnexpectedEof => {
+                Error::Corrupt(e)
+            }
+            ImageError::IoError(io) => Error::Io(io),
+            e => Error::Image(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,38 @@
+use crate::Error;
+
+/// The largest body a download may have, so that a wrong URL can't fill
+/// memory.
+const MAX_DOWNLOAD_BYTES: u64 = 256 << 20;
+
+/// Returns `true` if `input` is an `http://` or `https://` URL rather than a
+/// path.
+pub fn is_url(input: &str) -> bool {
+    ["http://", "https://"].iter().any(|scheme| {
+        input
+            .get(..scheme.len())
+            .is_some_and(|s| s.eq_ignore_ascii_case(scheme))
+    })
+}
+
+/// Downloads the body of `url` into memory, for [`crate::process_bytes`]
+/// and [`crate::resize_bytes_with`]. Failed requests, error statuses and
+/// bodies over 256 MB all fail with [`Error::Network`].
+pub fn fetch(url: &str) -> Result<Vec<u8>, Error> {
+    let network = |e: ureq::Error| Error::Network(e.to_string());
+    let mut response = ureq::get(url).call().map_err(network)?;
+    response
+        .body_mut()
+        .with_config()
+        .limit(MAX_DOWNLOAD_BYTES)
+        .read_to_vec()
+        .map_err(network)
+}
+
+/// The last segment of the URL's path, to name its output by, or `None`
+/// when the path has none.
+pub fn url_file_name(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let path = rest.split(['?', '#']).next()?;
+    let (_, path) = path.split_once('/')?;
+    path.rsplit('/').next().filter(|name| !name.is_empty())
+}
@@ -0,0 +1,270 @@
+use std::io::{BufRead, Read, Seek, SeekFrom};
+use std::sync::OnceLock;
+
+/// Identifies an ICC profile chunk in a JPEG APP2 segment.
+const JPEG_SIGNATURE: &[u8] = b"ICC_PROFILE\0";
+/// The most profile data one APP2 segment can hold after its 14-byte header.
+const JPEG_CHUNK_SIZE: usize = 65519;
+
+/// Reads the embedded ICC profile of an in-memory or already opened image.
+/// Returns `None` when it has no profile or isn't a JPEG, PNG or WebP.
+pub fn read_icc_profile_from<R: BufRead + Seek>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut magic = [0u8; 12];
+    reader.read_exact(&mut magic).ok()?;
+    reader.seek(SeekFrom::Start(0)).ok()?;
+    if magic.starts_with(&[0xFF, 0xD8]) {
+        read_jpeg(reader)
+    } else if magic.starts_with(b"\x89PNG") {
+        let decoder = png::Decoder::new(reader).read_info().ok()?;
+        decoder.info().icc_profile.as_ref().map(|p| p.to_vec())
+    } else if magic.starts_with(b"RIFF") && &magic[8..] == b"WEBP" {
+        read_webp(reader)
+    } else {
+        None
+    }
+}
+
+/// Collects the APP2 chunks before the first scan and joins them in sequence
+/// order, since large profiles are split across several segments.
+fn read_jpeg<R: Read>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut soi = [0u8; 2];
+    reader.read_exact(&mut soi).ok()?;
+    let mut chunks = Vec::new();
+    loop {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte).ok()?;
+        if byte[0] != 0xFF {
+            return None;
+        }
+        let mut marker = 0xFF;
+        while marker == 0xFF {
+            reader.read_exact(&mut byte).ok()?;
+            marker = byte[0];
+        }
+        match marker {
+            // Start of scan or end of image: no more metadata follows.
+            0xDA | 0xD9 => break,
+            // Standalone markers carry no length.
+            0x01 | 0xD0..=0xD7 => continue,
+            _ => {}
+        }
+        let mut len = [0u8; 2];
+        reader.read_exact(&mut len).ok()?;
+        let len = (u16::from_be_bytes(len) as usize).checked_sub(2)?;
+        let mut data = vec![0u8; len];
+        reader.read_exact(&mut data).ok()?;
+        if marker == 0xE2 && data.len() > 14 && data.starts_with(JPEG_SIGNATURE) {
+            chunks.push((data[12], data[14..].to_vec()));
+        }
+    }
+    if chunks.is_empty() {
+        return None;
+    }
+    chunks.sort_by_key(|(seq, _)| *seq);
+    Some(chunks.into_iter().flat_map(|(_, data)| data).collect())
+}
+
+/// Finds the `ICCP` chunk of an extended WebP file.
+fn read_webp<R: Read>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header).ok()?;
+    loop {
+        let mut chunk = [0u8; 8];
+        reader.read_exact(&mut chunk).ok()?;
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as usize;
+        // Chunks are padded to an even length.
+        let mut data = vec![0u8; size + size % 2];
+        reader.read_exact(&mut data).ok()?;
+        match &chunk[..4] {
+            b"ICCP" => {
+                data.truncate(size);
+                return Some(data);
+            }
+            // The profile must come before any image data.
+            b"VP8 " | b"VP8L" | b"ANIM" => return None,
+            _ => {}
+        }
+    }
+}
+
+/// Returns `true` if the profile describes an image with `channels` color
+/// channels, so that a profile isn't attached to pixels it doesn't fit, such
+/// as an RGB profile on a grayscale output.
+pub fn matches_channels(profile: &[u8], channels: usize) -> bool {
+    match profile.get(16..20) {
+        Some(b"GRAY") => channels == 1,
+        Some(b"RGB ") => channels == 3,
+        _ => false,
+    }
+}
+
+/// Splits a profile into JPEG APP2 segment payloads. Profiles too large to
+/// number in one byte's worth of segments are dropped.
+pub fn jpeg_segments(profile: &[u8]) -> Vec<Vec<u8>> {
+    let count = profile.len().div_ceil(JPEG_CHUNK_SIZE);
+    if count > 255 {
+        return Vec::new();
+    }
+    profile
+        .chunks(JPEG_CHUNK_SIZE)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut segment = Vec::with_capacity(14 + chunk.len());
+            segment.extend_from_slice(JPEG_SIGNATURE);
+            segment.push(i as u8 + 1);
+            segment.push(count as u8);
+            segment.extend_from_slice(chunk);
+            segment
+        })
+        .collect()
+}
+
+/// The D50 white point ICC profiles are connected through.
+const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+/// The sRGB primaries, adapted to D50.
+const SRGB_PRIMARIES: [[f64; 3]; 3] = [
+    [0.4361, 0.2225, 0.0139],
+    [0.3851, 0.7169, 0.0971],
+    [0.1431, 0.0606, 0.7141],
+];
+/// Entries in the tone curve of the built-in sRGB profile.
+const SRGB_CURVE_POINTS: usize = 256;
+
+/// A small version 2 sRGB display profile, built once, for tagging outputs
+/// with [`crate::Options::assume_srgb`].
+pub fn srgb_profile() -> &'static [u8] {
+    static PROFILE: OnceLock<Vec<u8>> = OnceLock::new();
+    PROFILE.get_or_init(build_srgb_profile)
+}
+
+fn build_srgb_profile() -> Vec<u8> {
+    let s15 = |v: f64| ((v * 65536.0).round() as i32).to_be_bytes();
+    let xyz = |[x, y, z]: [f64; 3]| [&b"XYZ \0\0\0\0"[..], &s15(x), &s15(y), &s15(z)].concat();
+    let description = b"sRGB\0";
+    let mut desc = b"desc\0\0\0\0".to_vec();
+    desc.extend_from_slice(&(description.len() as u32).to_be_bytes());
+    desc.extend_from_slice(description);
+    // Empty Unicode and ScriptCode descriptions.
+    desc.extend_from_slice(&[0; 4 + 4 + 2 + 1 + 67]);
+    let cprt = [&b"text\0\0\0\0"[..], b"No copyright, use freely\0"].concat();
+    let mut curve = b"curv\0\0\0\0".to_vec();
+    curve.extend_from_slice(&(SRGB_CURVE_POINTS as u32).to_be_bytes());
+    for i in 0..SRGB_CURVE_POINTS {
+        let v = i as f64 / (SRGB_CURVE_POINTS - 1) as f64;
+        let linear = if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        };
+        curve.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+    let [red, green, blue] = SRGB_PRIMARIES.map(xyz);
+    // The three channels share one tone curve.
+    let elements: [(&[u8; 4], &[u8]); 6] = [
+        (b"desc", &desc),
+        (b"cprt", &cprt),
+        (b"wtpt", &xyz(D50)),
+        (b"rXYZ", &red),
+        (b"gXYZ", &green),
+        (b"bXYZ", &blue),
+    ];
+    let tags = elements.len() + 3;
+    let mut table = (tags as u32).to_be_bytes().to_vec();
+    let mut body = Vec::new();
+    let data_start = 128 + 4 + tags * 12;
+    let mut add = |signatures: &[&[u8; 4]], element: &[u8]| {
+        let offset = (data_start + body.len()) as u32;
+        for signature in signatures {
+            table.extend_from_slice(*signature);
+            table.extend_from_slice(&offset.to_be_bytes());
+            table.extend_from_slice(&(element.len() as u32).to_be_bytes());
+        }
+        body.extend_from_slice(element);
+        // Elements start on four-byte boundaries.
+        body.resize(body.len().next_multiple_of(4), 0);
+    };
+    for (signature, element) in elements {
+        add(&[signature], element);
+    }
+    add(&[b"rTRC", b"gTRC", b"bTRC"], &curve);
+
+    let mut header = vec![0; 128];
+    header[0..4].copy_from_slice(&((data_start + body.len()) as u32).to_be_bytes());
+    header[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(b"RGB ");
+    header[20..24].copy_from_slice(b"XYZ ");
+    header[36..40].copy_from_slice(b"acsp");
+    for (i, v) in D50.into_iter().enumerate() {
+        header[68 + i * 4..72 + i * 4].copy_from_slice(&s15(v));
+    }
+    [header, table, body].concat()
+}
+
+/// Adds an `sRGB` chunk after the header of a PNG that has no color space
+/// chunk of its own.
+pub fn tag_png_srgb(data: &[u8]) -> Vec<u8> {
+    // The signature and the IHDR chunk, which is always 13 bytes long.
+    const IHDR_END: usize = 8 + 8 + 13 + 4;
+    let tagged = [b"iCCP", b"sRGB", b"gAMA", b"cHRM"].iter().any(|chunk| {
+        data.windows(4)
+            .take_while(|w| w != b"IDAT")
+            .any(|w| w == &chunk[..])
+    });
+    if data.len() < IHDR_END || tagged {
+        return data.to_vec();
+    }
+    // Perceptual rendering intent.
+    let chunk = [&b"sRGB"[..], &[0]].concat();
+    let mut out = Vec::with_capacity(data.len() + 13);
+    out.extend_from_slice(&data[..IHDR_END]);
+    out.extend_from_slice(&1u32.to_be_bytes());
+    out.extend_from_slice(&chunk);
+    out.extend_from_slice(&crc32fast::hash(&chunk).to_be_bytes());
+    out.extend_from_slice(&data[IHDR_END..]);
+    out
+}
+
+/// Embeds `profile` in a WebP file as an `ICCP` chunk, turning a simple
+/// file into an extended one when needed. Files that already carry a
+/// profile, or can't be parsed, are returned as they are.
+pub fn embed_webp(data: &[u8], profile: &[u8], width: u32, height: u32, alpha: bool) -> Vec<u8> {
+    const ICC_FLAG: u8 = 0x20;
+    const ALPHA_FLAG: u8 = 0x10;
+    if data.len() < 20 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return data.to_vec();
+    }
+    let mut iccp = b"ICCP".to_vec();
+    iccp.extend_from_slice(&(profile.len() as u32).to_le_bytes());
+    iccp.extend_from_slice(profile);
+    iccp.resize(iccp.len().next_multiple_of(2), 0);
+
+    let mut chunks = Vec::with_capacity(data.len() + iccp.len() + 18);
+    match &data[12..16] {
+        b"VP8X" if data.len() >= 30 => {
+            if data[20] & ICC_FLAG != 0 {
+                return data.to_vec();
+            }
+            chunks.extend_from_slice(&data[12..30]);
+            chunks[8] |= ICC_FLAG;
+            chunks.extend_from_slice(&iccp);
+            chunks.extend_from_slice(&data[30..]);
+        }
+        b"VP8 " | b"VP8L" => {
+            let flags = ICC_FLAG | if alpha { ALPHA_FLAG } else { 0 };
+            chunks.extend_from_slice(b"VP8X");
+            chunks.extend_from_slice(&10u32.to_le_bytes());
+            chunks.extend_from_slice(&[flags, 0, 0, 0]);
+            chunks.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+            chunks.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+            chunks.extend_from_slice(&iccp);
+            chunks.extend_from_slice(&data[12..]);
+        }
+        _ => return data.to_vec(),
+    }
+    let mut out = b"RIFF".to_vec();
+    out.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+    out.extend_from_slice(b"WEBP");
+    out.extend_from_slice(&chunks);
+    out
+}
@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::Path;
+
+use image::codecs::bmp::BmpDecoder;
+use image::codecs::gif::GifDecoder;
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::pnm::PnmDecoder;
+use image::codecs::tiff::TiffDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{ColorType, ImageDecoder, ImageFormat, ImageResult};
+
+use crate::{icc, orientation, pages, Error};
+
+/// What [`inspect`] finds in a file's headers.
+#[derive(Clone, Debug)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    /// Dimensions as stored, before the EXIF orientation is applied.
+    pub width: u32,
+    pub height: u32,
+    /// `None` for formats whose decoder can't report it without decoding.
+    pub color: Option<ColorType>,
+    pub has_exif: bool,
+    /// The EXIF orientation; 1 when there is none.
+    pub orientation: u32,
+    pub has_icc_profile: bool,
+    /// How many pages a TIFF has; 1 for every other format.
+    pub pages: usize,
+}
+
+/// Reads the format, dimensions, color type and metadata of an image from
+/// its headers, without decoding any pixels.
+pub fn inspect(path: &Path) -> Result<ImageInfo, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let format = image::io::Reader::new(&mut reader)
+        .with_guessed_format()?
+        .format()
+        .map_or_else(|| ImageFormat::from_path(path), Ok)?;
+    reader.seek(SeekFrom::Start(0))?;
+    let (width, height) = image::io::Reader::with_format(&mut reader, format).into_dimensions()?;
+    reader.seek(SeekFrom::Start(0))?;
+    let color = color_type(format, &mut reader)?;
+    reader.seek(SeekFrom::Start(0))?;
+    let has_exif = exif::Reader::new().read_from_container(&mut reader).is_ok();
+    reader.seek(SeekFrom::Start(0))?;
+    let orientation = orientation::read_orientation_from(&mut reader);
+    reader.seek(SeekFrom::Start(0))?;
+    let has_icc_profile = icc::read_icc_profile_from(&mut reader).is_some();
+    let pages = match format {
+        ImageFormat::Tiff => {
+            reader.seek(SeekFrom::Start(0))?;
+            pages::page_count(&mut reader)
+        }
+        _ => 1,
+    };
+    Ok(ImageInfo {
+        format,
+        width,
+        height,
+        color,
+        has_exif,
+        orientation,
+        has_icc_profile,
+        pages,
+    })
+}
+
+fn color_type<R: BufRead + Seek>(format: ImageFormat, reader: R) -> ImageResult<Option<ColorType>> {
+    let color = match format {
+        ImageFormat::Jpeg => JpegDecoder::new(reader)?.color_type(),
+        ImageFormat::Png => PngDecoder::new(reader)?.color_type(),
+        ImageFormat::Gif => GifDecoder::new(reader)?.color_type(),
+        ImageFormat::WebP => WebPDecoder::new(reader)?.color_type(),
+        ImageFormat::Tiff => TiffDecoder::new(reader)?.color_type(),
+        ImageFormat::Bmp => BmpDecoder::new(reader)?.color_type(),
+        ImageFormat::Pnm => PnmDecoder::new(reader)?.color_type(),
+        _ => return Ok(None),
+    };
+    Ok(Some(color))
+}
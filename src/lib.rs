@@ -0,0 +1,2598 @@
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Cursor, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use filetime::FileTime;
+use image::codecs::ico::{IcoEncoder, IcoFrame};
+use image::{ColorType, DynamicImage, ImageFormat, ImageResult, RgbaImage};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+
+mod animation;
+mod bucket;
+mod cmyk;
+mod compare;
+mod contact;
+mod content;
+mod dimensions;
+mod encode;
+mod error;
+mod fetch;
+mod icc;
+mod info;
+mod metadata;
+mod orientation;
+mod pages;
+mod pixels;
+mod strip;
+mod template;
+mod throttle;
+mod tiled;
+mod walk;
+mod watermark;
+
+pub use bucket::SizeBuckets;
+pub use compare::Comparison;
+pub use contact::contact_sheet;
+use dimensions::expected_dimensions;
+pub use dimensions::{fit_dimensions, output_dimensions};
+use encode::encode_above;
+pub use encode::{
+    compress, compress_strided, encode, encode_within, for_each_scanline, JpegColorSpace,
+    OutputFormat, Subsampling, TiffCompression,
+};
+pub use error::{Error, Stage};
+pub use fetch::{fetch, is_url, url_file_name};
+pub use image::imageops::FilterType;
+pub use info::{inspect, ImageInfo};
+pub use metadata::capture_date;
+pub use orientation::{Flip, Rotation};
+pub use pixels::{flatten, gray_to_rgb};
+use template::Fields;
+pub use template::NameTemplate;
+pub use throttle::{IoLimit, MemoryLimit};
+pub use walk::{is_image, read_dir_filtered, walk_dir, walk_dir_filtered};
+pub use watermark::WatermarkPosition;
+
+pub const TARGET_SIZE: usize = 1280;
+pub const QUALITY: f32 = 70.0;
+/// Enough for any camera sensor, while keeping a decoded image within a
+/// gigabyte or two.
+pub const MAX_PIXELS: u64 = 250_000_000;
+/// The memory a file is taken to need per source pixel under
+/// [`Options::memory_limit`]: the decoded image at up to 4 bytes per pixel,
+/// and as much again for the copies made while resizing and encoding.
+pub const MEMORY_PER_PIXEL: u64 = 8;
+/// The least quality `auto_quality` gives flat graphics, whose text and hard
+/// edges show artifacts long before photos do.
+const GRAPHIC_QUALITY: f32 = 90.0;
+/// Longest edges at and beyond which `quality_range` gives its lower and
+/// upper bound.
+const RANGE_EDGES: (usize, usize) = (256, 2048);
+/// The wait before the first retry of a failed write, doubled for each
+/// further one.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Minimum brightness difference, out of 255, that `sharpen` enhances.
+const SHARPEN_THRESHOLD: i32 = 2;
+
+#[derive(Clone)]
+pub struct Options {
+    pub target_size: usize,
+    /// Scales both dimensions by this percentage instead of fitting the
+    /// longest edge to `target_size`.
+    pub scale: Option<f32>,
+    /// Shrinks the image, keeping its aspect ratio, until it has at most this
+    /// many million pixels, instead of fitting the longest edge to
+    /// `target_size`.
+    pub max_megapixels: Option<f64>,
+    /// Fits the image within these bounds instead of fitting the longest edge
+    /// to `target_size`. A missing bound leaves that axis unconstrained.
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// Covers this exact width and height, center-cropping the overflow.
+    pub crop: Option<(u32, u32)>,
+    /// Sources narrower or shorter than this, once upright, are skipped.
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    /// Sources whose header declares more pixels than this are rejected
+    /// before being decoded; `None` accepts any size.
+    pub max_pixels: Option<u64>,
+    /// Whether images already within `target_size` or the bounds are resized.
+    pub resize_policy: ResizePolicy,
+    /// Which edge `target_size` applies to.
+    pub fit_edge: FitEdge,
+    /// Rounds the computed output size down to multiples of this, e.g. 16
+    /// for video codecs. A `crop` size is kept as given.
+    pub round_dimensions: Option<u32>,
+    pub quality: f32,
+    /// Replace `quality` for one output format each, since the same number
+    /// doesn't look the same across codecs.
+    pub jpeg_quality: Option<f32>,
+    pub webp_quality: Option<f32>,
+    pub avif_quality: Option<f32>,
+    /// Replaces every quality for sources that are JPEGs already. Each lossy
+    /// re-encode adds its own artifacts on top of the source's, so an
+    /// already compressed photo needs a higher quality than a lossless
+    /// source to come out looking the same.
+    pub jpeg_input_quality: Option<f32>,
+    /// Also encodes each image in its source's format when `format` converts
+    /// it, and keeps that instead if it is smaller, so that a conversion
+    /// never makes a file grow, as photos converted to PNG tend to.
+    pub keep_smaller_source_format: bool,
+    /// Encodes each image as JPEG, WebP and AVIF, and keeps whichever is
+    /// smallest in place of `format`. JPEG is left out for images with
+    /// transparency, which it can't keep.
+    pub smallest: bool,
+    /// Lowers the JPEG quality until the output fits in this many bytes.
+    pub max_bytes: Option<usize>,
+    /// The lowest quality the `max_bytes` search may go down to.
+    pub min_quality: f32,
+    /// Raises the quality until the output keeps at least this SSIM against
+    /// the resized image, up to 95. Ignored when `max_bytes` is set.
+    pub min_ssim: Option<f64>,
+    /// Replaces `quality` with one between these bounds, picked by the
+    /// output's longest edge: the first for thumbnails of up to 256 pixels,
+    /// the second from 2048 pixels, and proportionally in between.
+    pub quality_range: Option<(f32, f32)>,
+    /// Raises the quality to at least 90 for images that look like
+    /// screenshots or other flat graphics, keeping the configured quality
+    /// for photos. `max_bytes` takes precedence.
+    pub auto_quality: bool,
+    pub filter: FilterType,
+    /// Resamples each image in this many bands of rows at once, for batches
+    /// dominated by a few huge files that would otherwise leave cores idle.
+    /// 0 and 1 resize each image on one thread. Cropping isn't split.
+    pub threads_per_image: usize,
+    /// Applies an unsharp mask of this radius after resizing.
+    pub sharpen: Option<f32>,
+    /// Rotates the upright image before resizing.
+    pub rotate: Option<Rotation>,
+    /// Mirrors the image after `rotate`, before resizing.
+    pub flip: Option<Flip>,
+    /// Composited onto every output after resizing. Shared so that it is
+    /// decoded once for the whole batch.
+    pub watermark: Option<Arc<RgbaImage>>,
+    pub watermark_position: WatermarkPosition,
+    /// Multiplies the watermark's alpha, from 0 (invisible) to 1.
+    pub watermark_opacity: f32,
+    /// Applies the source's EXIF orientation so that the output is upright.
+    /// Turn it off for files whose pixels were rotated by a tool that left
+    /// the tag in place, which would otherwise be rotated twice.
+    pub autorotate: bool,
+    /// Converts color images to grayscale before resizing.
+    pub grayscale: bool,
+    /// The color transparent pixels are composited onto for formats without
+    /// an alpha channel.
+    pub background: [u8; 3],
+    /// JPEG chroma subsampling; `None` keeps mozjpeg's default.
+    pub subsampling: Option<Subsampling>,
+    pub jpeg_color_space: JpegColorSpace,
+    /// Writes progressive JPEGs, as mozjpeg does by default. When `false`,
+    /// baseline JPEGs are written for decoders that can't handle progressive
+    /// scans.
+    pub progressive: bool,
+    /// Computes optimal Huffman tables for each JPEG, as mozjpeg does by
+    /// default. Turning it off is faster but makes larger baseline files;
+    /// progressive files are always optimized.
+    pub optimize_coding: bool,
+    /// mozjpeg's input smoothing from 1 to 100, which can hide noise and
+    /// dithering; 0 disables it.
+    pub smoothing: u8,
+    pub webp_lossless: bool,
+    /// Applies Floyd–Steinberg dithering when GIF output is reduced to 256
+    /// colors, trading banding in gradients for fine noise. PNG output is
+    /// then also reduced to 256 colors with dithering and written indexed.
+    pub dither: bool,
+    /// oxipng preset from 1 (fastest) to 6 (smallest) applied to PNG output;
+    /// 0 writes the encoder's output as is.
+    pub png_level: u8,
+    /// Writes Adam7-interlaced PNGs, which display coarsely before they have
+    /// fully loaded at the cost of a slightly larger file.
+    pub png_interlace: bool,
+    pub tiff_compression: TiffCompression,
+    /// Resolution recorded in the JPEG header; `None` keeps mozjpeg's.
+    pub dpi: Option<u16>,
+    /// Copies the source unchanged when re-encoding it at its original size
+    /// doesn't make it smaller.
+    pub skip_smaller: bool,
+    /// Guarantees the output carries no EXIF, GPS, XMP or ICC metadata.
+    pub strip: bool,
+    /// Drops the source's ICC profile but keeps its other metadata.
+    pub strip_icc: bool,
+    /// Tags outputs without a profile of their own as sRGB, so that color
+    /// managed viewers don't have to guess: JPEG and WebP get a built-in
+    /// sRGB profile, PNG an `sRGB` chunk.
+    pub assume_srgb: bool,
+    /// Copies the source's EXIF data, such as camera, lens and capture
+    /// date, into JPEG output. Ignored when `strip` is set.
+    pub keep_exif: bool,
+    /// Keeps the GPS tags when `keep_exif` is set.
+    pub keep_gps: bool,
+    /// Overrides the output format. By default each output is written in the
+    /// format detected from the source's contents, or as JPEG if that format
+    /// can't be encoded.
+    pub format: Option<OutputFormat>,
+    /// Decodes every source as this format instead of the one detected from
+    /// its contents or extension.
+    pub input_format: Option<ImageFormat>,
+    /// Decodes CMYK and YCCK JPEG sources to RGB by their Adobe marker,
+    /// inverting the inks only where it says they are stored inverted, and
+    /// drops their ink profile so that they are recompressed as plain RGB.
+    /// Otherwise every CMYK JPEG is read as inverted, which turns those
+    /// from software that doesn't write the marker into negatives.
+    pub strip_to_baseline: bool,
+    /// Also writes every output in this format, e.g. a JPEG fallback for a
+    /// WebP, encoded from the same resized image. Its report follows the
+    /// main output's.
+    pub also_format: Option<OutputFormat>,
+    /// Prepended to the file stem of every output.
+    pub prefix: String,
+    /// Appended to the file stem of every output, before the extension.
+    pub suffix: String,
+    /// Appends the output's `_<width>x<height>` after `suffix`.
+    pub name_dimensions: bool,
+    /// Writes a `<output>.json` with the dimensions, format, quality and
+    /// sizes next to every output.
+    pub sidecar: bool,
+    /// Runs this command on every written output, e.g. an external
+    /// optimizer. It is split on whitespace, without a shell, and `{}` in
+    /// it is replaced by the output's path, which is otherwise appended.
+    pub post_command: Option<String>,
+    /// Names every output after this pattern instead.
+    pub name_template: Option<NameTemplate>,
+    /// Puts every output into a subfolder of its target directory by its
+    /// expected dimensions.
+    pub size_buckets: Option<SizeBuckets>,
+    /// Names every output after the SHA-256 of its contents, like
+    /// `<sha256>.jpg`, and skips writing it when that file already exists.
+    /// Dry runs still report the name the output would otherwise get, since
+    /// the contents aren't known.
+    pub content_name: bool,
+    /// Copies sources no decoder accepts, such as camera RAW files, instead
+    /// of failing on them.
+    pub copy_unsupported: bool,
+    pub overwrite: Overwrite,
+    /// Computes target paths and dimensions without encoding or writing.
+    pub dry_run: bool,
+    /// Encodes every output in memory with quicker compression settings to
+    /// predict its size, and writes nothing. The prediction is usually a
+    /// little above what a full run produces.
+    pub estimate: bool,
+    /// Reports the SHA-256 of every written file.
+    pub hash: bool,
+    /// Decodes every output again to measure, in [`Report::comparison`], how
+    /// much encoding degraded it.
+    pub compare: bool,
+    /// Gives every output its source's modification time, so that sync and
+    /// backup tools don't see it as changed on every run. Outputs without a
+    /// source file, such as those of downloads, keep the time of writing.
+    pub preserve_timestamps: bool,
+    /// Sets the permission bits of every output to this mode, such as
+    /// `0o644`, whatever the umask. Ignored on platforms other than Unix.
+    pub mode: Option<u32>,
+    /// Returns every output in [`Report::data`] instead of writing it, for
+    /// callers that store the outputs themselves. Targets are then never
+    /// checked for existing files, and no sidecars are written.
+    pub in_memory: bool,
+    /// Decodes every output again once it is written, or as held in memory,
+    /// and fails the file unless that works and gives the dimensions it was
+    /// encoded at. AVIF and JPEG XL can't be decoded and aren't checked.
+    pub validate: bool,
+    /// Removes an output that fails `validate`.
+    pub delete_invalid: bool,
+    /// Keeps a copy of every output scaled to fit a square of this many
+    /// pixels in [`Report::thumbnail`], e.g. for a contact sheet.
+    pub thumbnail_size: Option<u32>,
+    /// How many more times writing an output is attempted after an I/O
+    /// error, such as those a flaky network mount gives. Decoding and
+    /// encoding errors are never retried.
+    pub retries: u32,
+    /// Shared between all files of a batch to cap concurrent reads and
+    /// writes; `None` leaves I/O unthrottled.
+    pub io_limit: Option<Arc<IoLimit>>,
+    /// Shared between all files of a batch to bound the memory they take
+    /// together. A file is admitted once [`MEMORY_PER_PIXEL`] bytes per
+    /// source pixel are free, and fails if it needs more than the whole
+    /// limit.
+    pub memory_limit: Option<Arc<MemoryLimit>>,
+    /// The pool [`process_batch`] and [`process_batch_with`] run on. Services
+    /// that call them repeatedly can build one pool up front and share it
+    /// here, keeping it alive for as long as they like; `None` uses rayon's
+    /// global pool.
+    pub thread_pool: Option<Arc<rayon::ThreadPool>>,
+    /// Checked by [`process_batch`] and [`process_batch_with`] before each
+    /// file. Once it is set, files that haven't started fail with
+    /// [`Error::Cancelled`]; those already running finish.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Fails a file of [`process_batch`] or [`process_batch_with`] with
+    /// [`Error::TimedOut`] once it takes longer than this. Threads can't be
+    /// stopped safely, so the file's work is left running on the batch's
+    /// pool while the batch goes on. It holds a thread of the pool and
+    /// whatever else it took until it ends, and may still write its outputs.
+    pub timeout: Option<Duration>,
+}
+
+/// Which images are resized to fit the target box.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResizePolicy {
+    /// Only images larger than the box are shrunk; smaller ones keep their
+    /// size.
+    OnlyLarger,
+    /// Every image is scaled to fit the box, enlarging smaller ones with the
+    /// selected filter.
+    Always,
+}
+
+/// The edge of an image that is fitted to `target_size`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FitEdge {
+    /// The image fits in a `target_size` square.
+    Longest,
+    /// The image covers a `target_size` square, so that portrait and
+    /// landscape sources are both at least that large.
+    Shortest,
+}
+
+/// What to do when an output file already exists.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Overwrite it, but flag it in the report so the caller can warn.
+    Warn,
+    /// Leave it untouched and skip the source file.
+    Skip,
+    /// Overwrite it silently.
+    Force,
+    /// Fail the source file with [`Error::Exists`].
+    Error,
+    /// Write the output under the first free name with `_1`, `_2` and so
+    /// on appended to its stem.
+    Rename,
+}
+
+impl Options {
+    /// The quality `format` is encoded at.
+    pub fn quality_for(&self, format: OutputFormat) -> f32 {
+        let specific = match format {
+            OutputFormat::Jpeg => self.jpeg_quality,
+            OutputFormat::WebP => self.webp_quality,
+            OutputFormat::Avif => self.avif_quality,
+            _ => None,
+        };
+        specific.unwrap_or(self.quality)
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            target_size: TARGET_SIZE,
+            scale: None,
+            max_megapixels: None,
+            max_width: None,
+            max_height: None,
+            crop: None,
+            min_width: None,
+            min_height: None,
+            max_pixels: Some(MAX_PIXELS),
+            resize_policy: ResizePolicy::OnlyLarger,
+            fit_edge: FitEdge::Longest,
+            round_dimensions: None,
+            quality: QUALITY,
+            jpeg_quality: None,
+            webp_quality: None,
+            avif_quality: None,
+            jpeg_input_quality: None,
+            keep_smaller_source_format: false,
+            smallest: false,
+            max_bytes: None,
+            min_quality: 20.0,
+            min_ssim: None,
+            quality_range: None,
+            auto_quality: false,
+            filter: FilterType::Lanczos3,
+            threads_per_image: 0,
+            sharpen: None,
+            rotate: None,
+            flip: None,
+            watermark: None,
+            watermark_position: WatermarkPosition::BottomRight,
+            watermark_opacity: 1.0,
+            autorotate: true,
+            grayscale: false,
+            background: [255, 255, 255],
+            subsampling: None,
+            jpeg_color_space: JpegColorSpace::Rgb,
+            progressive: true,
+            optimize_coding: true,
+            smoothing: 0,
+            webp_lossless: false,
+            dither: false,
+            png_level: 2,
+            png_interlace: false,
+            tiff_compression: TiffCompression::Lzw,
+            dpi: None,
+            skip_smaller: false,
+            strip: false,
+            strip_icc: false,
+            assume_srgb: false,
+            keep_exif: false,
+            keep_gps: false,
+            format: None,
+            input_format: None,
+            strip_to_baseline: false,
+            also_format: None,
+            prefix: "resized_".to_string(),
+            suffix: String::new(),
+            name_dimensions: false,
+            sidecar: false,
+            post_command: None,
+            name_template: None,
+            size_buckets: None,
+            content_name: false,
+            copy_unsupported: false,
+            overwrite: Overwrite::Warn,
+            dry_run: false,
+            estimate: false,
+            hash: false,
+            compare: false,
+            preserve_timestamps: false,
+            mode: None,
+            in_memory: false,
+            validate: false,
+            delete_invalid: false,
+            thumbnail_size: None,
+            retries: 0,
+            io_limit: None,
+            memory_limit: None,
+            thread_pool: None,
+            cancel: None,
+            timeout: None,
+        }
+    }
+}
+
+/// Tightly packed pixels: 1 byte per pixel for `L8`, 3 for `Rgb8` or 4 for
+/// `Rgba8`, and twice that, in native byte order, for the 16-bit variants.
+#[derive(Clone)]
+pub struct ResizedImage {
+    pub data: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub color: ColorType,
+    /// The source's embedded ICC profile, carried over into JPEG output.
+    pub icc_profile: Option<Vec<u8>>,
+    /// The body of the EXIF segment written into JPEG output, when
+    /// [`Options::keep_exif`] is set.
+    pub exif: Option<Vec<u8>>,
+}
+
+impl ResizedImage {
+    /// Wraps a decoded image, e.g. one from [`decode`] that the caller
+    /// transformed themselves, for [`encode`].
+    ///
+    /// Takes the dimensions from the buffer itself rather than from the
+    /// requested target size, since `DynamicImage::resize` may round the
+    /// aspect ratio differently and the scanline loop must match the data.
+    ///
+    /// Transparency is kept so that formats supporting it can encode it;
+    /// [`encode`] flattens it for formats that don't.
+    ///
+    /// The image is consumed so that a buffer already in the target layout
+    /// is reused instead of copied.
+    pub fn from_image(img: DynamicImage) -> Self {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let alpha = img.color().has_alpha();
+        let gray = img.color().channel_count() == 1;
+        let (data, color) = if img.color().bytes_per_pixel() > img.color().channel_count() {
+            // Deeper sources keep 16 bits per channel until encoding, where
+            // only PNG can store them.
+            let (samples, color) = if alpha {
+                (img.into_rgba16().into_raw(), ColorType::Rgba16)
+            } else if gray {
+                (img.into_luma16().into_raw(), ColorType::L16)
+            } else {
+                (img.into_rgb16().into_raw(), ColorType::Rgb16)
+            };
+            (
+                samples.into_iter().flat_map(u16::to_ne_bytes).collect(),
+                color,
+            )
+        } else if alpha {
+            (img.into_rgba8().into_raw(), ColorType::Rgba8)
+        } else if gray {
+            (img.into_luma8().into_raw(), ColorType::L8)
+        } else {
+            (img.into_rgb8().into_raw(), ColorType::Rgb8)
+        };
+        ResizedImage {
+            data,
+            width,
+            height,
+            color,
+            icc_profile: None,
+            exif: None,
+        }
+    }
+
+    /// Rounds 16-bit samples down to 8 bits for the encoders that only take
+    /// those. Returns `None` when the image already is 8-bit.
+    pub(crate) fn to_8bit(&self) -> Option<ResizedImage> {
+        let color = match self.color {
+            ColorType::L16 => ColorType::L8,
+            ColorType::Rgb16 => ColorType::Rgb8,
+            ColorType::Rgba16 => ColorType::Rgba8,
+            _ => return None,
+        };
+        let data = self
+            .data
+            .chunks_exact(2)
+            .map(|b| ((u16::from_ne_bytes([b[0], b[1]]) as u32 + 128) / 257) as u8)
+            .collect();
+        Some(ResizedImage {
+            data,
+            width: self.width,
+            height: self.height,
+            color,
+            icc_profile: self.icc_profile.clone(),
+            exif: self.exif.clone(),
+        })
+    }
+
+    /// A copy of the image scaled to fit a `size` pixel square.
+    fn thumbnail(&self, size: u32) -> RgbaImage {
+        let narrowed = self.to_8bit();
+        let img = narrowed.as_ref().unwrap_or(self);
+        let rgba = ResizedImage {
+            data: img.data.clone(),
+            icc_profile: None,
+            exif: None,
+            ..*img
+        }
+        .into_rgba();
+        let full = RgbaImage::from_raw(img.width as u32, img.height as u32, rgba).unwrap();
+        let scale = (size as f64 / img.width.max(img.height) as f64).min(1.0);
+        let width = ((img.width as f64 * scale).round() as u32).max(1);
+        let height = ((img.height as f64 * scale).round() as u32).max(1);
+        image::imageops::thumbnail(&full, width, height)
+    }
+
+    /// Converts the pixels to RGBA, adding an opaque alpha channel if needed.
+    fn into_rgba(self) -> Vec<u8> {
+        match self.color {
+            ColorType::Rgba8 => self.data,
+            ColorType::L8 => self.data.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+            _ => self
+                .data
+                .chunks_exact(3)
+                .flat_map(|px| [px[0], px[1], px[2], 255])
+                .collect(),
+        }
+    }
+}
+
+/// The outcome of a successfully processed file.
+pub struct Report {
+    pub target: PathBuf,
+    /// The quality the output was encoded at.
+    pub quality: f32,
+    /// Dimensions of the source as stored, before orientation is applied.
+    pub original_width: usize,
+    pub original_height: usize,
+    /// Dimensions of the written image; zero when skipped.
+    pub width: usize,
+    pub height: usize,
+    /// Size of the source file.
+    pub original_bytes: u64,
+    /// Size of the written file; zero when nothing was written.
+    pub bytes: usize,
+    /// Hex SHA-256 of the written file, when `Options::hash` is set.
+    pub sha256: Option<String>,
+    pub status: Status,
+    pub timings: Timings,
+    /// The encoded output, when [`Options::in_memory`] is set.
+    pub data: Option<Vec<u8>>,
+    /// How many times writing the output was retried.
+    pub retries: u32,
+    /// The EXIF orientation applied to the source; 1 when it had none or
+    /// [`Options::autorotate`] is off.
+    pub orientation: u32,
+    /// The output scaled down, when [`Options::thumbnail_size`] is set. Not
+    /// kept for animated GIFs.
+    pub thumbnail: Option<RgbaImage>,
+    /// How the output compares with the resized image, when
+    /// [`Options::compare`] is set and the output format can be decoded.
+    /// Not measured for animated GIFs and multi-page TIFFs.
+    pub comparison: Option<Comparison>,
+    /// The output is in the source's format rather than [`Options::format`],
+    /// because [`Options::keep_smaller_source_format`] found it smaller.
+    pub kept_source_format: bool,
+    /// The output is larger than [`Options::max_bytes`] even at
+    /// [`Options::min_quality`].
+    pub over_budget: bool,
+    /// The output size was rounded down by [`Options::round_dimensions`].
+    pub snapped: bool,
+    /// How [`Options::post_command`] exited, when it ran.
+    pub post_command_status: Option<ExitStatus>,
+}
+
+/// Time spent in each stage of producing an output. A source decoded for
+/// several outputs counts its decode toward the first.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Timings {
+    pub decode: Duration,
+    pub resize: Duration,
+    /// Includes the whole of an animated GIF, whose frames are decoded and
+    /// resized one at a time as they are encoded.
+    pub encode: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Written,
+    /// The output replaced an existing file.
+    Overwritten,
+    /// The output already existed and nothing was written.
+    Skipped,
+    /// Only the output path and dimensions were computed.
+    DryRun,
+    /// The output was encoded to predict its size but not written.
+    Estimated,
+    /// The output's name was taken, so it was written under a numbered one.
+    Renamed,
+    /// Re-encoding didn't shrink the file, so the source was copied as is.
+    Copied,
+    /// The source is below `min_width` or `min_height` and was left alone.
+    TooSmall,
+    /// No decoder accepts the source, so it was copied as is under the name
+    /// its output would get.
+    CopiedUnsupported,
+}
+
+/// One input of a batch and where its output goes.
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub source: PathBuf,
+    pub target_dir: PathBuf,
+    /// Names the output instead of the source's file stem, e.g. to keep
+    /// same-named files apart when they are flattened into one directory.
+    pub stem: Option<OsString>,
+    /// Resizes only this page of a multi-page TIFF, counting from 0, instead
+    /// of every page.
+    pub page: Option<usize>,
+    /// The job's number in its batch, for `{index}` in
+    /// [`Options::name_template`].
+    pub index: Option<usize>,
+}
+
+impl Job {
+    /// Writes the output next to `source`.
+    pub fn new(source: PathBuf) -> Self {
+        let target_dir = source.parent().map(Path::to_path_buf).unwrap_or_default();
+        Job {
+            source,
+            target_dir,
+            stem: None,
+            page: None,
+            index: None,
+        }
+    }
+}
+
+/// Processes `paths` in parallel on `options.thread_pool`, writing each
+/// output next to its input. The results are in the same order as `paths`.
+pub fn process_batch(paths: &[PathBuf], options: &Options) -> Vec<Result<Report, Error>> {
+    let jobs = paths.iter().cloned().map(Job::new).collect::<Vec<_>>();
+    process_batch_with(&jobs, std::slice::from_ref(options), |_| {})
+        .into_iter()
+        .map(|result| result.map(|mut reports| reports.remove(0)))
+        .collect()
+}
+
+/// What [`process_batch_with`] reports about each file as it goes.
+pub enum Progress<'a> {
+    Started(&'a Job),
+    /// The file's reports, one per variant, and how long it took.
+    Finished(&'a Job, &'a Result<Vec<Report>, Error>, Duration),
+}
+
+/// Like [`process_batch`], but takes a target for every input, writes one
+/// output per entry of `variants` as [`process_variants`] does, and calls
+/// `on_progress` from the worker thread when each file starts and finishes,
+/// so that callers can drive their own progress display. The batch runs on
+/// the first variant's `thread_pool` and stops starting files once its
+/// `cancel` flag is set.
+pub fn process_batch_with<F>(
+    jobs: &[Job],
+    variants: &[Options],
+    on_progress: F,
+) -> Vec<Result<Vec<Report>, Error>>
+where
+    F: Fn(Progress) + Send + Sync,
+{
+    if let Some(timeout) = variants.first().and_then(|options| options.timeout) {
+        return process_within(jobs, variants, timeout, &on_progress);
+    }
+    let cancel = variants.first().and_then(|options| options.cancel.as_ref());
+    let run = || {
+        jobs.par_iter()
+            .map(|job| {
+                if cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+                    let result = Err(Error::Cancelled);
+                    on_progress(Progress::Finished(job, &result, Duration::ZERO));
+                    return result;
+                }
+                on_progress(Progress::Started(job));
+                let start = Instant::now();
+                let result = process_variants(job, variants);
+                on_progress(Progress::Finished(job, &result, start.elapsed()));
+                result
+            })
+            .collect()
+    };
+    match variants
+        .first()
+        .and_then(|options| options.thread_pool.as_ref())
+    {
+        Some(pool) => pool.install(run),
+        None => run(),
+    }
+}
+
+/// What a file of [`process_within`] sends back from the pool.
+enum Event {
+    Started(usize, Instant),
+    Finished(usize, Result<Vec<Report>, Error>),
+}
+
+/// Runs [`process_batch_with`] under `timeout`: every file is spawned onto
+/// the first variant's pool, or rayon's global one, and the calling thread
+/// waits for their results, giving up on each file once it has run for
+/// `timeout`. A file given up on is left running on the pool.
+fn process_within<F>(
+    jobs: &[Job],
+    variants: &[Options],
+    timeout: Duration,
+    on_progress: &F,
+) -> Vec<Result<Vec<Report>, Error>>
+where
+    F: Fn(Progress) + Send + Sync,
+{
+    let (sender, receiver) = mpsc::channel();
+    let batch = Arc::new((jobs.to_vec(), variants.to_vec()));
+    let pool = variants[0].thread_pool.as_ref();
+    for i in 0..jobs.len() {
+        let (sender, batch) = (sender.clone(), Arc::clone(&batch));
+        let task = move || {
+            let (jobs, variants) = &*batch;
+            if variants[0]
+                .cancel
+                .as_ref()
+                .is_some_and(|c| c.load(Ordering::Relaxed))
+            {
+                let _ = sender.send(Event::Finished(i, Err(Error::Cancelled)));
+                return;
+            }
+            let _ = sender.send(Event::Started(i, Instant::now()));
+            // A panic is only reported as the file timing out, as a hang would
+            // be, rather than taking the pool down.
+            let result =
+                panic::catch_unwind(AssertUnwindSafe(|| process_variants(&jobs[i], variants)));
+            if let Ok(result) = result {
+                // The receiver is gone once the batch stopped waiting.
+                let _ = sender.send(Event::Finished(i, result));
+            }
+        };
+        match pool {
+            Some(pool) => pool.spawn(task),
+            None => rayon::spawn(task),
+        }
+    }
+    drop(sender);
+
+    let mut started: Vec<Option<Instant>> = vec![None; jobs.len()];
+    let mut results: Vec<Option<Result<Vec<Report>, Error>>> =
+        (0..jobs.len()).map(|_| None).collect();
+    let mut pending = jobs.len();
+    while pending > 0 {
+        let running = (0..jobs.len()).filter(|&i| results[i].is_none());
+        let deadline = running
+            .filter_map(|i| started[i])
+            .min()
+            .map(|s| s + timeout);
+        let event = match deadline {
+            Some(deadline) => {
+                receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        let mut finished = Vec::new();
+        match event {
+            Ok(Event::Started(i, start)) => {
+                started[i] = Some(start);
+                on_progress(Progress::Started(&jobs[i]));
+            }
+            // A file that was given up on may still finish.
+            Ok(Event::Finished(i, result)) if results[i].is_none() => finished.push((i, result)),
+            Ok(Event::Finished(..)) => {}
+            Err(e) => {
+                // Once every file has reported, only those that panicked are
+                // left.
+                let disconnected = e == RecvTimeoutError::Disconnected;
+                finished.extend(
+                    (0..jobs.len())
+                        .filter(|&i| results[i].is_none())
+                        .filter(|&i| {
+                            disconnected || started[i].is_some_and(|s| s.elapsed() >= timeout)
+                        })
+                        .map(|i| (i, Err(Error::TimedOut(timeout)))),
+                );
+            }
+        }
+        for (i, result) in finished {
+            let elapsed = started[i].map_or(Duration::ZERO, |start| start.elapsed());
+            on_progress(Progress::Finished(&jobs[i], &result, elapsed));
+            results[i] = Some(result);
+            pending -= 1;
+        }
+    }
+    results.into_iter().flatten().collect()
+}
+
+/// Resizes and compresses `path`, writing the result into `target_dir`.
+pub fn process(path: &Path, target_dir: &Path, options: &Options) -> Result<Report, Error> {
+    let job = Job {
+        source: path.to_path_buf(),
+        target_dir: target_dir.to_path_buf(),
+        stem: None,
+        page: None,
+        index: None,
+    };
+    process_job(&job, options)
+}
+
+/// Like [`process`], naming the output after `job.stem` when it is set.
+pub fn process_job(job: &Job, options: &Options) -> Result<Report, Error> {
+    process_variants(job, std::slice::from_ref(options)).map(|mut reports| reports.remove(0))
+}
+
+/// Like [`process_job`], but writes one output per entry of `variants`,
+/// e.g. one per target size. The source is read and decoded only once.
+/// Each variant needs its own `prefix` or `suffix`, or the outputs
+/// overwrite each other. Returns a report per output, in order, with that of
+/// a variant's [`Options::also_format`] output right after its own.
+pub fn process_variants(job: &Job, variants: &[Options]) -> Result<Vec<Report>, Error> {
+    let Some(first) = variants.first() else {
+        return Ok(Vec::new());
+    };
+    let path = job.source.as_path();
+    let probed = with_io(first, || -> Result<_, Error> {
+        // The contents decide the format, so that a misnamed file is
+        // still decoded and written correctly.
+        let reader = match first.input_format {
+            Some(format) => {
+                image::io::Reader::with_format(BufReader::new(File::open(path)?), format)
+            }
+            None => image::io::Reader::open(path)?.with_guessed_format()?,
+        };
+        let input_format = match reader.format() {
+            Some(format) => format,
+            None => ImageFormat::from_path(path)?,
+        };
+        let dimensions = match job.page {
+            Some(page) if input_format == ImageFormat::Tiff => {
+                pages::page_dimensions(BufReader::new(File::open(path)?), page)?
+            }
+            _ => reader.into_dimensions()?,
+        };
+        Ok((fs::metadata(path)?, input_format, dimensions))
+    });
+    let (metadata, input_format, (original_width, original_height)) = match probed {
+        Err(Error::Unsupported(_)) if first.copy_unsupported => {
+            return copy_unsupported(job, variants)
+        }
+        probed => probed.map_err(|e| match e {
+            Error::Io(_) => e.at(Stage::Read),
+            e => e.at(Stage::Decode),
+        })?,
+    };
+    check_pixels((original_width, original_height), first)?;
+    let _reservation = match &first.memory_limit {
+        Some(limit) => {
+            let needed = original_width as u64 * original_height as u64 * MEMORY_PER_PIXEL;
+            Some(limit.reserve(needed).ok_or(Error::OverMemoryLimit {
+                needed,
+                limit: limit.limit(),
+            })?)
+        }
+        None => None,
+    };
+    let original_bytes = metadata.len();
+    let report = |target, quality, (width, height), status| Report {
+        target,
+        quality,
+        original_width: original_width as usize,
+        original_height: original_height as usize,
+        width,
+        height,
+        original_bytes,
+        bytes: 0,
+        sha256: None,
+        status,
+        timings: Timings::default(),
+        data: None,
+        retries: 0,
+        orientation: 1,
+        thumbnail: None,
+        comparison: None,
+        kept_source_format: false,
+        over_budget: false,
+        snapped: false,
+        post_command_status: None,
+    };
+
+    // Only read when a variant needs the upright size before decoding.
+    let exif_swapped = OnceCell::new();
+    let swapped = || {
+        *exif_swapped.get_or_init(|| first.autorotate && orientation::read_orientation(path) >= 5)
+    };
+    let expected = |options: &Options| {
+        expected_dimensions((original_width, original_height), swapped(), options)
+    };
+
+    // Both are loaded when the first variant needs them and shared by the
+    // rest.
+    let mut source_data = None;
+    let mut decoded = None;
+    let mut orientation = 1;
+    // Each variant's `also_format` output follows it, tagged with the same
+    // index so that it can take the variant's resized image.
+    let outputs = variants
+        .iter()
+        .enumerate()
+        .flat_map(|(i, options)| {
+            let format = options
+                .format
+                .unwrap_or_else(|| OutputFormat::for_input(input_format));
+            let also = options
+                .also_format
+                .filter(|&also| also != format)
+                .map(|format| Options {
+                    format: Some(format),
+                    also_format: None,
+                    ..options.clone()
+                });
+            [(i, Cow::Borrowed(options))]
+                .into_iter()
+                .chain(also.map(|also| (i, Cow::Owned(also))))
+        })
+        .collect::<Vec<_>>();
+    let mut shared = None;
+    let mut reports = Vec::with_capacity(outputs.len());
+    for (i, (variant, options)) in outputs.iter().enumerate() {
+        let (variant, options) = (*variant, options.as_ref());
+        let mut format = options
+            .format
+            .unwrap_or_else(|| OutputFormat::for_input(input_format));
+        let planned_format = format;
+        let dimensions = needs_dimensions(options).then(|| expected(options));
+        let target_file = output_path(job, format, dimensions, options);
+        if options.min_width.is_some() || options.min_height.is_some() {
+            let (width, height) = if swapped() {
+                (original_height, original_width)
+            } else {
+                (original_width, original_height)
+            };
+            if options.min_width.is_some_and(|min| width < min)
+                || options.min_height.is_some_and(|min| height < min)
+            {
+                reports.push(report(
+                    target_file,
+                    options.quality_for(format),
+                    (0, 0),
+                    Status::TooSmall,
+                ));
+                continue;
+            }
+        }
+        if options.dry_run {
+            let (width, height) = dimensions.unwrap_or_else(|| expected(options));
+            let dimensions = (width as usize, height as usize);
+            reports.push(report(
+                target_file,
+                options.quality_for(format),
+                dimensions,
+                Status::DryRun,
+            ));
+            continue;
+        }
+
+        // Skipped means the target is taken and must be left alone.
+        let claim = |target_file: PathBuf| match (
+            !options.in_memory && !options.estimate && target_file.exists(),
+            options.overwrite,
+        ) {
+            (false, _) => Ok((target_file, Status::Written)),
+            (true, Overwrite::Skip) => Ok((target_file, Status::Skipped)),
+            (true, Overwrite::Error) => Err(Error::Exists(target_file).at(Stage::Write)),
+            // The numbered name is only picked once the output is committed.
+            (true, Overwrite::Rename) => Ok((target_file, Status::Written)),
+            (true, _) => Ok((target_file, Status::Overwritten)),
+        };
+        // A content name is only claimed once the contents are known.
+        let (target_file, status) = if options.content_name {
+            (target_file, Status::Written)
+        } else {
+            claim(target_file)?
+        };
+        if status == Status::Skipped {
+            reports.push(report(
+                target_file,
+                options.quality_for(format),
+                (0, 0),
+                Status::Skipped,
+            ));
+            continue;
+        }
+
+        // The file is read in one go so that decoding, like encoding, needs
+        // no further I/O and runs outside the I/O limit.
+        if source_data.is_none() {
+            source_data = Some(
+                with_io(options, || fs::read(path)).map_err(|e| Error::from(e).at(Stage::Read))?,
+            );
+        }
+        let source = source_data.as_deref().unwrap();
+        let animated = input_format == ImageFormat::Gif && format == OutputFormat::Gif;
+        let multipage = job.page.is_none()
+            && input_format == ImageFormat::Tiff
+            && format == OutputFormat::Tiff
+            && pages::page_count(Cursor::new(source)) > 1;
+        let mut timings = Timings::default();
+        let mut thumbnail = None;
+        let mut comparison = None;
+        let (compressed_img_data, quality, width, height) = if animated {
+            let start = Instant::now();
+            let (data, width, height) =
+                animation::resize_gif(source, options).map_err(|e| e.at(Stage::Resize))?;
+            timings.encode = start.elapsed();
+            (data, options.quality_for(format), width, height)
+        } else if multipage {
+            let start = Instant::now();
+            let (data, width, height) =
+                pages::resize_tiff(source, options).map_err(|e| e.at(Stage::Resize))?;
+            timings.encode = start.elapsed();
+            (data, options.quality_for(format), width, height)
+        } else {
+            let resized = match shared.take().filter(|(of, _)| *of == variant) {
+                Some((_, resized)) => resized,
+                None => {
+                    if decoded.is_none() {
+                        let start = Instant::now();
+                        let (img, icc_profile, applied) = match job.page {
+                            Some(page) if input_format == ImageFormat::Tiff => {
+                                pages::decode_page(source, page).map(|(img, _)| (img, None, 1))
+                            }
+                            _ => decode_source(
+                                source,
+                                input_format,
+                                first.autorotate,
+                                first.strip_to_baseline,
+                            ),
+                        }
+                        .map_err(|e| e.at(Stage::Decode))?;
+                        decoded = Some((img, icc_profile));
+                        orientation = applied;
+                        timings.decode = start.elapsed();
+                    }
+                    // The last variant takes the image instead of copying it.
+                    let (img, icc_profile) = if variant + 1 == variants.len() {
+                        decoded.take().unwrap()
+                    } else {
+                        decoded.clone().unwrap()
+                    };
+                    let start = Instant::now();
+                    let mut resized = scale(img, options);
+                    resized.icc_profile = icc_profile;
+                    resized.exif = read_exif(source, options);
+                    timings.resize = start.elapsed();
+                    resized
+                }
+            };
+            if outputs.get(i + 1).is_some_and(|(next, _)| *next == variant) {
+                shared = Some((variant, resized.clone()));
+            }
+            thumbnail = options.thumbnail_size.map(|size| resized.thumbnail(size));
+            let mut encode_options = for_source(options, input_format);
+            if options.estimate {
+                encode_options.to_mut().png_level = options.png_level.min(1);
+            }
+            let start = Instant::now();
+            let (mut data, mut quality) = if options.smallest {
+                let (data, quality, smallest) =
+                    encode_smallest(&resized, &encode_options).map_err(|e| e.at(Stage::Encode))?;
+                format = smallest;
+                (data, quality)
+            } else {
+                encode_with(&resized, format, &encode_options).map_err(|e| e.at(Stage::Encode))?
+            };
+            if let Some(source_format) = OutputFormat::from_image_format(input_format)
+                .filter(|&f| options.keep_smaller_source_format && f != format)
+            {
+                let (kept, kept_quality) = encode_with(&resized, source_format, &encode_options)
+                    .map_err(|e| e.at(Stage::Encode))?;
+                if kept.len() < data.len() {
+                    (data, quality, format) = (kept, kept_quality, source_format);
+                }
+            }
+            timings.encode = start.elapsed();
+            if options.compare || options.min_ssim.is_some() {
+                comparison = compare::compare(&resized, &data, format, options.background);
+            }
+            (data, quality, resized.width, resized.height)
+        };
+        let kept_source_format = !options.smallest && options.format.is_some_and(|f| f != format);
+        let (target_file, status) = if format != planned_format && !options.content_name {
+            let target_file = output_path(job, format, dimensions, options);
+            let (target_file, status) = claim(target_file)?;
+            if status == Status::Skipped {
+                reports.push(report(target_file, quality, (0, 0), Status::Skipped));
+                continue;
+            }
+            (target_file, status)
+        } else {
+            (target_file, status)
+        };
+
+        let unchanged = (width as u32, height as u32) == (original_width, original_height)
+            || (height as u32, width as u32) == (original_width, original_height);
+        let (compressed_img_data, status) = if options.skip_smaller
+            && compressed_img_data.len() as u64 >= original_bytes
+            && unchanged
+            && keeps_pixels(options)
+            && OutputFormat::from_image_format(input_format) == Some(format)
+        {
+            (source.to_vec(), Status::Copied)
+        } else {
+            (compressed_img_data, status)
+        };
+        let target_file = if options.content_name {
+            let mut name = OsString::from(sha256_hex(&compressed_img_data));
+            name.push(".");
+            name.push(output_extension(&job.source, format));
+            let target_file = job.target_dir.join(name);
+            // The same name means the same contents.
+            if !options.in_memory && !options.estimate && target_file.exists() {
+                reports.push(report(target_file, quality, (0, 0), Status::Skipped));
+                continue;
+            }
+            target_file
+        } else {
+            target_file
+        };
+
+        let mut written = Report {
+            quality,
+            bytes: compressed_img_data.len(),
+            sha256: options.hash.then(|| sha256_hex(&compressed_img_data)),
+            timings,
+            orientation,
+            thumbnail,
+            comparison,
+            kept_source_format,
+            over_budget: options
+                .max_bytes
+                .is_some_and(|max| compressed_img_data.len() > max),
+            snapped: options.round_dimensions.is_some()
+                && (width as u32, height as u32)
+                    != expected(&Options {
+                        round_dimensions: None,
+                        ..options.clone()
+                    }),
+            ..report(
+                target_file,
+                options.quality_for(format),
+                (width, height),
+                status,
+            )
+        };
+        if options.estimate {
+            written.status = Status::Estimated;
+        } else if options.in_memory {
+            if options.validate {
+                validate(&compressed_img_data, format, (width, height))
+                    .map_err(|e| e.at(Stage::Validate))?;
+            }
+            written.data = Some(compressed_img_data);
+        } else {
+            written.retries = with_retries(options, || {
+                with_io(options, || -> Result<(), Error> {
+                    let Some(target) = write_output(
+                        &written.target,
+                        &compressed_img_data,
+                        Some(&metadata),
+                        options,
+                    )?
+                    else {
+                        // Another output of the batch took the name.
+                        written.status = Status::Skipped;
+                        return Ok(());
+                    };
+                    // The name was taken, before encoding or since.
+                    if target != written.target {
+                        written.target = target;
+                        written.status = Status::Renamed;
+                    }
+                    if options.sidecar {
+                        write_sidecar(Some(path), Some(format), &written, options)?;
+                    }
+                    Ok(())
+                })
+            })
+            .map_err(|e| e.at(Stage::Write))?;
+            if written.status == Status::Skipped {
+                reports.push(report(written.target, quality, (0, 0), Status::Skipped));
+                continue;
+            }
+            if options.validate {
+                let check = with_io(options, || fs::read(&written.target))
+                    .map_err(Error::from)
+                    .and_then(|data| validate(&data, format, (width, height)));
+                if let Err(e) = check {
+                    if options.delete_invalid {
+                        fs::remove_file(&written.target)
+                            .map_err(|e| Error::from(e).at(Stage::Validate))?;
+                    }
+                    return Err(e.at(Stage::Validate));
+                }
+            }
+            if let Some(command) = &options.post_command {
+                let status = run_post_command(command, &written.target)
+                    .map_err(|e| Error::from(e).at(Stage::PostCommand))?;
+                written.post_command_status = Some(status);
+                // The command may have rewritten the output in place.
+                let data = with_io(options, || fs::read(&written.target))
+                    .map_err(|e| Error::from(e).at(Stage::PostCommand))?;
+                written.bytes = data.len();
+                written.sha256 = options.hash.then(|| sha256_hex(&data));
+            }
+        }
+        reports.push(written);
+    }
+    Ok(reports)
+}
+
+/// Writes `data`, an output made outside [`process`] such as an archive, to
+/// `target` the way [`process`] writes its own: completely or not at all,
+/// under `options.overwrite`, and with the mode, retries, I/O limit, hash
+/// and sidecar that `options` asks for. `source` is the file it was made
+/// from, if any, for `options.preserve_timestamps` and the report, and
+/// `format` is what goes into the sidecar.
+pub fn write_bytes(
+    data: &[u8],
+    target: &Path,
+    source: Option<&Path>,
+    format: Option<OutputFormat>,
+    options: &Options,
+) -> Result<Report, Error> {
+    let metadata = source
+        .map(|source| with_io(options, || fs::metadata(source)))
+        .transpose()
+        .map_err(|e| Error::from(e).at(Stage::Read))?;
+    let (width, height) = image::io::Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok())
+        .unwrap_or_default();
+    let status = match options.overwrite {
+        _ if !target.exists() => Status::Written,
+        Overwrite::Skip => Status::Skipped,
+        Overwrite::Error => return Err(Error::Exists(target.to_path_buf()).at(Stage::Write)),
+        // The numbered name is only picked once the output is committed.
+        Overwrite::Rename => Status::Written,
+        _ => Status::Overwritten,
+    };
+    let mut report = Report {
+        target: target.to_path_buf(),
+        quality: options.quality,
+        original_width: 0,
+        original_height: 0,
+        width: width as usize,
+        height: height as usize,
+        original_bytes: metadata.as_ref().map_or(0, fs::Metadata::len),
+        bytes: data.len(),
+        sha256: options.hash.then(|| sha256_hex(data)),
+        status,
+        timings: Timings::default(),
+        data: None,
+        retries: 0,
+        orientation: 1,
+        thumbnail: None,
+        comparison: None,
+        kept_source_format: false,
+        over_budget: false,
+        snapped: false,
+        post_command_status: None,
+    };
+    if status == Status::Skipped {
+        return Ok(report);
+    }
+    report.retries = with_retries(options, || {
+        with_io(options, || -> Result<(), Error> {
+            match write_output(target, data, metadata.as_ref(), options)? {
+                Some(written) if written != target => {
+                    report.target = written;
+                    report.status = Status::Renamed;
+                }
+                Some(_) => {}
+                None => {
+                    report.status = Status::Skipped;
+                    return Ok(());
+                }
+            }
+            if options.sidecar {
+                write_sidecar(source, format, &report, options)?;
+            }
+            Ok(())
+        })
+    })
+    .map_err(|e| e.at(Stage::Write))?;
+    Ok(report)
+}
+
+/// Runs `command` on `target` as [`Options::post_command`] describes. Its
+/// output goes to stderr, so that it can't mix with what the caller prints
+/// to stdout.
+fn run_post_command(command: &str, target: &Path) -> io::Result<ExitStatus> {
+    let mut words = command.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "the command is empty"))?;
+    let mut args = words
+        .map(|word| match word {
+            "{}" => target.as_os_str().to_os_string(),
+            word => word.replace("{}", &target.to_string_lossy()).into(),
+        })
+        .collect::<Vec<_>>();
+    if !command.contains("{}") {
+        args.push(target.as_os_str().to_os_string());
+    }
+    Command::new(program)
+        .args(args)
+        .stdout(io::stderr())
+        .status()
+}
+
+/// Checks that `data` decodes as `format` to `dimensions`. Formats without a
+/// decoder pass unchecked.
+fn validate(data: &[u8], format: OutputFormat, dimensions: (usize, usize)) -> Result<(), Error> {
+    let Some(image_format) = (!matches!(format, OutputFormat::Avif | OutputFormat::Jxl))
+        .then(|| ImageFormat::from_extension(format.extension()))
+        .flatten()
+    else {
+        return Ok(());
+    };
+    let img = image::load_from_memory_with_format(data, image_format)
+        .map_err(|e| Error::Invalid(format!("it doesn't decode ({})", e)))?;
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    if (width, height) != dimensions {
+        return Err(Error::Invalid(format!(
+            "it decodes to {}x{} instead of {}x{}",
+            width, height, dimensions.0, dimensions.1
+        )));
+    }
+    Ok(())
+}
+
+/// Copies a source that can't be decoded for every variant, under the name
+/// its output would get but with its own extension. A copy that would land
+/// on the source itself is skipped.
+fn copy_unsupported(job: &Job, variants: &[Options]) -> Result<Vec<Report>, Error> {
+    let path = job.source.as_path();
+    let stem = job
+        .stem
+        .as_deref()
+        .unwrap_or_else(|| path.file_stem().unwrap_or_default());
+    let mut data = None;
+    let mut reports = Vec::with_capacity(variants.len());
+    for options in variants {
+        let metadata =
+            with_io(options, || fs::metadata(path)).map_err(|e| Error::from(e).at(Stage::Read))?;
+        let mut name = OsString::from(&options.prefix);
+        name.push(stem);
+        name.push(&options.suffix);
+        if let Some(ext) = path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        let mut target = job.target_dir.join(name);
+        let report = |target, status, bytes| Report {
+            target,
+            quality: options.quality,
+            original_width: 0,
+            original_height: 0,
+            width: 0,
+            height: 0,
+            original_bytes: metadata.len(),
+            bytes,
+            sha256: None,
+            status,
+            timings: Timings::default(),
+            data: None,
+            retries: 0,
+            orientation: 1,
+            thumbnail: None,
+            comparison: None,
+            kept_source_format: false,
+            over_budget: false,
+            snapped: false,
+            post_command_status: None,
+        };
+        if options.dry_run {
+            reports.push(report(target, Status::DryRun, 0));
+            continue;
+        }
+        if options.estimate {
+            let bytes = metadata.len() as usize;
+            reports.push(report(target, Status::Estimated, bytes));
+            continue;
+        }
+        let onto_source = fs::canonicalize(&target).ok()
+            == Some(fs::canonicalize(path).map_err(|e| Error::from(e).at(Stage::Read))?);
+        let exists = !options.in_memory && (onto_source || target.exists());
+        match options.overwrite {
+            _ if !exists => {}
+            Overwrite::Rename => {}
+            _ if onto_source || options.overwrite == Overwrite::Skip => {
+                reports.push(report(target, Status::Skipped, 0));
+                continue;
+            }
+            Overwrite::Error => return Err(Error::Exists(target).at(Stage::Write)),
+            _ => {}
+        }
+        if data.is_none() {
+            data = Some(
+                with_io(options, || fs::read(path)).map_err(|e| Error::from(e).at(Stage::Read))?,
+            );
+        }
+        let data = data.as_deref().unwrap();
+        let mut retries = 0;
+        let mut skipped = false;
+        if !options.in_memory {
+            retries = with_retries(options, || {
+                with_io(options, || {
+                    match write_output(&target, data, Some(&metadata), options)? {
+                        Some(written) => target = written,
+                        None => skipped = true,
+                    }
+                    Ok(())
+                })
+            })
+            .map_err(|e| e.at(Stage::Write))?;
+        }
+        if skipped {
+            reports.push(report(target, Status::Skipped, 0));
+            continue;
+        }
+        reports.push(Report {
+            sha256: options.hash.then(|| sha256_hex(data)),
+            retries,
+            data: options.in_memory.then(|| data.to_vec()),
+            ..report(target, Status::CopiedUnsupported, data.len())
+        });
+    }
+    Ok(reports)
+}
+
+/// Runs `write` again after an I/O error, up to `options.retries` times,
+/// waiting twice as long before each retry. Returns how many retries it
+/// took.
+fn with_retries(
+    options: &Options,
+    mut write: impl FnMut() -> Result<(), Error>,
+) -> Result<u32, Error> {
+    let mut retries = 0;
+    loop {
+        match write() {
+            Err(Error::Io(_)) if retries < options.retries => {
+                thread::sleep(RETRY_DELAY.saturating_mul(1 << retries.min(10)));
+                retries += 1;
+            }
+            result => return result.map(|()| retries),
+        }
+    }
+}
+
+/// Writes one output, creating its directory if needed, and returns the
+/// path it was written to, or `None` if it was skipped because the name was
+/// taken meanwhile.
+fn write_output(
+    target: &Path,
+    data: &[u8],
+    source_metadata: Option<&fs::Metadata>,
+    options: &Options,
+) -> Result<Option<PathBuf>, Error> {
+    fs::create_dir_all(target.parent().unwrap_or(Path::new("")))?;
+    // Written next to the target and moved into place once complete, so
+    // that a failure or crash never leaves a truncated output behind.
+    let temp = temp_path(target);
+    let written = write_temp(&temp, data, source_metadata, options)
+        .and_then(|()| commit_output(&temp, target, options.overwrite));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+/// A hidden name in `target`'s directory that no other output of this or
+/// another running batch uses.
+fn temp_path(target: &Path) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let mut name = OsString::from(".");
+    name.push(target.file_name().unwrap_or_default());
+    name.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    target.with_file_name(name)
+}
+
+fn write_temp(
+    temp: &Path,
+    data: &[u8],
+    source_metadata: Option<&fs::Metadata>,
+    options: &Options,
+) -> Result<(), Error> {
+    let mut file = BufWriter::new(File::create(temp)?);
+    file.write_all(data)?;
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    if let Some(metadata) = source_metadata.filter(|_| options.preserve_timestamps) {
+        let mtime = FileTime::from_last_modification_time(metadata);
+        filetime::set_file_mtime(temp, mtime)?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = options.mode {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(temp, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+/// `target` with `_<n>` appended to its stem.
+fn numbered(target: &Path, n: u32) -> PathBuf {
+    let mut name = target.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("_{}", n));
+    if let Some(ext) = target.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    target.with_file_name(name)
+}
+
+/// Moves the finished `temp` file to `target`. Unless existing files may be
+/// replaced, it only takes the name if it is still free, so that a file that
+/// appeared since the check before encoding, such as another output of the
+/// batch, is never overwritten. With [`Overwrite::Rename`] the first free
+/// of `target`'s stem with `_1`, `_2` and so on appended is taken instead,
+/// and with [`Overwrite::Skip`] the output is dropped. Returns the path the
+/// output ended up at, or `None` if it was dropped.
+fn commit_output(
+    temp: &Path,
+    target: &Path,
+    overwrite: Overwrite,
+) -> Result<Option<PathBuf>, Error> {
+    if matches!(overwrite, Overwrite::Warn | Overwrite::Force) {
+        fs::rename(temp, target)?;
+        return Ok(Some(target.to_path_buf()));
+    }
+    let mut candidate = target.to_path_buf();
+    let mut n = 0;
+    loop {
+        // A hard link fails rather than replace an existing file, which a
+        // rename would do.
+        match link_or_copy(temp, &candidate) {
+            Ok(()) => {
+                fs::remove_file(temp)?;
+                return Ok(Some(candidate));
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => match overwrite {
+                Overwrite::Rename => {
+                    n += 1;
+                    candidate = numbered(target, n);
+                }
+                Overwrite::Skip => {
+                    fs::remove_file(temp)?;
+                    return Ok(None);
+                }
+                _ => return Err(Error::Exists(candidate)),
+            },
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Hard-links `from` to `to`, or copies it on file systems without hard
+/// links. Fails with [`io::ErrorKind::AlreadyExists`] if `to` exists.
+fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => {
+            let mut file = OpenOptions::new().write(true).create_new(true).open(to)?;
+            let copied = io::copy(&mut File::open(from)?, &mut file).map(drop);
+            if copied.is_err() {
+                let _ = fs::remove_file(to);
+            }
+            copied
+        }
+        linked => linked,
+    }
+}
+
+/// Writes `<target>.json` describing an output, so that tools don't need to
+/// open the image for its size. Fields are only ever added to it.
+fn write_sidecar(
+    source: Option<&Path>,
+    format: Option<OutputFormat>,
+    report: &Report,
+    options: &Options,
+) -> Result<(), Error> {
+    let sidecar = serde_json::json!({
+        "source": source,
+        "target": report.target,
+        "format": format.map(OutputFormat::name),
+        "quality": format
+            .filter(|format| format.has_quality())
+            .map(|_| report.quality),
+        "original_width": report.original_width,
+        "original_height": report.original_height,
+        "width": report.width,
+        "height": report.height,
+        "original_bytes": report.original_bytes,
+        "bytes": report.bytes,
+        "sha256": report.sha256,
+    });
+    let mut path = report.target.clone().into_os_string();
+    path.push(".json");
+    let path = PathBuf::from(path);
+    // Committed like the output it describes, but always replacing an older
+    // sidecar, since it has to match the output just written.
+    let temp = temp_path(&path);
+    let data = serde_json::to_vec_pretty(&sidecar).unwrap_or_default();
+    let written = write_temp(&temp, &data, None, options)
+        .and_then(|()| commit_output(&temp, &path, Overwrite::Force));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written.map(drop)
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Runs `f` under `options.io_limit`, if one is set.
+fn with_io<T>(options: &Options, f: impl FnOnce() -> T) -> T {
+    match &options.io_limit {
+        Some(limit) => limit.run(f),
+        None => f(),
+    }
+}
+
+/// Returns `true` if `options` leave the pixels of an image that isn't
+/// resized as they are, so the source file is an acceptable output.
+fn keeps_pixels(options: &Options) -> bool {
+    !options.grayscale
+        && options.sharpen.is_none()
+        && !options.strip
+        && !options.strip_icc
+        && !options.assume_srgb
+        && options.rotate.is_none()
+        && options.flip.is_none()
+        && options.watermark.is_none()
+}
+
+/// Resizes and compresses an image held in memory, such as one read from
+/// stdin. The output format is chosen the same way as in [`process`].
+pub fn process_bytes(data: &[u8], options: &Options) -> Result<Vec<u8>, Error> {
+    let input_format = format_of(data, options)?;
+    let format = options
+        .format
+        .unwrap_or_else(|| OutputFormat::for_input(input_format));
+    let resized = resize_bytes_with(data, options)?;
+    Ok(encode_with(&resized, format, &for_source(options, input_format))?.0)
+}
+
+/// The largest icon an ICO file can hold.
+const MAX_ICO_SIZE: u32 = 256;
+
+/// Builds a multi-resolution ICO file, such as a favicon, from an image held
+/// in memory. Each of `sizes` becomes one square PNG-compressed icon of that
+/// many pixels, the image scaled with `options.filter` to fit and centered on
+/// transparency. Sizes above 256 are rejected.
+pub fn process_ico(data: &[u8], sizes: &[u32], options: &Options) -> Result<Vec<u8>, Error> {
+    if let Some(&size) = sizes.iter().find(|&&s| s == 0 || s > MAX_ICO_SIZE) {
+        return Err(Error::Compress(format!(
+            "icon size {} is outside 1-{}",
+            size, MAX_ICO_SIZE
+        )));
+    }
+    let input_format = format_of(data, options)?;
+    let dimensions =
+        image::io::Reader::with_format(Cursor::new(data), input_format).into_dimensions()?;
+    check_pixels(dimensions, options)?;
+    let (img, _, _) = decode_source(
+        data,
+        input_format,
+        options.autorotate,
+        options.strip_to_baseline,
+    )?;
+    let icons = sizes
+        .iter()
+        .map(|&size| {
+            let (width, height) =
+                fit_dimensions(img.width(), img.height(), size as usize, FitEdge::Longest);
+            let scaled = img.resize_exact(width, height, options.filter).into_rgba8();
+            let mut icon = RgbaImage::new(size, size);
+            image::imageops::overlay(
+                &mut icon,
+                &scaled,
+                ((size - width) / 2) as i64,
+                ((size - height) / 2) as i64,
+            );
+            icon
+        })
+        .collect::<Vec<_>>();
+    let frames = icons
+        .iter()
+        .map(|icon| IcoFrame::as_png(icon, icon.width(), icon.height(), ColorType::Rgba8))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut buf = Vec::new();
+    IcoEncoder::new(&mut buf).encode_images(&frames)?;
+    Ok(buf)
+}
+
+/// Applies `options.jpeg_input_quality` when the source is a JPEG.
+fn for_source(options: &Options, input_format: ImageFormat) -> Cow<'_, Options> {
+    let mut options = Cow::Borrowed(options);
+    if let Some(quality) = options
+        .jpeg_input_quality
+        .filter(|_| input_format == ImageFormat::Jpeg)
+    {
+        let options = options.to_mut();
+        options.quality = quality;
+        options.jpeg_quality = Some(quality);
+        options.webp_quality = Some(quality);
+        options.avif_quality = Some(quality);
+    }
+    options
+}
+
+/// Decodes an encoded image, applies its EXIF orientation and scales it,
+/// keeping its ICC profile.
+fn decode_and_scale(
+    data: &[u8],
+    format: ImageFormat,
+    options: &Options,
+) -> Result<ResizedImage, Error> {
+    let (img, icc_profile, _) =
+        decode_source(data, format, options.autorotate, options.strip_to_baseline)?;
+    let mut resized = scale(img, options);
+    resized.icc_profile = icc_profile;
+    resized.exif = read_exif(data, options);
+    Ok(resized)
+}
+
+/// Rejects a source that would exceed `options.max_pixels` once decoded.
+fn check_pixels((width, height): (u32, u32), options: &Options) -> Result<(), Error> {
+    match options.max_pixels {
+        Some(max_pixels) if width as u64 * height as u64 > max_pixels => Err(Error::TooLarge {
+            width,
+            height,
+            max_pixels,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The EXIF segment to carry over from `data`, if `options` ask for one.
+fn read_exif(data: &[u8], options: &Options) -> Option<Vec<u8>> {
+    if !options.keep_exif || options.strip {
+        return None;
+    }
+    metadata::jpeg_exif_segment(data, options.keep_gps)
+}
+
+/// Decodes an encoded image, along with its ICC profile and the EXIF
+/// orientation applied to make it upright, which is 1 when `autorotate` is
+/// off.
+fn decode_source(
+    data: &[u8],
+    format: ImageFormat,
+    autorotate: bool,
+    strip_to_baseline: bool,
+) -> Result<(DynamicImage, Option<Vec<u8>>, u32), Error> {
+    // A CMYK source's profile describes inks, not the RGB it is decoded to.
+    let (img, cmyk) = match (strip_to_baseline && format == ImageFormat::Jpeg)
+        .then(|| cmyk::decode_cmyk_jpeg(data))
+        .flatten()
+    {
+        Some(img) => (img?, true),
+        None => (image::load_from_memory_with_format(data, format)?, false),
+    };
+    let orientation = if autorotate {
+        orientation::read_orientation_from(&mut Cursor::new(data))
+    } else {
+        1
+    };
+    let icc_profile = (!cmyk)
+        .then(|| icc::read_icc_profile_from(&mut Cursor::new(data)))
+        .flatten();
+    Ok((
+        orientation::apply_orientation(img, orientation),
+        icc_profile,
+        orientation,
+    ))
+}
+
+/// Encodes `resized` in each of the formats [`Options::smallest`] picks
+/// from, in parallel. Returns the smallest output, its quality and format.
+fn encode_smallest(
+    resized: &ResizedImage,
+    options: &Options,
+) -> Result<(Vec<u8>, f32, OutputFormat), Error> {
+    let candidates = [OutputFormat::Jpeg, OutputFormat::WebP, OutputFormat::Avif];
+    let encoded = candidates
+        .into_par_iter()
+        .filter(|&format| format != OutputFormat::Jpeg || !resized.color.has_alpha())
+        .map(|format| {
+            let (data, quality) = encode_with(resized, format, options)?;
+            Ok((data, quality, format))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    Ok(encoded
+        .into_iter()
+        .min_by_key(|(data, _, _)| data.len())
+        .unwrap())
+}
+
+/// Encodes at the quality for `format`, or searches for the quality that fits
+/// `options.max_bytes` when it is set, or the one that meets
+/// `options.min_ssim`. Returns the data and quality used.
+fn encode_with(
+    resized: &ResizedImage,
+    format: OutputFormat,
+    options: &Options,
+) -> Result<(Vec<u8>, f32), Error> {
+    if let Some(max_bytes) = options
+        .max_bytes
+        .filter(|_| format.has_quality() && !options.webp_lossless)
+    {
+        return encode_within(resized, format, max_bytes, options);
+    }
+    let mut options = Cow::Borrowed(options);
+    if let Some((low, high)) = options.quality_range {
+        let (small, large) = RANGE_EDGES;
+        let edge = resized.width.max(resized.height).clamp(small, large);
+        let share = (edge - small) as f32 / (large - small) as f32;
+        options.to_mut().quality = low + (high - low) * share;
+    }
+    if options.auto_quality && format.has_quality() && content::looks_graphic(resized) {
+        let quality = options.quality_for(format).max(GRAPHIC_QUALITY);
+        let options = options.to_mut();
+        options.jpeg_quality = Some(quality);
+        options.webp_quality = Some(quality);
+        options.avif_quality = Some(quality);
+    }
+    if let Some(min_ssim) = options
+        .min_ssim
+        .filter(|_| format.has_quality() && !options.webp_lossless)
+    {
+        return encode_above(resized, format, min_ssim, &options);
+    }
+    Ok((
+        encode(resized, format, &options)?,
+        options.quality_for(format),
+    ))
+}
+
+/// Builds the output path for `path`: `<prefix><stem><suffix>.<ext>` inside
+/// `target_dir`, or [`Options::name_template`] expanded, within its size
+/// bucket if there are any. The source extension is kept when it matches
+/// `format`.
+///
+/// When the path depends on the dimensions the image header is read for
+/// the size, and an unreadable one leaves them out.
+pub fn target_path(
+    path: &Path,
+    target_dir: &Path,
+    format: OutputFormat,
+    options: &Options,
+) -> PathBuf {
+    let job = Job {
+        target_dir: target_dir.to_path_buf(),
+        ..Job::new(path.to_path_buf())
+    };
+    let dimensions = needs_dimensions(options)
+        .then(|| image::image_dimensions(path).ok())
+        .flatten()
+        .map(|dimensions| {
+            let swapped = options.autorotate && orientation::read_orientation(path) >= 5;
+            expected_dimensions(dimensions, swapped, options)
+        });
+    output_path(&job, format, dimensions, options)
+}
+
+/// Whether the output's name includes its dimensions.
+fn names_dimensions(options: &Options) -> bool {
+    match &options.name_template {
+        Some(template) => template.needs_dimensions(),
+        None => options.name_dimensions,
+    }
+}
+
+/// Whether the output's path depends on its dimensions, through its name or
+/// its size bucket.
+fn needs_dimensions(options: &Options) -> bool {
+    names_dimensions(options) || options.size_buckets.is_some()
+}
+
+/// Joins the name as an `OsString`, so that a stem that isn't valid UTF-8
+/// comes through byte for byte.
+fn output_path(
+    job: &Job,
+    format: OutputFormat,
+    dimensions: Option<(u32, u32)>,
+    options: &Options,
+) -> PathBuf {
+    let stem = job
+        .stem
+        .as_deref()
+        .unwrap_or_else(|| job.source.file_stem().unwrap_or_default());
+    let ext = output_extension(&job.source, format);
+    let name = match &options.name_template {
+        Some(template) => template.expand(&Fields {
+            name: stem,
+            ext,
+            dimensions,
+            parent: &source_dir_name(&job.source),
+            index: job.index,
+        }),
+        None => {
+            let mut name = OsString::from(&options.prefix);
+            name.push(stem);
+            name.push(&options.suffix);
+            if let (Some((width, height)), true) = (dimensions, options.name_dimensions) {
+                name.push(format!("_{}x{}", width, height));
+            }
+            name.push(".");
+            name.push(ext);
+            name
+        }
+    };
+    match (&options.size_buckets, dimensions) {
+        (Some(buckets), Some((width, height))) => job
+            .target_dir
+            .join(buckets.bucket_for(width, height))
+            .join(name),
+        _ => job.target_dir.join(name),
+    }
+}
+
+/// The source's own extension when it matches `format`, so that `.jpeg`
+/// stays `.jpeg`, and the format's usual one otherwise.
+fn output_extension(source: &Path, format: OutputFormat) -> &OsStr {
+    match source.extension() {
+        Some(ext) if format.matches_extension(ext) => ext,
+        _ => OsStr::new(format.extension()),
+    }
+}
+
+/// The name of the directory `path` is in. Relative parents like `` or `..`
+/// are made absolute first, since they have no name of their own.
+fn source_dir_name(path: &Path) -> OsString {
+    let parent = path.parent().unwrap_or(Path::new(""));
+    match parent.file_name() {
+        Some(name) => name.to_os_string(),
+        None => fs::canonicalize(parent.join("."))
+            .ok()
+            .and_then(|dir| dir.file_name().map(OsStr::to_os_string))
+            .unwrap_or_default(),
+    }
+}
+
+/// Opens `path` and shrinks it so that its longest edge fits `target_size`.
+/// Images already within the limit are returned at their original size.
+/// The EXIF orientation is applied first, so the output is always upright.
+pub fn resize(path: &Path, target_size: usize) -> Result<ResizedImage, Error> {
+    let options = Options {
+        target_size,
+        ..Options::default()
+    };
+    resize_with(path, &options)
+}
+
+/// Like [`resize`], but decodes an image held in memory, such as an HTTP
+/// upload, detecting its format from its contents.
+pub fn resize_bytes(data: &[u8], target_size: usize) -> Result<ResizedImage, Error> {
+    let options = Options {
+        target_size,
+        ..Options::default()
+    };
+    resize_bytes_with(data, &options)
+}
+
+/// Like [`resize_bytes`], but takes the target size, scale, crop and filter
+/// from `options`.
+pub fn resize_bytes_with(data: &[u8], options: &Options) -> Result<ResizedImage, Error> {
+    let input_format = format_of(data, options)?;
+    let dimensions =
+        image::io::Reader::with_format(Cursor::new(data), input_format).into_dimensions()?;
+    check_pixels(dimensions, options)?;
+    decode_and_scale(data, input_format, options)
+}
+
+/// Decodes the image at `path` and turns it upright by its EXIF
+/// orientation, without resizing it, for callers that run their own
+/// transforms before [`encode`]. The contents decide the format, as they do
+/// for a batch.
+pub fn decode(path: &Path) -> Result<DynamicImage, Error> {
+    let data = fs::read(path).map_err(|e| Error::from(e).at(Stage::Read))?;
+    let format = image::guess_format(&data)
+        .or_else(|_| ImageFormat::from_path(path))
+        .map_err(|e| Error::from(e).at(Stage::Decode))?;
+    let (img, _, _) = decode_source(&data, format, true, false).map_err(|e| e.at(Stage::Decode))?;
+    Ok(img)
+}
+
+/// Like [`resize`], but takes the target size, scale, crop and filter from
+/// `options`.
+pub fn resize_with(path: &Path, options: &Options) -> Result<ResizedImage, Error> {
+    let format = match options.input_format {
+        Some(format) => format,
+        None => ImageFormat::from_path(path)?,
+    };
+    let reader = image::io::Reader::with_format(BufReader::new(File::open(path)?), format);
+    check_pixels(reader.into_dimensions()?, options)?;
+    let data = fs::read(path)?;
+    decode_and_scale(&data, format, options)
+}
+
+/// [`Options::input_format`], or the format the contents of `data` say.
+fn format_of(data: &[u8], options: &Options) -> ImageResult<ImageFormat> {
+    match options.input_format {
+        Some(format) => Ok(format),
+        None => image::guess_format(data),
+    }
+}
+
+/// Transforms, resizes, sharpens and watermarks an image that is already
+/// decoded, as [`resize_with`] does after reading it, without touching any
+/// files.
+///
+/// Each step goes through `replace`, so at most the input and output of
+/// one step are held at a time.
+pub fn scale(img: DynamicImage, options: &Options) -> ResizedImage {
+    // Transform first, so that the output size is computed from the
+    // rotated dimensions.
+    let img = orientation::apply_transform(img, options.rotate, options.flip);
+    let img = if options.grayscale {
+        replace(img, DynamicImage::grayscale)
+    } else {
+        img
+    };
+
+    let img = if let Some((crop_width, crop_height)) = options.crop {
+        replace(img, |img| {
+            img.resize_to_fill(crop_width, crop_height, options.filter)
+        })
+    } else {
+        let (target_width, target_height) = output_dimensions(img.width(), img.height(), options);
+        if (target_width, target_height) == (img.width(), img.height()) {
+            img
+        } else {
+            replace(img, |img| match options.threads_per_image {
+                0 | 1 => img.resize_exact(target_width, target_height, options.filter),
+                bands => {
+                    tiled::resize_exact(img, target_width, target_height, options.filter, bands)
+                }
+            })
+        }
+    };
+
+    let img = match options.sharpen {
+        // The threshold keeps flat areas, where the mask would only amplify
+        // noise and JPEG artifacts, untouched.
+        Some(sigma) => replace(img, |img| img.unsharpen(sigma, SHARPEN_THRESHOLD)),
+        None => img,
+    };
+    let mut img = img;
+    if let Some(mark) = &options.watermark {
+        watermark::apply(
+            &mut img,
+            mark,
+            options.watermark_position,
+            options.watermark_opacity,
+        );
+    }
+    ResizedImage::from_image(img)
+}
+
+/// Derives a new image from `img` and frees `img` right away, instead of at
+/// the end of the caller's scope.
+fn replace(img: DynamicImage, f: impl FnOnce(&DynamicImage) -> DynamicImage) -> DynamicImage {
+    f(&img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::png::PngEncoder;
+    use image::{GrayImage, ImageEncoder, Luma, Rgb, RgbImage, Rgba};
+
+    /// Writes `data` to a file of its own in the temporary directory.
+    fn temp_file(name: &str, data: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("resize-image-test-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn png(img: &DynamicImage) -> Vec<u8> {
+        let mut png = Cursor::new(Vec::new());
+        img.write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        png.into_inner()
+    }
+
+    /// Saves `img` as a PNG named `name` and resizes it under `options`.
+    fn resized_png(name: &str, img: &DynamicImage, options: &Options) -> ResizedImage {
+        let path = temp_file(name, &[]);
+        img.save_with_format(&path, image::ImageFormat::Png)
+            .unwrap();
+        let resized = resize_with(&path, options);
+        fs::remove_file(&path).unwrap();
+        resized.unwrap()
+    }
+
+    /// An empty directory of its own for a test that writes files.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("resize-image-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// The number of components in a JPEG's frame header.
+    fn jpeg_components(jpeg: &[u8]) -> u8 {
+        let sof = jpeg
+            .windows(2)
+            .position(|w| w[0] == 0xFF && matches!(w[1], 0xC0..=0xC2))
+            .unwrap();
+        jpeg[sof + 9]
+    }
+
+    /// A PNG whose left half is fully transparent and right half opaque.
+    fn half_transparent_png(width: u32, height: u32) -> Vec<u8> {
+        let img = RgbaImage::from_fn(width, height, |x, _| {
+            if x < width / 2 {
+                Rgba([255, 0, 0, 0])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        });
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png)
+            .write_image(img.as_raw(), width, height, ColorType::Rgba8)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn downscaled_png_keeps_alpha() {
+        let options = Options {
+            target_size: 16,
+            ..Options::default()
+        };
+        let resized = resize_bytes_with(&half_transparent_png(64, 32), &options).unwrap();
+        assert_eq!((resized.width, resized.height), (16, 8));
+        assert_eq!(resized.color, ColorType::Rgba8);
+
+        let png = encode(&resized, OutputFormat::Png, &options).unwrap();
+        let output = image::load_from_memory(&png).unwrap();
+        assert!(output.color().has_alpha());
+        let output = output.to_rgba8();
+        assert_eq!(output.get_pixel(0, 4)[3], 0);
+        assert_eq!(output.get_pixel(15, 4)[3], 255);
+    }
+
+    #[test]
+    fn square_oversized_image_fills_the_target() {
+        let img = RgbImage::from_fn(64, 64, |x, y| Rgb([x as u8 * 4, y as u8 * 4, 0]));
+        let options = Options {
+            target_size: 16,
+            ..Options::default()
+        };
+        let resized = resized_png("square.png", &DynamicImage::ImageRgb8(img), &options);
+        assert_eq!((resized.width, resized.height), (16, 16));
+        assert_eq!(resized.data.len(), 16 * 16 * 3);
+
+        let jpeg = encode(&resized, OutputFormat::Jpeg, &options).unwrap();
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (16, 16));
+    }
+
+    #[test]
+    fn grayscale_source_stays_single_channel() {
+        let img = GrayImage::from_fn(40, 30, |x, _| Luma([x as u8 * 6]));
+        let options = Options {
+            target_size: 20,
+            ..Options::default()
+        };
+        let resized = resized_png("gray.png", &DynamicImage::ImageLuma8(img), &options);
+        assert_eq!(resized.color, ColorType::L8);
+        assert_eq!(resized.data.len(), 20 * 15);
+
+        let jpeg = encode(&resized, OutputFormat::Jpeg, &options).unwrap();
+        assert_eq!(jpeg_components(&jpeg), 1);
+        let decoded = image::load_from_memory(&jpeg).unwrap();
+        assert_eq!(decoded.color(), ColorType::L8);
+        assert_eq!((decoded.width(), decoded.height()), (20, 15));
+        // The gradient runs left to right, so rows that are misaligned by a
+        // wrong stride would break it.
+        let decoded = decoded.into_luma8();
+        assert!(decoded.get_pixel(0, 7)[0] < decoded.get_pixel(19, 7)[0]);
+    }
+
+    #[test]
+    fn rotated_jpeg_is_resized_upright() {
+        let path = temp_file("rotated.jpg", &orientation::tests::jpeg_with_orientation(6));
+        let resized = resize(&path, 1280);
+        let unrotated = resize_with(
+            &path,
+            &Options {
+                autorotate: false,
+                ..Options::default()
+            },
+        );
+        fs::remove_file(&path).unwrap();
+        let resized = resized.unwrap();
+        assert_eq!((resized.width, resized.height), (2, 4));
+        let unrotated = unrotated.unwrap();
+        assert_eq!((unrotated.width, unrotated.height), (4, 2));
+    }
+
+    #[test]
+    fn sixteen_bit_png_keeps_its_depth() {
+        // Values that 8 bits can't hold, so narrowing anywhere would show.
+        let img =
+            image::ImageBuffer::from_fn(32, 16, |x, _| Rgb([1000 + x as u16 * 1000, 40_001, 3]));
+        let options = Options {
+            target_size: 16,
+            ..Options::default()
+        };
+        let resized = resized_png("sixteen.png", &DynamicImage::ImageRgb16(img), &options);
+        assert_eq!(resized.color, ColorType::Rgb16);
+        assert_eq!((resized.width, resized.height), (16, 8));
+
+        let png = encode(&resized, OutputFormat::Png, &options).unwrap();
+        let output = image::load_from_memory(&png).unwrap();
+        assert_eq!(output.color(), ColorType::Rgb16);
+        let output = output.into_rgb16();
+        assert_eq!(output.get_pixel(8, 4)[1], 40_001);
+        assert_eq!(output.get_pixel(8, 4)[2], 3);
+        assert!(output.get_pixel(0, 4)[0] < output.get_pixel(15, 4)[0]);
+
+        let jpeg = encode(&resized, OutputFormat::Jpeg, &options).unwrap();
+        assert_eq!(
+            image::load_from_memory(&jpeg).unwrap().color(),
+            ColorType::Rgb8
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_file_name_round_trips() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        let name = OsString::from_vec(b"caf\xe9.png".to_vec());
+        let expected = OsStr::from_bytes(b"resized_caf\xe9.png");
+        let options = Options::default();
+        let target = target_path(
+            Path::new(&name),
+            Path::new("out"),
+            OutputFormat::Png,
+            &options,
+        );
+        assert_eq!(target, Path::new("out").join(expected));
+
+        let dir = scratch_dir("non-utf8");
+        let source = dir.join(&name);
+        fs::write(&source, png(&DynamicImage::ImageRgb8(RgbImage::new(8, 8)))).unwrap();
+        let report = process(&source, &dir, &options).unwrap();
+        assert_eq!(report.target, dir.join(expected));
+        assert!(report.target.is_file());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bare_file_name_targets_the_working_directory() {
+        let job = Job::new(PathBuf::from("small.png"));
+        assert_eq!(job.target_dir, Path::new(""));
+        let options = Options::default();
+        let target = target_path(&job.source, &job.target_dir, OutputFormat::Png, &options);
+        assert_eq!(target, Path::new("resized_small.png"));
+        // Nothing to take a stem from still gives a name, not a panic.
+        let target = target_path(Path::new(".."), Path::new(""), OutputFormat::Png, &options);
+        assert_eq!(target, Path::new("resized_.png"));
+    }
+
+    #[test]
+    fn sidecar_is_written_beside_the_output() {
+        let dir = scratch_dir("sidecar");
+        let source = dir.join("photo.png");
+        fs::write(&source, png(&DynamicImage::ImageRgb8(RgbImage::new(6, 4)))).unwrap();
+        let options = Options {
+            sidecar: true,
+            ..Options::default()
+        };
+        let report = process(&source, &dir, &options).unwrap();
+        let mut path = report.target.into_os_string();
+        path.push(".json");
+        let sidecar: serde_json::Value = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+        assert_eq!(sidecar["format"], "png");
+        assert_eq!(sidecar["width"], 6);
+        // No temporary file is left behind.
+        let mut names = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            ["photo.png", "resized_photo.png", "resized_photo.png.json"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resize_bytes_decodes_png_and_jpeg_buffers() {
+        let img = RgbImage::from_fn(64, 32, |x, _| Rgb([x as u8 * 4, 0, 0]));
+        let resized = resize_bytes(&png(&DynamicImage::ImageRgb8(img)), 16).unwrap();
+        assert_eq!((resized.width, resized.height), (16, 8));
+        assert_eq!(resized.color, ColorType::Rgb8);
+        assert_eq!(resized.data.len(), 16 * 8 * 3);
+
+        let jpeg = compress(&[90; 40 * 20 * 3], 40, 20, 90.0).unwrap();
+        let resized = resize_bytes(&jpeg, 20).unwrap();
+        assert_eq!((resized.width, resized.height), (20, 10));
+        assert_eq!(resized.data.len(), 20 * 10 * 3);
+    }
+
+    #[test]
+    fn resize_bytes_rejects_data_that_is_no_image() {
+        assert!(resize_bytes(b"not an image", 16).is_err());
+        assert!(resize_bytes(&[], 16).is_err());
+        let jpeg = compress(&[90; 40 * 20 * 3], 40, 20, 90.0).unwrap();
+        assert!(resize_bytes(&jpeg[..jpeg.len() / 2], 16).is_err());
+    }
+
+    #[test]
+    fn commit_skips_a_name_taken_meanwhile() {
+        let dir = scratch_dir("commit-skip");
+        let (temp, target) = (dir.join(".a.tmp"), dir.join("a.jpg"));
+        fs::write(&target, "first").unwrap();
+        fs::write(&temp, "second").unwrap();
+        assert_eq!(
+            commit_output(&temp, &target, Overwrite::Skip).unwrap(),
+            None
+        );
+        assert!(!temp.exists());
+        assert_eq!(fs::read(&target).unwrap(), b"first");
+
+        fs::write(&temp, "second").unwrap();
+        let result = commit_output(&temp, &target, Overwrite::Error);
+        assert!(matches!(result, Err(Error::Exists(path)) if path == target));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn commit_numbers_renamed_outputs_from_the_original_stem() {
+        let dir = scratch_dir("commit-rename");
+        let target = dir.join("a.jpg");
+        let temp = dir.join(".a.tmp");
+        for (n, expected) in ["a.jpg", "a_1.jpg", "a_2.jpg", "a_3.jpg"]
+            .iter()
+            .enumerate()
+        {
+            fs::write(&temp, n.to_string()).unwrap();
+            let committed = commit_output(&temp, &target, Overwrite::Rename).unwrap();
+            assert_eq!(committed, Some(dir.join(expected)));
+        }
+        assert_eq!(fs::read(dir.join("a_2.jpg")).unwrap(), b"2");
+        assert!(!dir.join("a_1_1.jpg").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_bytes_follows_the_overwrite_policy() {
+        let dir = scratch_dir("write-bytes");
+        let target = dir.join("icon.ico");
+        let write = |overwrite| {
+            let options = Options {
+                overwrite,
+                ..Options::default()
+            };
+            write_bytes(b"new", &target, None, None, &options)
+        };
+        let report = write(Overwrite::Skip).unwrap();
+        assert_eq!(
+            (report.status, fs::read(&target).unwrap()),
+            (Status::Written, b"new".to_vec())
+        );
+
+        fs::write(&target, "old").unwrap();
+        assert_eq!(write(Overwrite::Skip).unwrap().status, Status::Skipped);
+        assert_eq!(write(Overwrite::Error).err().unwrap().kind(), "exists");
+        assert_eq!(fs::read(&target).unwrap(), b"old");
+        let report = write(Overwrite::Rename).unwrap();
+        assert_eq!(
+            (report.target, report.status),
+            (dir.join("icon_1.ico"), Status::Renamed)
+        );
+        assert_eq!(write(Overwrite::Force).unwrap().status, Status::Overwritten);
+        assert_eq!(fs::read(&target).unwrap(), b"new");
+        // Only the finished outputs are left.
+        let mut names = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["icon.ico", "icon_1.ico"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_bytes_adds_the_hash_and_sidecar() {
+        let dir = scratch_dir("write-bytes-sidecar");
+        let target = dir.join("out.png");
+        let data = png(&DynamicImage::ImageRgb8(RgbImage::new(6, 4)));
+        let options = Options {
+            hash: true,
+            sidecar: true,
+            ..Options::default()
+        };
+        let report = write_bytes(&data, &target, None, Some(OutputFormat::Png), &options).unwrap();
+        assert_eq!(report.sha256, Some(sha256_hex(&data)));
+        assert_eq!((report.width, report.height), (6, 4));
+        let sidecar: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.join("out.png.json")).unwrap()).unwrap();
+        assert_eq!(sidecar["format"], "png");
+        assert_eq!(sidecar["width"], 6);
+        // The sidecar's temporary file is gone too.
+        let mut names = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["out.png", "out.png.json"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rename_policy_numbers_repeated_runs() {
+        let dir = scratch_dir("rename-runs");
+        let source = dir.join("photo.png");
+        fs::write(&source, png(&DynamicImage::ImageRgb8(RgbImage::new(8, 8)))).unwrap();
+        let options = Options {
+            overwrite: Overwrite::Rename,
+            ..Options::default()
+        };
+        let targets = (0..3)
+            .map(|_| process(&source, &dir, &options).unwrap())
+            .map(|report| (report.target, report.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            [
+                (dir.join("resized_photo.png"), Status::Written),
+                (dir.join("resized_photo_1.png"), Status::Renamed),
+                (dir.join("resized_photo_2.png"), Status::Renamed),
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn timed_batch_runs_on_the_shared_pool() {
+        let dir = scratch_dir("timeout-pool");
+        let paths = (0..4)
+            .map(|i| {
+                let path = dir.join(format!("{}.png", i));
+                fs::write(&path, png(&DynamicImage::ImageRgb8(RgbImage::new(8, 8)))).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let jobs = paths.iter().cloned().map(Job::new).collect::<Vec<_>>();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let options = Options {
+            timeout: Some(Duration::from_secs(60)),
+            thread_pool: Some(Arc::new(pool)),
+            ..Options::default()
+        };
+        let events = std::sync::Mutex::new(Vec::new());
+        let results = process_batch_with(&jobs, &[options], |progress| {
+            events
+                .lock()
+                .unwrap()
+                .push(matches!(progress, Progress::Started(_)));
+        });
+        let targets = results
+            .into_iter()
+            .map(|result| result.unwrap().remove(0).target)
+            .collect::<Vec<_>>();
+        let expected = (0..4)
+            .map(|i| dir.join(format!("resized_{}.png", i)))
+            .collect::<Vec<_>>();
+        assert_eq!(targets, expected);
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.iter().filter(|&&started| started).count(), 4);
+        assert_eq!(events.len(), 8);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn timed_batch_gives_up_on_a_slow_file() {
+        let dir = scratch_dir("timeout-slow");
+        let path = dir.join("big.png");
+        let img = RgbImage::from_fn(1000, 1000, |x, y| Rgb([x as u8, y as u8, 0]));
+        fs::write(&path, png(&DynamicImage::ImageRgb8(img))).unwrap();
+        // Nothing is written, so the abandoned work can't race the cleanup.
+        let options = Options {
+            timeout: Some(Duration::from_millis(1)),
+            in_memory: true,
+            ..Options::default()
+        };
+        let mut results = process_batch(&[path], &options);
+        assert_eq!(results.remove(0).err().unwrap().kind(), "timeout");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn quarter_rotation_swaps_the_output_dimensions() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(40, 20));
+        for (rotation, dimensions) in [
+            (Rotation::R90, (5, 10)),
+            (Rotation::R180, (10, 5)),
+            (Rotation::R270, (5, 10)),
+        ] {
+            let options = Options {
+                target_size: 10,
+                rotate: Some(rotation),
+                ..Options::default()
+            };
+            let resized = resized_png("rotated.png", &img, &options);
+            assert_eq!(
+                (resized.width, resized.height),
+                dimensions,
+                "{:?}",
+                rotation
+            );
+        }
+    }
+}
@@ -1,127 +1,2779 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
-use std::fs::{self, File};
-use std::io::{self, BufWriter, Read, Write};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Cursor, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use image::imageops::FilterType;
-use image::{self};
-use mozjpeg::{ColorSpace, Compress, ScanMode};
-use rayon::prelude::*;
+use filetime::FileTime;
+use ignore::gitignore::Gitignore;
+use indicatif::ProgressBar;
+use log::LevelFilter;
+use resize_image::{
+    Error, FilterType, FitEdge, Flip, IoLimit, Job, JpegColorSpace, MemoryLimit, NameTemplate,
+    Options, OutputFormat, Overwrite, Progress, Report, ResizePolicy, Rotation, SizeBuckets, Stage,
+    Status, Subsampling, TiffCompression, WatermarkPosition,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
 
-const TARGET_SIZE: usize = 1280;
+/// Read from the current directory when `--config` isn't given.
+const CONFIG_FILE: &str = "resize-image.toml";
+/// Created next to a directory input for its outputs when `--out` isn't
+/// given.
+const RESIZED_DIR: &str = "resized";
+/// Holds the outputs of `--exif-date-subfolder` without a capture date.
+const UNDATED_DIR: &str = "undated";
+/// Lists, in gitignore syntax, the paths under a directory input that are
+/// left alone.
+const IGNORE_FILE: &str = ".resizeignore";
+/// Where `--incremental` keeps its state when `--manifest` isn't given.
+const MANIFEST_FILE: &str = "resize-image-manifest.json";
+/// Replaces the prefix of the outputs `--thumb` adds.
+const THUMB_PREFIX: &str = "thumb_";
+/// Target sizes `--preset` knows without a config file.
+const BUILTIN_PRESETS: &[(&str, usize)] = &[("thumb", 256), ("medium", 1024), ("large", 1920)];
+/// Encoder settings `--preset-quality` knows without a config file.
+const BUILTIN_QUALITY_PRESETS: &[(&str, QualityPreset)] = &[
+    (
+        "web",
+        QualityPreset {
+            quality: 75.0,
+            subsampling: 420,
+            progressive: true,
+        },
+    ),
+    (
+        "print",
+        QualityPreset {
+            quality: 88.0,
+            subsampling: 444,
+            progressive: true,
+        },
+    ),
+    (
+        "archive",
+        QualityPreset {
+            quality: 92.0,
+            subsampling: 444,
+            progressive: false,
+        },
+    ),
+];
+/// Flags that each decide the output dimensions on their own.
+const SIZING_FLAGS: &[&str] = &[
+    "-s",
+    "--size",
+    "--long-edge",
+    "--short-edge",
+    "--scale",
+    "--max-megapixels",
+    "--max-width",
+    "--max-height",
+    "--fit",
+    "--match",
+    "--crop",
+    "--preset",
+    "--sizes",
+];
+
+struct Args {
+    options: Options,
+    /// Where outputs are written. Defaults to alongside each source file, and
+    /// for a directory input to a `resized` directory next to it.
+    out: Option<PathBuf>,
+    /// Collects every output into this archive instead of writing files.
+    zip: Option<PathBuf>,
+    /// Also tiles a thumbnail of every output into this one image.
+    contact_sheet: Option<PathBuf>,
+    contact_columns: u32,
+    /// Side of the square each thumbnail is fitted into, in pixels.
+    contact_cell: u32,
+    /// Turns the one input into a multi-resolution icon file here instead.
+    ico: Option<PathBuf>,
+    ico_sizes: Vec<u32>,
+    recursive: bool,
+    /// Named target sizes that `--preset` can pick from.
+    preset_sizes: BTreeMap<String, usize>,
+    /// The sizes chosen with `--preset` or `--sizes`, each labeled with the
+    /// name that goes into its output's file name.
+    presets: Vec<(String, usize)>,
+    /// Also writes a `thumb_` copy of every output, fitted to this size.
+    thumb: Option<usize>,
+    /// Named encoder settings that `--preset-quality` can pick from.
+    quality_presets: BTreeMap<String, QualityPreset>,
+    /// Writes every output of a recursive walk into one directory instead of
+    /// mirroring the source tree.
+    flatten: bool,
+    /// Sorts outputs into `YYYY/MM/DD` folders by their EXIF capture date,
+    /// or `undated` without one, in place of the mirrored source tree.
+    exif_date_subfolder: bool,
+    /// Writes each page of a multi-page TIFF as a numbered file of its own
+    /// instead of one multi-page output.
+    split_pages: bool,
+    /// Worker thread count; 0 uses one per core.
+    threads: usize,
+    /// Processes only the first this many inputs.
+    limit: Option<usize>,
+    no_pause: bool,
+    /// Prints a JSON array of results instead of one line per file.
+    json: bool,
+    /// Prints how long decoding, resizing and encoding took across the batch.
+    stats: bool,
+    /// Sorts the inputs and logs results in that order once the batch is
+    /// done, instead of as files finish.
+    deterministic: bool,
+    /// Names the outputs `0001`, `0002` and so on in sorted input order, as
+    /// frames for assembling a video.
+    number: bool,
+    /// Skips sources that are unchanged since the run recorded in the
+    /// manifest and whose outputs still exist.
+    incremental: bool,
+    /// Where `--incremental` keeps its state. Defaults to [`MANIFEST_FILE`]
+    /// in the output directory, or the current one.
+    manifest: Option<PathBuf>,
+    /// `Info` prints a line per file, `Error` only failures and `Debug` adds
+    /// timing and sizes.
+    log_level: LevelFilter,
+    paths: Vec<OsString>,
+}
+
+/// A quality, chroma subsampling and scan mode applied together.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct QualityPreset {
+    quality: f32,
+    /// 420, 422 or 444, as for `--subsampling`.
+    subsampling: u32,
+    progressive: bool,
+}
+
+/// Default settings from a config file. Each key is named after the command
+/// line flag it stands for, e.g. `max-width = 1920` for `--max-width 1920`.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Config {
+    size: Option<usize>,
+    long_edge: Option<usize>,
+    short_edge: Option<usize>,
+    scale: Option<f32>,
+    max_megapixels: Option<f64>,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+    min_width: Option<u32>,
+    min_height: Option<u32>,
+    max_pixels: Option<u64>,
+    round_dimensions: Option<u32>,
+    fit: Option<String>,
+    #[serde(rename = "match")]
+    match_reference: Option<PathBuf>,
+    crop: Option<String>,
+    preset: Option<Vec<String>>,
+    sizes: Option<Vec<usize>>,
+    thumb: Option<usize>,
+    /// Adds to or overrides the built-in presets, e.g. `thumb = 200`.
+    presets: Option<BTreeMap<String, usize>>,
+    preset_quality: Option<String>,
+    /// Adds to or overrides the built-in quality presets, e.g.
+    /// `[quality-presets.web] quality = 70, subsampling = 420, progressive = true`.
+    quality_presets: Option<BTreeMap<String, QualityPreset>>,
+    resize_policy: Option<String>,
+    fit_edge: Option<String>,
+    allow_upscale: Option<bool>,
+    quality: Option<f32>,
+    jpeg_quality: Option<f32>,
+    jpeg_input_quality: Option<f32>,
+    webp_quality: Option<f32>,
+    avif_quality: Option<f32>,
+    max_bytes: Option<usize>,
+    min_quality: Option<f32>,
+    min_ssim: Option<f64>,
+    keep_jpeg_on_gain: Option<bool>,
+    smallest: Option<bool>,
+    quality_range: Option<String>,
+    auto_quality: Option<bool>,
+    filter: Option<String>,
+    sharpen: Option<f32>,
+    rotate: Option<u32>,
+    flip: Option<String>,
+    watermark: Option<PathBuf>,
+    watermark_pos: Option<String>,
+    watermark_opacity: Option<f32>,
+    grayscale: Option<bool>,
+    background: Option<String>,
+    subsampling: Option<u32>,
+    colorspace: Option<String>,
+    /// `false` selects `--baseline`.
+    progressive: Option<bool>,
+    /// `false` selects `--no-optimize-coding`.
+    optimize_coding: Option<bool>,
+    /// `false` selects `--no-autorotate`.
+    autorotate: Option<bool>,
+    smoothing: Option<u8>,
+    webp_lossless: Option<bool>,
+    dither: Option<bool>,
+    png_level: Option<u8>,
+    png_interlace: Option<bool>,
+    tiff_compression: Option<String>,
+    strip: Option<bool>,
+    strip_icc: Option<bool>,
+    assume_srgb: Option<bool>,
+    keep_exif: Option<bool>,
+    keep_gps: Option<bool>,
+    strip_to_baseline: Option<bool>,
+    dpi: Option<u16>,
+    skip_smaller: Option<bool>,
+    format: Option<String>,
+    input_format: Option<String>,
+    also: Option<String>,
+    prefix: Option<String>,
+    rename_template: Option<String>,
+    bucket_by_size: Option<String>,
+    content_name: Option<bool>,
+    suffix: Option<String>,
+    name_dimensions: Option<bool>,
+    sidecar: Option<bool>,
+    post_cmd: Option<String>,
+    out: Option<PathBuf>,
+    zip: Option<PathBuf>,
+    contact_sheet: Option<PathBuf>,
+    contact_columns: Option<u32>,
+    contact_cell: Option<u32>,
+    ico: Option<PathBuf>,
+    ico_sizes: Option<String>,
+    no_clobber: Option<bool>,
+    force: Option<bool>,
+    suffix_on_conflict: Option<String>,
+    dry_run: Option<bool>,
+    estimate: Option<bool>,
+    validate: Option<bool>,
+    delete_invalid: Option<bool>,
+    recursive: Option<bool>,
+    copy_unsupported: Option<bool>,
+    keep_structure: Option<bool>,
+    flatten: Option<bool>,
+    split_pages: Option<bool>,
+    exif_date_subfolder: Option<bool>,
+    threads: Option<usize>,
+    threads_per_image: Option<usize>,
+    limit: Option<usize>,
+    retries: Option<u32>,
+    timeout: Option<f64>,
+    io_threads: Option<usize>,
+    memory_limit: Option<u64>,
+    no_pause: Option<bool>,
+    from_file: Option<String>,
+    json: Option<bool>,
+    stats: Option<bool>,
+    deterministic: Option<bool>,
+    number: Option<bool>,
+    incremental: Option<bool>,
+    manifest: Option<PathBuf>,
+    hash: Option<bool>,
+    compare: Option<bool>,
+    preserve_timestamps: Option<bool>,
+    chmod: Option<String>,
+    verbose: Option<bool>,
+    quiet: Option<bool>,
+}
+
+impl Config {
+    fn load(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("invalid config {}: {}", path.display(), e))
+    }
+
+    /// Spells the settings out as flags, so that they are validated exactly
+    /// like the command line.
+    fn into_flags(self) -> Vec<String> {
+        let values = [
+            ("--size", self.size.map(|v| v.to_string())),
+            ("--long-edge", self.long_edge.map(|v| v.to_string())),
+            ("--short-edge", self.short_edge.map(|v| v.to_string())),
+            ("--scale", self.scale.map(|v| v.to_string())),
+            (
+                "--max-megapixels",
+                self.max_megapixels.map(|v| v.to_string()),
+            ),
+            ("--max-width", self.max_width.map(|v| v.to_string())),
+            ("--max-height", self.max_height.map(|v| v.to_string())),
+            ("--min-width", self.min_width.map(|v| v.to_string())),
+            ("--min-height", self.min_height.map(|v| v.to_string())),
+            ("--max-pixels", self.max_pixels.map(|v| v.to_string())),
+            (
+                "--round-dimensions",
+                self.round_dimensions.map(|v| v.to_string()),
+            ),
+            ("--fit", self.fit),
+            (
+                "--match",
+                self.match_reference
+                    .map(|v| v.to_string_lossy().into_owned()),
+            ),
+            ("--crop", self.crop),
+            ("--resize-policy", self.resize_policy),
+            ("--fit-edge", self.fit_edge),
+            // Before the settings it stands for, so that those override it.
+            ("--preset-quality", self.preset_quality),
+            ("--thumb", self.thumb.map(|v| v.to_string())),
+            ("--quality", self.quality.map(|v| v.to_string())),
+            ("--jpeg-quality", self.jpeg_quality.map(|v| v.to_string())),
+            (
+                "--jpeg-input-quality",
+                self.jpeg_input_quality.map(|v| v.to_string()),
+            ),
+            ("--webp-quality", self.webp_quality.map(|v| v.to_string())),
+            ("--avif-quality", self.avif_quality.map(|v| v.to_string())),
+            ("--max-bytes", self.max_bytes.map(|v| v.to_string())),
+            ("--min-quality", self.min_quality.map(|v| v.to_string())),
+            ("--min-ssim", self.min_ssim.map(|v| v.to_string())),
+            ("--quality-range", self.quality_range),
+            ("--filter", self.filter),
+            ("--sharpen", self.sharpen.map(|v| v.to_string())),
+            ("--rotate", self.rotate.map(|v| v.to_string())),
+            ("--flip", self.flip),
+            (
+                "--watermark",
+                self.watermark.map(|v| v.to_string_lossy().into_owned()),
+            ),
+            ("--watermark-pos", self.watermark_pos),
+            (
+                "--watermark-opacity",
+                self.watermark_opacity.map(|v| v.to_string()),
+            ),
+            ("--background", self.background),
+            ("--subsampling", self.subsampling.map(|v| v.to_string())),
+            ("--colorspace", self.colorspace),
+            ("--format", self.format),
+            ("--input-format", self.input_format),
+            ("--also", self.also),
+            ("--prefix", self.prefix),
+            ("--suffix", self.suffix),
+            ("--rename-template", self.rename_template),
+            ("--bucket-by-size", self.bucket_by_size),
+            ("--chmod", self.chmod),
+            ("--post-cmd", self.post_cmd),
+            ("--out", self.out.map(|v| v.to_string_lossy().into_owned())),
+            ("--zip", self.zip.map(|v| v.to_string_lossy().into_owned())),
+            (
+                "--contact-sheet",
+                self.contact_sheet.map(|v| v.to_string_lossy().into_owned()),
+            ),
+            (
+                "--contact-columns",
+                self.contact_columns.map(|v| v.to_string()),
+            ),
+            ("--contact-cell", self.contact_cell.map(|v| v.to_string())),
+            ("--ico", self.ico.map(|v| v.to_string_lossy().into_owned())),
+            ("--ico-sizes", self.ico_sizes),
+            (
+                "--manifest",
+                self.manifest.map(|v| v.to_string_lossy().into_owned()),
+            ),
+            ("--threads", self.threads.map(|v| v.to_string())),
+            (
+                "--threads-per-image",
+                self.threads_per_image.map(|v| v.to_string()),
+            ),
+            ("--limit", self.limit.map(|v| v.to_string())),
+            ("--retries", self.retries.map(|v| v.to_string())),
+            ("--timeout", self.timeout.map(|v| v.to_string())),
+            ("--io-threads", self.io_threads.map(|v| v.to_string())),
+            ("--memory-limit", self.memory_limit.map(|v| v.to_string())),
+            ("--png-level", self.png_level.map(|v| v.to_string())),
+            ("--smoothing", self.smoothing.map(|v| v.to_string())),
+            ("--tiff-compression", self.tiff_compression),
+            ("--suffix-on-conflict", self.suffix_on_conflict),
+            ("--dpi", self.dpi.map(|v| v.to_string())),
+            ("--from-file", self.from_file),
+        ];
+        let switches = [
+            ("--allow-upscale", self.allow_upscale),
+            ("--grayscale", self.grayscale),
+            ("--progressive", self.progressive),
+            ("--baseline", self.progressive.map(|v| !v)),
+            ("--optimize-coding", self.optimize_coding),
+            ("--no-optimize-coding", self.optimize_coding.map(|v| !v)),
+            ("--autorotate", self.autorotate),
+            ("--no-autorotate", self.autorotate.map(|v| !v)),
+            ("--webp-lossless", self.webp_lossless),
+            ("--dither", self.dither),
+            ("--png-interlace", self.png_interlace),
+            ("--auto-quality", self.auto_quality),
+            ("--strip", self.strip),
+            ("--strip-icc", self.strip_icc),
+            ("--assume-srgb", self.assume_srgb),
+            ("--keep-exif", self.keep_exif),
+            ("--keep-gps", self.keep_gps),
+            ("--strip-to-baseline", self.strip_to_baseline),
+            ("--skip-smaller", self.skip_smaller),
+            ("--name-dimensions", self.name_dimensions),
+            ("--sidecar", self.sidecar),
+            ("--content-name", self.content_name),
+            ("--no-clobber", self.no_clobber),
+            ("--force", self.force),
+            ("--dry-run", self.dry_run),
+            ("--estimate", self.estimate),
+            ("--keep-jpeg-on-gain", self.keep_jpeg_on_gain),
+            ("--smallest", self.smallest),
+            ("--validate", self.validate),
+            ("--delete-invalid", self.delete_invalid),
+            ("--recursive", self.recursive),
+            ("--copy-unsupported", self.copy_unsupported),
+            ("--keep-structure", self.keep_structure),
+            ("--flatten", self.flatten),
+            ("--split-pages", self.split_pages),
+            ("--exif-date-subfolder", self.exif_date_subfolder),
+            ("--no-pause", self.no_pause),
+            ("--json", self.json),
+            ("--stats", self.stats),
+            ("--deterministic", self.deterministic),
+            ("--number", self.number),
+            ("--incremental", self.incremental),
+            ("--hash", self.hash),
+            ("--compare", self.compare),
+            ("--preserve-timestamps", self.preserve_timestamps),
+            ("--verbose", self.verbose),
+            ("--quiet", self.quiet),
+        ];
+
+        let mut flags = Vec::new();
+        for (flag, value) in values {
+            if let Some(value) = value {
+                flags.push(flag.to_string());
+                flags.push(value);
+            }
+        }
+        for (flag, on) in switches {
+            if on == Some(true) {
+                flags.push(flag.to_string());
+            }
+        }
+        for name in self.preset.unwrap_or_default() {
+            flags.push("--preset".to_string());
+            flags.push(name);
+        }
+        if let Some(sizes) = self.sizes {
+            let sizes = sizes.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            flags.push("--sizes".to_string());
+            flags.push(sizes.join(","));
+        }
+        flags
+    }
+}
 
 fn main() {
-    let target_dir = match env::args().nth(1) {
-        Some(v) => PathBuf::from(v).parent().unwrap().join(""),
-        None => return,
+    let mut raw = env::args_os().skip(1).peekable();
+    if raw.next_if(|a| a == "info").is_some() {
+        process::exit(info(raw));
+    }
+    let mut args = match parse_args(raw) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        }
     };
-    if !target_dir.exists() {
-        fs::create_dir(&target_dir).unwrap();
+
+    init_logging(&args);
+
+    if args.paths.is_empty() {
+        return;
+    }
+    let started = Instant::now();
+    let writes = !args.options.dry_run && !args.options.estimate;
+    if let (Some(out), true) = (&args.out, writes) {
+        if let Err(e) = fs::create_dir_all(out) {
+            eprintln!("error: cannot create {}: {}", out.display(), e);
+            process::exit(2);
+        }
     }
 
-    let source_files = env::args()
-        .skip(1)
-        .map(PathBuf::from)
-        .filter(|p| p.is_file() && p.file_name().is_some() && p.extension().is_some())
-        .collect::<Vec<PathBuf>>();
+    if args.incremental && args.zip.is_some() {
+        eprintln!("error: --incremental cannot be combined with --zip");
+        process::exit(2);
+    }
+    // Skipping unchanged sources would shift the numbers of the rest.
+    if args.incremental && args.number {
+        eprintln!("error: --incremental cannot be combined with --number");
+        process::exit(2);
+    }
+    if let Some(ico) = &args.ico {
+        let [input] = args.paths.as_slice() else {
+            eprintln!("error: --ico takes exactly one input");
+            process::exit(2);
+        };
+        if let Err(e) = write_ico(Path::new(input), ico, &args) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    // Every output of a file would get the same name.
+    if args.options.name_template.is_some() && (args.presets.len() > 1 || args.thumb.is_some()) {
+        eprintln!("error: --rename-template cannot be combined with several sizes or --thumb");
+        process::exit(2);
+    }
+    if args.paths.iter().any(|p| p == "-") {
+        if args.paths.len() > 1 {
+            eprintln!("error: - cannot be combined with other inputs");
+            process::exit(2);
+        }
+        if args.zip.is_some() {
+            eprintln!("error: - cannot be combined with --zip");
+            process::exit(2);
+        }
+        if args.thumb.is_some() {
+            eprintln!("error: - cannot be combined with --thumb");
+            process::exit(2);
+        }
+        let options = match args.presets.as_slice() {
+            [] => args.options.clone(),
+            [(_, size)] => Options {
+                target_size: *size,
+                ..args.options.clone()
+            },
+            _ => {
+                eprintln!("error: - cannot be combined with several sizes");
+                process::exit(2);
+            }
+        };
+        if let Err(e) = process_stdin(&options) {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    if args.threads > 0 {
+        if let Err(e) = rayon::ThreadPoolBuilder::new()
+            .num_threads(args.threads)
+            .build_global()
+        {
+            eprintln!("error: cannot start {} threads: {}", args.threads, e);
+            process::exit(2);
+        }
+    }
 
-    source_files
-        .par_iter()
-        .for_each(|path| match process(&path, &target_dir) {
-            Ok(file_name) => println!("{} is compressed.", file_name),
-            Err((file_name, err)) => println!("{} FAILED due to {}", file_name, err),
-        });
-    pause();
-}
+    let (urls, paths) = std::mem::take(&mut args.paths)
+        .into_iter()
+        .partition::<Vec<_>, _>(|p| p.to_str().is_some_and(resize_image::is_url));
+    args.paths = paths;
+    if !urls.is_empty() && args.zip.is_some() {
+        eprintln!("error: a URL cannot be combined with --zip");
+        process::exit(2);
+    }
+    let url_failures = process_urls(&urls, &args);
+    if args.paths.is_empty() {
+        if url_failures > 0 {
+            process::exit(url_failures.min(255) as i32);
+        }
+        return;
+    }
 
-fn process(path: &Path, target_dir: &Path) -> Result<String, (String, String)> {
-    let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+    // Archive entries are laid out as an output directory would be, with the
+    // archive's root in its place.
+    if args.zip.is_some() {
+        args.out = Some(PathBuf::new());
+        args.options.in_memory = true;
+    }
+    if args.contact_sheet.is_some() {
+        args.options.thumbnail_size = Some(args.contact_cell);
+    }
+    // The first Ctrl-C lets the files in progress finish, and their outputs
+    // are only moved into place once complete. A second one quits at once.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    args.options.cancel = Some(interrupted.clone());
+    let flag = interrupted.clone();
+    let handler = ctrlc::set_handler(move || {
+        if flag.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+        log::warn!("Interrupted: finishing the files in progress. Press Ctrl-C again to quit now.");
+    });
+    if let Err(e) = handler {
+        log::warn!("Ctrl-C stops at once: {}", e);
+    }
+    let mut source_files = collect_sources(&args);
+    let manifest_path = args
+        .manifest
+        .clone()
+        .unwrap_or_else(|| args.out.clone().unwrap_or_default().join(MANIFEST_FILE));
+    let mut manifest = Manifest::new();
+    if args.incremental {
+        manifest = match read_manifest(&manifest_path) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(2);
+            }
+        };
+        let count = source_files.len();
+        source_files.retain(|job| !is_unchanged(&job.source, &manifest));
+        if source_files.len() < count {
+            log::info!(
+                "{} unchanged files are skipped.",
+                count - source_files.len()
+            );
+        }
+    }
+    if let Some(limit) = args.limit.filter(|&limit| limit < source_files.len()) {
+        let skipped = source_files.len() - limit;
+        source_files.truncate(limit);
+        log::info!("{} files are skipped by --limit.", skipped);
+    }
+    for (i, job) in source_files.iter_mut().enumerate() {
+        job.index = Some(i + 1);
+    }
+    if args.number {
+        number_jobs(&mut source_files);
+    }
+    let runs = preset_runs(&args);
 
-    let (resized_img_data, target_width, target_height) = match resize(path) {
-        Ok(v) => v,
-        Err(e) => return Err((file_name, e.to_string())),
+    // The bar draws to stderr, so per-file lines go through it to avoid
+    // tearing. Without a terminal the plain line-per-file output is kept.
+    let progress = if io::stderr().is_terminal() {
+        ProgressBar::new(source_files.len() as u64)
+    } else {
+        ProgressBar::hidden()
     };
 
-    let compressed_img_data = match compress(resized_img_data, target_width, target_height) {
-        Ok(v) => v,
-        Err(e) => return Err((file_name, e.to_string())),
+    let labels = runs.iter().map(|(label, _)| *label).collect::<Vec<_>>();
+    let variants = runs
+        .into_iter()
+        .map(|(_, options)| options)
+        .collect::<Vec<_>>();
+    let log_result = |job: &Job, result: &Result<Vec<Report>, Error>, elapsed: Duration| {
+        let file_name = job
+            .source
+            .file_name()
+            .unwrap_or(job.source.as_os_str())
+            .to_string_lossy();
+        progress.suspend(|| match result {
+            Ok(reports) => {
+                // Every run writes as many outputs, one more with `--also`,
+                // which are then told apart by their file names.
+                let per_run = (reports.len() / labels.len()).max(1);
+                for (i, report) in reports.iter().enumerate() {
+                    let name = match labels.get(i / per_run).copied().flatten() {
+                        Some(label) => format!("{} ({})", file_name, label),
+                        None => file_name.to_string(),
+                    };
+                    let name = match report.target.file_name() {
+                        Some(target) if per_run > 1 => {
+                            format!("{} as {}", name, target.to_string_lossy())
+                        }
+                        _ => name,
+                    };
+                    log::info!("{}", message(&name, Ok(report), &args));
+                    log::debug!("{}", details(&name, report, elapsed));
+                    if let (true, Some(format)) = (report.kept_source_format, args.options.format) {
+                        log::warn!(
+                            "{} would be larger as {}, so it is kept in its own format.",
+                            name,
+                            format.name().to_uppercase()
+                        );
+                    }
+                    match (report.comparison, args.options.min_ssim) {
+                        (Some(comparison), Some(min_ssim)) => {
+                            log::info!(
+                                "{}: SSIM {:.4}, PSNR {:.2} dB at quality {}.",
+                                name,
+                                comparison.ssim,
+                                comparison.psnr,
+                                report.quality
+                            );
+                            if comparison.ssim < min_ssim {
+                                log::warn!(
+                                    "{} stays below SSIM {} even at quality {}.",
+                                    name,
+                                    min_ssim,
+                                    report.quality
+                                );
+                            }
+                        }
+                        (Some(comparison), None) => log::info!(
+                            "{}: SSIM {:.4}, PSNR {:.2} dB.",
+                            name,
+                            comparison.ssim,
+                            comparison.psnr
+                        ),
+                        (None, _) => {}
+                    }
+                    match report.post_command_status {
+                        Some(status) if !status.success() => {
+                            log::warn!("{}: the post-command failed ({}).", name, status)
+                        }
+                        Some(status) => {
+                            log::debug!("{}: the post-command finished ({}).", name, status)
+                        }
+                        None => {}
+                    }
+                    if let (true, Some(ext)) = (
+                        args.options.smallest && report.bytes > 0,
+                        report.target.extension(),
+                    ) {
+                        log::info!(
+                            "{} is smallest as {}.",
+                            name,
+                            ext.to_string_lossy().to_uppercase()
+                        );
+                    }
+                    if report.snapped {
+                        log::info!("{} is snapped to {}x{}.", name, report.width, report.height);
+                    }
+                    if report.over_budget {
+                        log::warn!(
+                            "{} does not fit in {} bytes even at quality {}.",
+                            name,
+                            args.options.max_bytes.unwrap_or_default(),
+                            report.quality
+                        );
+                    }
+                }
+                if reports.len() > 1 {
+                    let written = reports.iter().filter(|r| r.bytes > 0).count();
+                    log::info!("{} produced {} outputs.", file_name, written);
+                }
+            }
+            // Summed up once the batch stops.
+            Err(Error::Cancelled) => {}
+            Err(err) => log::error!("{}", message(&file_name, Err(err), &args)),
+        });
     };
+    // Keyed by the job's address, since the same file may be given twice.
+    let elapsed_by_job = Mutex::new(HashMap::new());
+    let results = resize_image::process_batch_with(&source_files, &variants, |event| {
+        let Progress::Finished(job, result, elapsed) = event else {
+            return;
+        };
+        if args.json {
+        } else if args.deterministic {
+            let key = job as *const Job as usize;
+            elapsed_by_job.lock().unwrap().insert(key, elapsed);
+        } else {
+            log_result(job, result, elapsed);
+        }
+        progress.inc(1);
+    });
+    if args.deterministic && !args.json {
+        let elapsed_by_job = elapsed_by_job.into_inner().unwrap();
+        for (job, result) in source_files.iter().zip(&results) {
+            let key = job as *const Job as usize;
+            log_result(job, result, elapsed_by_job[&key]);
+        }
+    }
+    let mut failures = url_failures + results.iter().filter(|r| r.is_err()).count();
+    if let (Some(path), true) = (&args.zip, writes) {
+        match write_zip(path, &results, &args.options) {
+            Ok(failed) => failures += failed,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let (Some(path), true) = (&args.contact_sheet, writes) {
+        if let Err(e) = write_contact_sheet(path, &results, &args) {
+            log::error!("{}", e);
+            failures += 1;
+        }
+    }
 
-    let target_file = target_dir.join("resized_".to_string() + &file_name);
-    let mut file =
-        BufWriter::new(File::create(target_file).map_err(|e| (file_name.clone(), e.to_string()))?);
-    file.write_all(&compressed_img_data)
-        .map_err(|e| (file_name.clone(), e.to_string()))?;
+    if args.incremental && writes {
+        update_manifest(&mut manifest, &source_files, &results);
+        if let Err(e) = write_manifest(&manifest_path, &manifest) {
+            log::error!("{}", e);
+            failures += 1;
+        }
+    }
 
-    Ok(file_name)
+    if args.json {
+        progress.finish_and_clear();
+        let mut entries = Vec::new();
+        for (job, result) in source_files.iter().zip(&results) {
+            match result {
+                Ok(reports) => {
+                    entries.extend(reports.iter().map(|r| json_entry(&job.source, Ok(r))))
+                }
+                Err(err) => entries.push(json_entry(&job.source, Err(err))),
+            }
+        }
+        println!("{}", serde_json::Value::Array(entries));
+    } else {
+        progress.finish_and_clear();
+    }
+    if !args.json {
+        // Every source counts once, however many outputs it produced.
+        let (original, compressed) = results
+            .iter()
+            .flatten()
+            .filter(|reports| reports.iter().any(|r| r.bytes > 0))
+            .fold((0, 0), |(o, c), reports| {
+                let bytes = reports.iter().map(|r| r.bytes as u64).sum::<u64>();
+                (o + reports[0].original_bytes, c + bytes)
+            });
+        if compressed > 0 && args.options.estimate {
+            log::info!("Estimated total: {}.", savings(original, compressed));
+        } else if compressed > 0 {
+            log::info!("Total: {}.", savings(original, compressed));
+        }
+        let failed = results.iter().filter(|r| r.is_err()).count();
+        let mut footer = format!(
+            "Processed {} files ({} ok, {} failed) in {:.1}s",
+            results.len(),
+            results.len() - failed,
+            failed,
+            started.elapsed().as_secs_f64()
+        );
+        if compressed > 0 {
+            let verb = if args.options.estimate {
+                "would save"
+            } else {
+                "saved"
+            };
+            footer = match original.checked_sub(compressed) {
+                Some(saved) => format!("{}, {} {}", footer, verb, format_bytes(saved)),
+                None => format!(
+                    "{}, grew by {}",
+                    footer,
+                    format_bytes(compressed - original)
+                ),
+            };
+        }
+        log::info!("{}.", footer);
+    }
+
+    if args.stats {
+        let timings = results
+            .iter()
+            .flatten()
+            .flatten()
+            .map(|r| r.timings)
+            .collect::<Vec<_>>();
+        log::info!("Stage timings over {} outputs:", timings.len());
+        log::info!(
+            "{}",
+            stage_stats("decode", timings.iter().map(|t| t.decode))
+        );
+        log::info!(
+            "{}",
+            stage_stats("resize", timings.iter().map(|t| t.resize))
+        );
+        log::info!(
+            "{}",
+            stage_stats("encode", timings.iter().map(|t| t.encode))
+        );
+    }
+
+    if interrupted.load(Ordering::SeqCst) {
+        let not_started = results
+            .iter()
+            .filter(|r| matches!(r, Err(Error::Cancelled)))
+            .count();
+        log::warn!(
+            "Interrupted: {} files finished, {} were not started.",
+            results.len() - not_started,
+            not_started
+        );
+        process::exit(130);
+    }
+
+    // Keep the console window open when launched by drag-and-drop, but never
+    // block scripts or pipelines waiting for a key press.
+    if !args.no_pause && io::stdin().is_terminal() && io::stdout().is_terminal() {
+        pause();
+    }
+    if failures > 0 {
+        process::exit(failures.min(255) as i32);
+    }
+}
+
+/// The options for each output of a source: one per `--preset` or
+/// `--sizes` entry, with its size and label appended to the suffix, or just
+/// the settings as given otherwise. `--thumb` adds one more, which only
+/// fits the longest edge, whatever decides the size of the others.
+fn preset_runs(args: &Args) -> Vec<(Option<&str>, Options)> {
+    let mut runs = if args.presets.is_empty() {
+        vec![(None, args.options.clone())]
+    } else {
+        args.presets
+            .iter()
+            .map(|(name, size)| {
+                let options = Options {
+                    target_size: *size,
+                    suffix: format!("{}_{}", args.options.suffix, name),
+                    ..args.options.clone()
+                };
+                (Some(name.as_str()), options)
+            })
+            .collect()
+    };
+    if let Some(size) = args.thumb {
+        let options = Options {
+            target_size: size,
+            scale: None,
+            max_megapixels: None,
+            max_width: None,
+            max_height: None,
+            crop: None,
+            fit_edge: FitEdge::Longest,
+            prefix: THUMB_PREFIX.to_string(),
+            ..args.options.clone()
+        };
+        runs.push((Some("thumb"), options));
+    }
+    runs
 }
 
-fn resize(path: &Path) -> Result<(Vec<u8>, usize, usize), String> {
-    let img = image::open(path).map_err(|e| e.to_string())?;
-    let width = img.width() as usize;
-    let height = img.height() as usize;
+/// Writes the icon file for `--ico`, leaving it alone on a dry run.
+fn write_ico(input: &Path, ico: &Path, args: &Args) -> Result<(), String> {
+    let data = fs::read(input).map_err(|e| format!("cannot read {}: {}", input.display(), e))?;
+    let icon = resize_image::process_ico(&data, &args.ico_sizes, &args.options)
+        .map_err(|e| format!("{} FAILED due to {}", input.display(), e))?;
+    let sizes = args
+        .ico_sizes
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if args.options.dry_run || args.options.estimate {
+        log::info!(
+            "{} -> {} ({} bytes, sizes {})",
+            input.display(),
+            ico.display(),
+            icon.len(),
+            sizes
+        );
+        return Ok(());
+    }
+    let report = resize_image::write_bytes(&icon, ico, Some(input), None, &args.options)
+        .map_err(|e| format!("{} FAILED due to {}", input.display(), e))?;
+    if report.status == Status::Skipped {
+        log::info!("{} is skipped: {} exists.", input.display(), ico.display());
+        return Ok(());
+    }
+    log::info!(
+        "{} is converted to {} with sizes {}.",
+        input.display(),
+        report.target.display(),
+        sizes
+    );
+    Ok(())
+}
 
-    if width > TARGET_SIZE || height > TARGET_SIZE {
-        let (target_width, target_height) = if width > height {
-            let ratio: f32 = TARGET_SIZE as f32 / width as f32;
-            (TARGET_SIZE, (height as f32 * ratio) as usize)
-        } else {
-            let ratio: f32 = TARGET_SIZE as f32 / height as f32;
-            ((width as f32 * ratio) as usize, TARGET_SIZE)
+/// Prints what the headers of each file say, for `info`, with `--json` as a
+/// JSON array. Returns the exit code: 1 when a file couldn't be read.
+fn info(raw: impl Iterator<Item = OsString>) -> i32 {
+    let (mut json, mut paths) = (false, Vec::new());
+    for arg in raw {
+        match arg.to_str() {
+            Some("--json") => json = true,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    let mut entries = Vec::new();
+    let mut code = 0;
+    for path in &paths {
+        let info = match resize_image::inspect(path) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("{} FAILED due to {}", path.display(), e);
+                code = 1;
+                continue;
+            }
+        };
+        let (channels, bits) = match info.color {
+            Some(color) => {
+                let channels = match color.channel_count() {
+                    1 => "gray",
+                    2 => "gray with alpha",
+                    3 => "RGB",
+                    _ => "RGBA",
+                };
+                let bits = color.bits_per_pixel() / color.channel_count() as u16;
+                (Some(channels), Some(bits))
+            }
+            None => (None, None),
         };
-        let resized_img = img.resize(
-            target_width as u32,
-            target_height as u32,
-            FilterType::Lanczos3,
+        let format = format!("{:?}", info.format).to_uppercase();
+        if json {
+            entries.push(serde_json::json!({
+                "source": path,
+                "format": format,
+                "width": info.width,
+                "height": info.height,
+                "bit_depth": bits,
+                "channels": channels,
+                "alpha": info.color.map(image::ColorType::has_alpha),
+                "exif": info.has_exif,
+                "orientation": info.orientation,
+                "icc_profile": info.has_icc_profile,
+                "pages": info.pages,
+            }));
+            continue;
+        }
+        let mut fields = vec![format, format!("{}x{}", info.width, info.height)];
+        if let (Some(channels), Some(bits)) = (channels, bits) {
+            fields.push(format!("{}-bit {}", bits, channels));
+        }
+        if info.has_exif {
+            fields.push(format!("EXIF (orientation {})", info.orientation));
+        }
+        if info.has_icc_profile {
+            fields.push("ICC profile".to_string());
+        }
+        if info.pages > 1 {
+            fields.push(format!("{} pages", info.pages));
+        }
+        println!("{}: {}", path.display(), fields.join(", "));
+    }
+    if json {
+        println!("{}", serde_json::Value::Array(entries));
+    }
+    code
+}
+
+/// Downloads each URL and resizes it in memory, writing its outputs to
+/// `--out`, or the current directory, named after the last segment of its
+/// path. Returns how many URLs failed.
+fn process_urls(urls: &[OsString], args: &Args) -> usize {
+    let target_dir = args.out.clone().unwrap_or_default();
+    let mut failures = 0;
+    for url in urls.iter().filter_map(|url| url.to_str()) {
+        if let Err(e) = process_url(url, &target_dir, args) {
+            log::error!("{} FAILED due to {}", url, e);
+            failures += 1;
+        }
+    }
+    failures
+}
+
+fn process_url(url: &str, target_dir: &Path, args: &Args) -> Result<(), Error> {
+    let data = resize_image::fetch(url)?;
+    let name = Path::new(resize_image::url_file_name(url).unwrap_or("image"));
+    let input_format = match args.options.input_format {
+        Some(format) => format,
+        None => image::guess_format(&data)?,
+    };
+    for (_, options) in preset_runs(args) {
+        let format = options
+            .format
+            .unwrap_or_else(|| OutputFormat::for_input(input_format));
+        let target = resize_image::target_path(name, target_dir, format, &options);
+        if target.exists() {
+            match options.overwrite {
+                Overwrite::Skip => {
+                    log::info!("{} is skipped: {} exists.", url, target.display());
+                    continue;
+                }
+                Overwrite::Error => return Err(Error::Exists(target)),
+                _ => {}
+            }
+        }
+        let compressed = resize_image::process_bytes(&data, &options)?;
+        let (original, bytes) = (data.len() as u64, compressed.len() as u64);
+        if options.dry_run || options.estimate {
+            log::info!("{} -> {} ({} bytes)", url, target.display(), bytes);
+            continue;
+        }
+        // The name may have been taken while the image was downloaded. There
+        // is no source file whose modification time could be preserved.
+        let report = resize_image::write_bytes(&compressed, &target, None, Some(format), &options)?;
+        if report.status == Status::Skipped {
+            log::info!("{} is skipped: {} exists.", url, target.display());
+            continue;
+        }
+        let hash = report
+            .sha256
+            .map(|hash| format!(" SHA-256 {}", hash))
+            .unwrap_or_default();
+        log::info!(
+            "{} is compressed to {}: {}.{}",
+            url,
+            report.target.display(),
+            savings(original, bytes),
+            hash
         );
-        Ok((
-            resized_img.to_rgb8().to_vec(),
-            resized_img.width() as usize,
-            resized_img.height() as usize,
-        ))
+    }
+    Ok(())
+}
+
+/// Reads a single image from stdin and writes the result to stdout.
+fn process_stdin(options: &Options) -> Result<(), Error> {
+    let mut data = Vec::new();
+    io::stdin().read_to_end(&mut data)?;
+    let compressed = resize_image::process_bytes(&data, options)?;
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&compressed)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Sends log lines to stdout like the per-file output always was, printing
+/// just the message. With `--json`, stdout is reserved for the document.
+fn init_logging(args: &Args) {
+    let target = if args.json {
+        env_logger::Target::Stderr
     } else {
-        Ok((img.to_rgb8().to_vec(), width, height))
+        env_logger::Target::Stdout
+    };
+    env_logger::Builder::new()
+        .filter_module(env!("CARGO_CRATE_NAME"), args.log_level)
+        .target(target)
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .init();
+}
+
+/// Summarizes one stage for `--stats`. Outputs that skipped the stage, like
+/// the extra outputs of a source that was decoded once, are left out.
+fn stage_stats(stage: &str, times: impl Iterator<Item = Duration>) -> String {
+    let mut times = times.filter(|t| !t.is_zero()).collect::<Vec<_>>();
+    if times.is_empty() {
+        return format!("  {}: not run", stage);
     }
+    times.sort_unstable();
+    let ms = |t: Duration| t.as_secs_f64() * 1000.0;
+    format!(
+        "  {}: min {:.1} ms, median {:.1} ms, max {:.1} ms, total {:.1} ms",
+        stage,
+        ms(times[0]),
+        ms(times[times.len() / 2]),
+        ms(times[times.len() - 1]),
+        ms(times.iter().sum())
+    )
 }
 
-fn compress(
-    resized_img_data: Vec<u8>,
-    target_width: usize,
-    target_height: usize,
-) -> Result<Vec<u8>, String> {
-    let mut comp = Compress::new(ColorSpace::JCS_RGB);
-    comp.set_scan_optimization_mode(ScanMode::AllComponentsTogether);
-    comp.set_quality(70.0);
+/// The `--verbose` line for a processed file.
+fn details(file_name: &str, report: &Report, elapsed: Duration) -> String {
+    let line = format!(
+        "{}: {}x{} -> {}x{} in {} ms",
+        file_name,
+        report.original_width,
+        report.original_height,
+        report.width,
+        report.height,
+        elapsed.as_millis()
+    );
+    let line = match report.orientation {
+        1 => line,
+        n => format!("{} with EXIF orientation {} applied", line, n),
+    };
+    match report.retries {
+        0 => line,
+        1 => format!("{} after 1 retry", line),
+        n => format!("{} after {} retries", line, n),
+    }
+}
 
-    comp.set_size(target_width, target_height);
+/// Describes a size change, e.g. `4.2 MB -> 310.0 KB (93% smaller)`.
+fn savings(original: u64, compressed: u64) -> String {
+    let change = if compressed <= original {
+        format!(
+            "{:.0}% smaller",
+            (original - compressed) as f64 * 100.0 / original.max(1) as f64
+        )
+    } else {
+        format!(
+            "{:.0}% larger",
+            (compressed - original) as f64 * 100.0 / original.max(1) as f64
+        )
+    };
+    format!(
+        "{} -> {} ({})",
+        format_bytes(original),
+        format_bytes(compressed),
+        change
+    )
+}
 
-    comp.set_mem_dest();
-    comp.start_compress();
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
 
-    let mut line = 0;
-    loop {
-        if line > target_height - 1 {
-            break;
+fn message(file_name: &str, result: Result<&Report, &Error>, args: &Args) -> String {
+    let line = match result {
+        Ok(report) if report.status == Status::DryRun && report.width == 0 => {
+            format!("{} -> {}", file_name, report.target.display())
+        }
+        Ok(report) if report.status == Status::DryRun => format!(
+            "{} -> {} ({}x{})",
+            file_name,
+            report.target.display(),
+            report.width,
+            report.height
+        ),
+        Ok(report) if report.status == Status::Estimated => {
+            format!("{} is estimated.", file_name)
+        }
+        Ok(report) if report.status == Status::Skipped => format!(
+            "{} is skipped because {} already exists.",
+            file_name,
+            report.target.display()
+        ),
+        Ok(report) if report.status == Status::TooSmall => format!(
+            "{} is skipped: too small ({}x{}).",
+            file_name, report.original_width, report.original_height
+        ),
+        Ok(report) if report.status == Status::Copied => {
+            format!("{} is copied unchanged.", file_name)
         }
-        comp.write_scanlines(
-            &resized_img_data[line * target_width * 3..(line + 1) * target_width * 3],
+        Ok(report) if report.status == Status::CopiedUnsupported => {
+            format!("{} is copied (unsupported).", file_name)
+        }
+        Ok(report)
+            if report.status == Status::Overwritten
+                && args.options.overwrite == Overwrite::Warn =>
+        {
+            format!(
+                "{} is compressed, overwriting {}.",
+                file_name,
+                report.target.display()
+            )
+        }
+        Ok(report) if report.status == Status::Renamed => format!(
+            "{} is compressed as {}.",
+            file_name,
+            report.target.display()
+        ),
+        Ok(report) if args.options.crop.is_some() => format!(
+            "{} is compressed to {}x{}.",
+            file_name, report.width, report.height
+        ),
+        Ok(report)
+            if args.options.max_bytes.is_some()
+                || args.options.auto_quality
+                || args.options.quality_range.is_some() =>
+        {
+            format!("{} is compressed at quality {}.", file_name, report.quality)
+        }
+        Ok(_) => format!("{} is compressed.", file_name),
+        Err(err) => return format!("{} FAILED due to {}", file_name, err),
+    };
+    let line = match result {
+        Ok(report)
+            if report.bytes > 0
+                && !matches!(report.status, Status::Copied | Status::CopiedUnsupported) =>
+        {
+            format!(
+                "{}: {}.",
+                line.trim_end_matches('.'),
+                savings(report.original_bytes, report.bytes as u64)
+            )
+        }
+        _ => line,
+    };
+    match result {
+        Ok(Report {
+            sha256: Some(hash), ..
+        }) => format!("{} SHA-256 {}", line, hash),
+        _ => line,
+    }
+}
+
+fn json_entry(source: &Path, result: Result<&Report, &Error>) -> serde_json::Value {
+    match result {
+        Ok(report) => serde_json::json!({
+            "source": source,
+            "target": report.target,
+            "status": match report.status {
+                Status::Written => "written",
+                Status::Overwritten => "overwritten",
+                Status::Renamed => "renamed",
+                Status::Skipped => "skipped",
+                Status::DryRun => "dry_run",
+                Status::Estimated => "estimated",
+                Status::Copied => "copied",
+                Status::TooSmall => "too_small",
+                Status::CopiedUnsupported => "copied_unsupported",
+            },
+            "original_width": report.original_width,
+            "original_height": report.original_height,
+            "width": report.width,
+            "height": report.height,
+            "original_bytes": report.original_bytes,
+            "bytes": report.bytes,
+            "quality": report.quality,
+            "sha256": report.sha256,
+            "over_budget": report.over_budget,
+            "snapped": report.snapped,
+            "post_command_status": report.post_command_status.and_then(|s| s.code()),
+            // Identical images have an infinite PSNR, which is written as null.
+            "ssim": report.comparison.map(|c| c.ssim),
+            "psnr": report.comparison.map(|c| c.psnr),
+            "kept_source_format": report.kept_source_format,
+            "success": true,
+        }),
+        Err(err) => serde_json::json!({
+            "source": source,
+            "success": false,
+            "error": err.to_string(),
+            "error_kind": err.kind(),
+            "error_stage": err.stage().map(Stage::name),
+        }),
+    }
+}
+
+/// Builds the settings from the built-in defaults, then the config file, then
+/// the command line, each overriding the last.
+fn parse_args(raw: impl Iterator<Item = OsString>) -> Result<Args, String> {
+    let raw = raw.collect::<Vec<_>>();
+    let config_path = match raw.iter().position(|a| a == "--config") {
+        Some(i) => Some(PathBuf::from(
+            raw.get(i + 1).ok_or("--config requires a value")?,
+        )),
+        None => Some(PathBuf::from(CONFIG_FILE)).filter(|p| p.is_file()),
+    };
+
+    let mut args = Args {
+        options: Options::default(),
+        out: None,
+        zip: None,
+        contact_sheet: None,
+        contact_columns: 6,
+        contact_cell: 200,
+        ico: None,
+        ico_sizes: vec![16, 32, 48],
+        recursive: false,
+        preset_sizes: BUILTIN_PRESETS
+            .iter()
+            .map(|&(name, size)| (name.to_string(), size))
+            .collect(),
+        presets: Vec::new(),
+        thumb: None,
+        quality_presets: BUILTIN_QUALITY_PRESETS
+            .iter()
+            .map(|&(name, preset)| (name.to_string(), preset))
+            .collect(),
+        flatten: false,
+        split_pages: false,
+        exif_date_subfolder: false,
+        threads: 0,
+        limit: None,
+        no_pause: false,
+        json: false,
+        stats: false,
+        deterministic: false,
+        number: false,
+        incremental: false,
+        manifest: None,
+        log_level: LevelFilter::Info,
+        paths: Vec::new(),
+    };
+    if let Some(path) = config_path {
+        let mut config = Config::load(&path)?;
+        // Presets defined in the file are known before any flag picks one.
+        args.preset_sizes
+            .extend(config.presets.take().unwrap_or_default());
+        args.quality_presets
+            .extend(config.quality_presets.take().unwrap_or_default());
+        let flags = config.into_flags();
+        apply_flags(&mut args, flags.into_iter().map(OsString::from))
+            .map_err(|e| format!("in {}: {}", path.display(), e))?;
+    }
+    apply_flags(&mut args, raw.into_iter())?;
+    // CMYK would put the gray back into four channels.
+    if args.options.grayscale && args.options.jpeg_color_space != JpegColorSpace::Rgb {
+        return Err("--grayscale needs --colorspace rgb".to_string());
+    }
+    // The number is the whole name unless another prefix is asked for.
+    if args.number && args.options.prefix == Options::default().prefix {
+        args.options.prefix.clear();
+    }
+    if args.zip.is_some() && args.out.is_some() {
+        return Err("--zip and --out cannot be used together".to_string());
+    }
+    if args.options.content_name && args.options.name_template.is_some() {
+        return Err("--content-name and --rename-template cannot be used together".to_string());
+    }
+    if args.options.smallest && args.options.format.is_some() {
+        return Err("--smallest and --format cannot be used together".to_string());
+    }
+    if args.options.smallest && args.options.keep_smaller_source_format {
+        return Err("--smallest and --keep-jpeg-on-gain cannot be used together".to_string());
+    }
+    if args.options.assume_srgb && args.options.strip {
+        return Err("--assume-srgb and --strip cannot be used together".to_string());
+    }
+    if args.options.min_ssim.is_some() && args.options.max_bytes.is_some() {
+        return Err("--min-ssim and --max-bytes cannot be used together".to_string());
+    }
+    Ok(args)
+}
+
+/// Applies one source of flags on top of `args`. Conflicts are only checked
+/// within a source, so the command line can override what a config file set.
+/// Flags and their values must be UTF-8, but input paths and `--out` are
+/// taken as given.
+fn apply_flags(args: &mut Args, mut raw: impl Iterator<Item = OsString>) -> Result<(), String> {
+    let mut sizing = Vec::new();
+    let mut overwrite_flag = None;
+    let mut log_flag = None;
+    let mut policy_flag = None;
+    let mut layout_flag = None;
+    let mut edge_flag = None;
+    while let Some(arg) = raw.next() {
+        let arg = match arg.into_string() {
+            Ok(arg) => arg,
+            Err(path) => {
+                args.paths.push(path);
+                continue;
+            }
+        };
+        if sizing.is_empty() && SIZING_FLAGS.contains(&arg.as_str()) {
+            // Whatever sizing an earlier source chose is replaced outright,
+            // rather than combining with this one.
+            let defaults = Options::default();
+            args.options.target_size = defaults.target_size;
+            args.options.scale = None;
+            args.options.max_megapixels = None;
+            args.options.max_width = None;
+            args.options.max_height = None;
+            args.options.crop = None;
+            args.presets.clear();
+        }
+        match arg.as_str() {
+            "--config" => {
+                value::<PathBuf>(&mut raw, &arg)?;
+            }
+            "-s" | "--size" => {
+                sizing.push("--size");
+                args.options.target_size = match value::<usize>(&mut raw, &arg)? {
+                    0 => return Err(format!("invalid value for {}: 0", arg)),
+                    n => n,
+                };
+            }
+            // Unlike --size with --fit-edge, these name the edge themselves.
+            "--long-edge" | "--short-edge" => {
+                let (flag, edge) = match arg.as_str() {
+                    "--long-edge" => ("--long-edge", FitEdge::Longest),
+                    _ => ("--short-edge", FitEdge::Shortest),
+                };
+                if let Some(other) = edge_flag.filter(|&f| f != flag) {
+                    return Err(format!("{} and {} cannot be used together", other, flag));
+                }
+                sizing.push(flag);
+                args.options.target_size = match value::<usize>(&mut raw, &arg)? {
+                    0 => return Err(format!("invalid value for {}: 0", arg)),
+                    n => n,
+                };
+                args.options.fit_edge = edge;
+                edge_flag = Some(flag);
+            }
+            "-q"
+            | "--quality"
+            | "--jpeg-quality"
+            | "--webp-quality"
+            | "--avif-quality"
+            | "--jpeg-input-quality"
+            | "--min-quality" => {
+                let v = value::<f32>(&mut raw, &arg)?;
+                if !(0.0..=100.0).contains(&v) {
+                    return Err(format!("invalid value for {}: {} (expected 0-100)", arg, v));
+                }
+                match arg.as_str() {
+                    "--jpeg-quality" => args.options.jpeg_quality = Some(v),
+                    "--webp-quality" => args.options.webp_quality = Some(v),
+                    "--avif-quality" => args.options.avif_quality = Some(v),
+                    "--jpeg-input-quality" => args.options.jpeg_input_quality = Some(v),
+                    "--min-quality" => args.options.min_quality = v,
+                    _ => args.options.quality = v,
+                }
+            }
+            "--quality-range" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                let range = v
+                    .split_once('-')
+                    .and_then(|(low, high)| {
+                        Some((low.parse::<f32>().ok()?, high.parse::<f32>().ok()?))
+                    })
+                    .filter(|&(low, high)| 0.0 <= low && low <= high && high <= 100.0);
+                args.options.quality_range = Some(range.ok_or_else(|| {
+                    format!(
+                        "invalid value for {}: {} (expected MIN-MAX within 0-100)",
+                        arg, v
+                    )
+                })?);
+            }
+            "--scale" => {
+                let v = value::<f32>(&mut raw, &arg)?;
+                if v <= 0.0 {
+                    return Err(format!("invalid value for {}: {}", arg, v));
+                }
+                args.options.scale = Some(v);
+                sizing.push("--scale");
+            }
+            "--max-megapixels" => {
+                let v = value::<f64>(&mut raw, &arg)?;
+                if !(v > 0.0 && v.is_finite()) {
+                    return Err(format!("invalid value for {}: {}", arg, v));
+                }
+                args.options.max_megapixels = Some(v);
+                sizing.push("--max-megapixels");
+            }
+            "--max-width" | "--max-height" => {
+                let v = match value::<u32>(&mut raw, &arg)? {
+                    0 => return Err(format!("invalid value for {}: 0", arg)),
+                    n => Some(n),
+                };
+                if arg == "--max-width" {
+                    args.options.max_width = v;
+                    sizing.push("--max-width");
+                } else {
+                    args.options.max_height = v;
+                    sizing.push("--max-height");
+                }
+            }
+            "--min-width" => args.options.min_width = Some(value::<u32>(&mut raw, &arg)?),
+            "--min-height" => args.options.min_height = Some(value::<u32>(&mut raw, &arg)?),
+            // Zero lifts the limit.
+            "--max-pixels" => {
+                args.options.max_pixels = Some(value::<u64>(&mut raw, &arg)?).filter(|&n| n > 0)
+            }
+            "--round-dimensions" => {
+                args.options.round_dimensions = match value::<u32>(&mut raw, &arg)? {
+                    0 => return Err(format!("invalid value for {}: 0", arg)),
+                    n => Some(n),
+                }
+            }
+            "--preset" => {
+                let name = value::<String>(&mut raw, &arg)?;
+                let size = *args.preset_sizes.get(&name).ok_or_else(|| {
+                    let known = args.preset_sizes.keys().cloned().collect::<Vec<_>>();
+                    format!(
+                        "invalid value for {}: {} (expected {})",
+                        arg,
+                        name,
+                        known.join(", ")
+                    )
+                })?;
+                if !args.presets.iter().any(|(n, _)| *n == name) {
+                    args.presets.push((name, size));
+                }
+                sizing.push("--preset");
+            }
+            "--thumb" => {
+                args.thumb = match value::<usize>(&mut raw, &arg)? {
+                    0 => return Err(format!("invalid value for {}: 0", arg)),
+                    n => Some(n),
+                };
+            }
+            "--sizes" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                for size in v.split(',') {
+                    let size = match size.trim().parse::<usize>() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("invalid value for {}: {}", arg, v)),
+                    };
+                    if !args.presets.iter().any(|&(_, s)| s == size) {
+                        args.presets.push((size.to_string(), size));
+                    }
+                }
+                sizing.push("--sizes");
+            }
+            "--max-bytes" => {
+                args.options.max_bytes = match value::<usize>(&mut raw, &arg)? {
+                    0 => return Err(format!("invalid value for {}: 0", arg)),
+                    n => Some(n),
+                };
+            }
+            "--min-ssim" => {
+                let v = value::<f64>(&mut raw, &arg)?;
+                if !(0.0..=1.0).contains(&v) {
+                    return Err(format!("invalid value for {}: {} (expected 0-1)", arg, v));
+                }
+                args.options.min_ssim = Some(v);
+            }
+            "--auto-quality" => args.options.auto_quality = true,
+            "--fit" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                let (width, height) = parse_dimensions(&v)
+                    .ok_or_else(|| format!("invalid value for {}: {} (expected WxH)", arg, v))?;
+                args.options.max_width = Some(width);
+                args.options.max_height = Some(height);
+                sizing.push("--fit");
+            }
+            // Fits into the reference as it displays, so its EXIF orientation
+            // counts.
+            "--match" => {
+                let v = PathBuf::from(
+                    raw.next()
+                        .ok_or_else(|| format!("{} requires a value", arg))?,
+                );
+                let info = resize_image::inspect(&v)
+                    .map_err(|e| format!("cannot read the size of {}: {}", v.display(), e))?;
+                let (width, height) = match info.orientation {
+                    5..=8 => (info.height, info.width),
+                    _ => (info.width, info.height),
+                };
+                args.options.max_width = Some(width);
+                args.options.max_height = Some(height);
+                sizing.push("--match");
+            }
+            "--crop" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                args.options.crop =
+                    Some(parse_dimensions(&v).ok_or_else(|| {
+                        format!("invalid value for {}: {} (expected WxH)", arg, v)
+                    })?);
+                sizing.push("--crop");
+            }
+            "--filter" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                args.options.filter = parse_filter(&v).ok_or_else(|| {
+                    format!(
+                        "invalid value for {}: {} (expected nearest, triangle, catmullrom, gaussian or lanczos3)",
+                        arg, v
+                    )
+                })?;
+            }
+            "--sharpen" => {
+                let v = value::<f32>(&mut raw, &arg)?;
+                if v <= 0.0 {
+                    return Err(format!("invalid value for {}: {}", arg, v));
+                }
+                args.options.sharpen = Some(v);
+            }
+            "--prefix" => args.options.prefix = value::<String>(&mut raw, &arg)?,
+            "--suffix" => args.options.suffix = value::<String>(&mut raw, &arg)?,
+            "--content-name" => args.options.content_name = true,
+            "--rename-template" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                let template = v
+                    .parse::<NameTemplate>()
+                    .map_err(|e| format!("invalid value for {}: {} ({})", arg, v, e))?;
+                args.options.name_template = Some(template);
+            }
+            "--bucket-by-size" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                let buckets = v
+                    .parse::<SizeBuckets>()
+                    .map_err(|e| format!("invalid value for {}: {} ({})", arg, v, e))?;
+                args.options.size_buckets = Some(buckets);
+            }
+            "--name-dimensions" => args.options.name_dimensions = true,
+            "--sidecar" => args.options.sidecar = true,
+            "--post-cmd" => args.options.post_command = Some(value::<String>(&mut raw, &arg)?),
+            "--copy-unsupported" => args.options.copy_unsupported = true,
+            "--rotate" => {
+                args.options.rotate = match value::<String>(&mut raw, &arg)?.as_str() {
+                    "90" => Some(Rotation::R90),
+                    "180" => Some(Rotation::R180),
+                    "270" => Some(Rotation::R270),
+                    v => {
+                        return Err(format!(
+                            "invalid value for {}: {} (expected 90, 180 or 270)",
+                            arg, v
+                        ))
+                    }
+                };
+            }
+            "--resize-policy" | "--allow-upscale" => {
+                let policy = if arg == "--allow-upscale" {
+                    ResizePolicy::Always
+                } else {
+                    match value::<String>(&mut raw, &arg)?.as_str() {
+                        "only-larger" => ResizePolicy::OnlyLarger,
+                        "always" => ResizePolicy::Always,
+                        v => {
+                            return Err(format!(
+                                "invalid value for {}: {} (expected only-larger or always)",
+                                arg, v
+                            ))
+                        }
+                    }
+                };
+                if policy_flag.is_some_and(|p| p != policy) {
+                    return Err(
+                        "--allow-upscale and --resize-policy only-larger cannot be used together"
+                            .to_string(),
+                    );
+                }
+                args.options.resize_policy = policy;
+                policy_flag = Some(policy);
+            }
+            "--fit-edge" => {
+                if let Some(other) = edge_flag.filter(|&f| f != "--fit-edge") {
+                    return Err(format!("{} and --fit-edge cannot be used together", other));
+                }
+                edge_flag = Some("--fit-edge");
+                args.options.fit_edge = match value::<String>(&mut raw, &arg)?.as_str() {
+                    "longest" => FitEdge::Longest,
+                    "shortest" => FitEdge::Shortest,
+                    v => {
+                        return Err(format!(
+                            "invalid value for {}: {} (expected longest or shortest)",
+                            arg, v
+                        ))
+                    }
+                };
+            }
+            "--flip" => {
+                args.options.flip = match value::<String>(&mut raw, &arg)?.as_str() {
+                    "h" => Some(Flip::Horizontal),
+                    "v" => Some(Flip::Vertical),
+                    v => {
+                        return Err(format!(
+                            "invalid value for {}: {} (expected h or v)",
+                            arg, v
+                        ))
+                    }
+                };
+            }
+            "--watermark" => {
+                let v = value::<PathBuf>(&mut raw, &arg)?;
+                let mark = image::open(&v)
+                    .map_err(|e| format!("cannot read watermark {}: {}", v.display(), e))?;
+                args.options.watermark = Some(Arc::new(mark.into_rgba8()));
+            }
+            "--watermark-pos" => {
+                args.options.watermark_position = match value::<String>(&mut raw, &arg)?.as_str() {
+                    "top-left" => WatermarkPosition::TopLeft,
+                    "bottom-right" => WatermarkPosition::BottomRight,
+                    "center" => WatermarkPosition::Center,
+                    v => {
+                        return Err(format!(
+                            "invalid value for {}: {} (expected top-left, bottom-right or center)",
+                            arg, v
+                        ))
+                    }
+                };
+            }
+            "--watermark-opacity" => {
+                let v = value::<f32>(&mut raw, &arg)?;
+                if !(0.0..=1.0).contains(&v) {
+                    return Err(format!("invalid value for {}: {} (expected 0-1)", arg, v));
+                }
+                args.options.watermark_opacity = v;
+            }
+            "--grayscale" => args.options.grayscale = true,
+            "--background" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                args.options.background = parse_color(&v).ok_or_else(|| {
+                    format!(
+                        "invalid value for {}: {} (expected a hex color like #ffffff)",
+                        arg, v
+                    )
+                })?;
+            }
+            "--subsampling" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                args.options.subsampling = Some(parse_subsampling(&v).ok_or_else(|| {
+                    format!(
+                        "invalid value for {}: {} (expected 420, 422 or 444)",
+                        arg, v
+                    )
+                })?);
+            }
+            "--colorspace" => {
+                args.options.jpeg_color_space = match value::<String>(&mut raw, &arg)?.as_str() {
+                    "rgb" => JpegColorSpace::Rgb,
+                    "cmyk" => JpegColorSpace::Cmyk,
+                    "ycck" => JpegColorSpace::Ycck,
+                    v => {
+                        return Err(format!(
+                            "invalid value for {}: {} (expected rgb, ycck or cmyk)",
+                            arg, v
+                        ))
+                    }
+                };
+            }
+            "--preset-quality" => {
+                let name = value::<String>(&mut raw, &arg)?;
+                let preset = *args.quality_presets.get(&name).ok_or_else(|| {
+                    let known = args.quality_presets.keys().cloned().collect::<Vec<_>>();
+                    format!(
+                        "invalid value for {}: {} (expected {})",
+                        arg,
+                        name,
+                        known.join(", ")
+                    )
+                })?;
+                let subsampling = parse_subsampling(&preset.subsampling.to_string())
+                    .filter(|_| (0.0..=100.0).contains(&preset.quality))
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid quality preset {}: expected a quality of 0-100 and \
+                             subsampling 420, 422 or 444",
+                            name
+                        )
+                    })?;
+                args.options.quality = preset.quality;
+                args.options.subsampling = Some(subsampling);
+                args.options.progressive = preset.progressive;
+            }
+            "--progressive" => args.options.progressive = true,
+            "--baseline" => args.options.progressive = false,
+            "--optimize-coding" => args.options.optimize_coding = true,
+            "--no-optimize-coding" => args.options.optimize_coding = false,
+            // The mozjpeg wrapper has no setter for trellis quantization, so
+            // it stays at mozjpeg's default of on.
+            "--trellis" | "--no-trellis" => {
+                return Err(format!(
+                    "{} is not supported: trellis quantization is always on",
+                    arg
+                ))
+            }
+            "--autorotate" => args.options.autorotate = true,
+            "--no-autorotate" => args.options.autorotate = false,
+            "--smoothing" => {
+                args.options.smoothing = match value::<u8>(&mut raw, &arg)? {
+                    n @ 0..=100 => n,
+                    n => return Err(format!("invalid value for {}: {} (expected 0-100)", arg, n)),
+                };
+            }
+            "--webp-lossless" => args.options.webp_lossless = true,
+            "--dither" => args.options.dither = true,
+            "--png-interlace" => args.options.png_interlace = true,
+            "--strip" => args.options.strip = true,
+            "--strip-icc" => args.options.strip_icc = true,
+            "--assume-srgb" => args.options.assume_srgb = true,
+            "--keep-exif" => args.options.keep_exif = true,
+            "--keep-gps" => args.options.keep_gps = true,
+            "--strip-to-baseline" => args.options.strip_to_baseline = true,
+            "--png-level" => {
+                args.options.png_level = match value::<u8>(&mut raw, &arg)? {
+                    n @ 0..=6 => n,
+                    n => return Err(format!("invalid value for {}: {} (expected 0-6)", arg, n)),
+                };
+            }
+            "--tiff-compression" => {
+                args.options.tiff_compression = match value::<String>(&mut raw, &arg)?.as_str() {
+                    "none" => TiffCompression::None,
+                    "lzw" => TiffCompression::Lzw,
+                    "deflate" => TiffCompression::Deflate,
+                    v => {
+                        return Err(format!(
+                            "invalid value for {}: {} (expected none, lzw or deflate)",
+                            arg, v
+                        ))
+                    }
+                };
+            }
+            "--dpi" => {
+                args.options.dpi = match value::<u16>(&mut raw, &arg)? {
+                    0 => return Err(format!("invalid value for {}: 0", arg)),
+                    n => Some(n),
+                };
+            }
+            "--skip-smaller" => args.options.skip_smaller = true,
+            "--input-format" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                let format = image::ImageFormat::from_extension(&v)
+                    .filter(|format| format.can_read())
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid value for {}: {} (expected jpeg, png, webp, gif, tiff, bmp, pnm or another format that can be read)",
+                            arg, v
+                        )
+                    })?;
+                args.options.input_format = Some(format);
+            }
+            "--format" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                args.options.format = Some(parse_format(&v).ok_or_else(|| {
+                    format!(
+                        "invalid value for {}: {} (expected jpeg, png, webp, gif, avif, tiff, bmp or jxl)",
+                        arg, v
+                    )
+                })?);
+            }
+            "--also" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                args.options.also_format = Some(parse_format(&v).ok_or_else(|| {
+                    format!(
+                        "invalid value for {}: {} (expected jpeg, png, webp, gif, avif, tiff, bmp or jxl)",
+                        arg, v
+                    )
+                })?);
+            }
+            "-o" | "--out" => {
+                let v = raw
+                    .next()
+                    .ok_or_else(|| format!("{} requires a value", arg))?;
+                args.out = Some(PathBuf::from(v));
+            }
+            "--zip" => {
+                let v = raw
+                    .next()
+                    .ok_or_else(|| format!("{} requires a value", arg))?;
+                args.zip = Some(PathBuf::from(v));
+            }
+            "--contact-sheet" => {
+                let v = raw
+                    .next()
+                    .ok_or_else(|| format!("{} requires a value", arg))?;
+                args.contact_sheet = Some(PathBuf::from(v));
+            }
+            "--ico" => {
+                let v = raw
+                    .next()
+                    .ok_or_else(|| format!("{} requires a value", arg))?;
+                args.ico = Some(PathBuf::from(v));
+            }
+            "--ico-sizes" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                args.ico_sizes = v
+                    .split(',')
+                    .map(|s| {
+                        s.trim()
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|n| (1..=256).contains(n))
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| {
+                        format!("invalid value for {}: {} (expected sizes of 1-256)", arg, v)
+                    })?;
+            }
+            "--contact-columns" | "--contact-cell" => {
+                let v = match value::<u32>(&mut raw, &arg)? {
+                    0 => return Err(format!("invalid value for {}: 0", arg)),
+                    v => v,
+                };
+                if arg == "--contact-columns" {
+                    args.contact_columns = v;
+                } else {
+                    args.contact_cell = v;
+                }
+            }
+            "--no-clobber" | "--force" | "--suffix-on-conflict" => {
+                if overwrite_flag.is_some_and(|f| f != arg) {
+                    return Err(
+                        "--no-clobber, --force and --suffix-on-conflict cannot be used together"
+                            .to_string(),
+                    );
+                }
+                args.options.overwrite = match arg.as_str() {
+                    "--force" => Overwrite::Force,
+                    "--no-clobber" => Overwrite::Skip,
+                    _ => match value::<String>(&mut raw, &arg)?.as_str() {
+                        "error" => Overwrite::Error,
+                        "skip" => Overwrite::Skip,
+                        "rename" => Overwrite::Rename,
+                        v => {
+                            return Err(format!(
+                                "invalid value for {}: {} (expected error, skip or rename)",
+                                arg, v
+                            ))
+                        }
+                    },
+                };
+                overwrite_flag = Some(arg);
+            }
+            "-n" | "--dry-run" => args.options.dry_run = true,
+            "--estimate" => args.options.estimate = true,
+            // Named for the usual case of photos converted to PNG, but keeps
+            // any source format that comes out smaller.
+            "--keep-jpeg-on-gain" => args.options.keep_smaller_source_format = true,
+            "--smallest" => args.options.smallest = true,
+            "--validate" => args.options.validate = true,
+            // Deleting what fails validation implies validating.
+            "--delete-invalid" => {
+                args.options.validate = true;
+                args.options.delete_invalid = true;
+            }
+            "-r" | "--recursive" => args.recursive = true,
+            "--keep-structure" | "--flatten" => {
+                if layout_flag.is_some_and(|f| f != arg) {
+                    return Err(
+                        "--keep-structure and --flatten cannot be used together".to_string()
+                    );
+                }
+                args.flatten = arg == "--flatten";
+                layout_flag = Some(arg);
+            }
+            "--split-pages" => args.split_pages = true,
+            "--exif-date-subfolder" => args.exif_date_subfolder = true,
+            "-j" | "--threads" => args.threads = value::<usize>(&mut raw, &arg)?,
+            "--threads-per-image" => {
+                args.options.threads_per_image = value::<usize>(&mut raw, &arg)?
+            }
+            "--limit" => args.limit = Some(value::<usize>(&mut raw, &arg)?),
+            "--retries" => args.options.retries = value::<u32>(&mut raw, &arg)?,
+            // In seconds.
+            "--timeout" => {
+                let v = value::<f64>(&mut raw, &arg)?;
+                if !(v > 0.0 && v.is_finite()) {
+                    return Err(format!("invalid value for {}: {}", arg, v));
+                }
+                args.options.timeout = Some(Duration::from_secs_f64(v));
+            }
+            "--io-threads" => {
+                args.options.io_limit = match value::<usize>(&mut raw, &arg)? {
+                    0 => return Err(format!("invalid value for {}: 0", arg)),
+                    n => Some(Arc::new(IoLimit::new(n))),
+                };
+            }
+            // In megabytes.
+            "--memory-limit" => {
+                args.options.memory_limit = match value::<u64>(&mut raw, &arg)? {
+                    0 => return Err(format!("invalid value for {}: 0", arg)),
+                    n => Some(Arc::new(MemoryLimit::new(n.saturating_mul(1 << 20)))),
+                };
+            }
+            "--no-pause" => args.no_pause = true,
+            "--hash" => args.options.hash = true,
+            "--compare" => args.options.compare = true,
+            "--preserve-timestamps" => args.options.preserve_timestamps = true,
+            "--chmod" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                args.options.mode = Some(
+                    u32::from_str_radix(&v, 8)
+                        .ok()
+                        .filter(|&mode| mode <= 0o7777)
+                        .ok_or_else(|| {
+                            format!(
+                                "invalid value for {}: {} (expected an octal mode such as 644)",
+                                arg, v
+                            )
+                        })?,
+                );
+            }
+            "--from-file" => {
+                let v = value::<String>(&mut raw, &arg)?;
+                args.paths
+                    .extend(read_list(&v)?.into_iter().map(OsString::from));
+            }
+            "--json" => args.json = true,
+            "--stats" => args.stats = true,
+            "--deterministic" => args.deterministic = true,
+            "--number" => args.number = true,
+            "--incremental" => args.incremental = true,
+            "--manifest" => {
+                let v = raw
+                    .next()
+                    .ok_or_else(|| format!("{} requires a value", arg))?;
+                args.manifest = Some(PathBuf::from(v));
+            }
+            "-v" | "--verbose" | "--quiet" => {
+                let level = if arg == "--quiet" {
+                    LevelFilter::Error
+                } else {
+                    LevelFilter::Debug
+                };
+                if log_flag.is_some_and(|l| l != level) {
+                    return Err("--verbose and --quiet cannot be used together".to_string());
+                }
+                args.log_level = level;
+                log_flag = Some(level);
+            }
+            _ => args.paths.push(arg.into()),
+        }
+    }
+    // --max-width and --max-height combine into a single bound.
+    if sizing.contains(&"--max-width") {
+        sizing.retain(|f| *f != "--max-height");
+    }
+    sizing.sort_unstable();
+    sizing.dedup();
+    if let [first, second, ..] = sizing.as_slice() {
+        return Err(format!("{} and {} cannot be used together", first, second));
+    }
+    Ok(())
+}
+
+/// Pairs each input file with the directory its output is written to.
+/// Without `--out` every output lands next to its source. With it, files
+/// found by walking a directory keep their position relative to that
+/// directory, so the output mirrors the input tree.
+///
+/// Arguments containing wildcards are expanded as glob patterns, since the
+/// Windows shell passes them through literally.
+fn collect_sources(args: &Args) -> Vec<Job> {
+    let mut sources = Vec::new();
+    let flat_target = |p: &Path| match &args.out {
+        Some(out) => out.clone(),
+        None => source_dir(p),
+    };
+    // Every file is wanted when the unsupported ones are copied.
+    let wanted = |p: &Path| args.options.copy_unsupported || resize_image::is_image(p);
+    for arg in &args.paths {
+        let p = PathBuf::from(arg);
+        let pattern = arg.to_str().filter(|a| a.contains(['*', '?', '[']));
+        if let (false, Some(pattern)) = (p.exists(), pattern) {
+            let matches = match glob::glob(pattern) {
+                Ok(paths) => paths
+                    .filter_map(Result::ok)
+                    .filter(|m| m.is_file() && wanted(m))
+                    .collect::<Vec<_>>(),
+                Err(e) => {
+                    log::error!("{} FAILED due to {}", pattern, e);
+                    continue;
+                }
+            };
+            log::info!("{} matched {} files.", pattern, matches.len());
+            for m in matches {
+                let target_dir = flat_target(&m);
+                sources.push(Job {
+                    source: m,
+                    target_dir,
+                    stem: None,
+                    page: None,
+                    index: None,
+                });
+            }
+        } else if p.is_file() && p.file_name().is_some() && p.extension().is_some() {
+            let target_dir = flat_target(&p);
+            sources.push(Job {
+                source: p,
+                target_dir,
+                stem: None,
+                page: None,
+                index: None,
+            });
+        } else if p.is_dir() {
+            // Only a walk goes into subdirectories.
+            let files = if args.recursive {
+                resize_image::walk_dir_filtered(&p, wanted)
+            } else {
+                resize_image::read_dir_filtered(&p, wanted)
+            };
+            let mut files = match files {
+                Ok(v) => v,
+                Err(e) => {
+                    log::error!("{} FAILED due to {}", p.display(), e);
+                    continue;
+                }
+            };
+            let ignore_file = p.join(IGNORE_FILE);
+            if ignore_file.is_file() {
+                let (ignore, error) = Gitignore::new(&ignore_file);
+                if let Some(e) = error {
+                    log::warn!("{}: {}", ignore_file.display(), e);
+                }
+                let count = files.len();
+                files.retain(|file| !ignore.matched_path_or_any_parents(file, false).is_ignore());
+                log::info!(
+                    "{} files are excluded by {}.",
+                    count - files.len(),
+                    ignore_file.display()
+                );
+            }
+            // Names like `.` only resolve to the directory's own name once
+            // made absolute.
+            let dir = match p.file_name() {
+                Some(_) => p.clone(),
+                None => fs::canonicalize(&p).unwrap_or_else(|_| p.clone()),
+            };
+            let out = args
+                .out
+                .clone()
+                .unwrap_or_else(|| source_dir(&dir).join(RESIZED_DIR));
+            for file in files {
+                let target_dir = if args.flatten || args.exif_date_subfolder {
+                    out.clone()
+                } else {
+                    let relative = file
+                        .strip_prefix(&p)
+                        .ok()
+                        .and_then(Path::parent)
+                        .unwrap_or(Path::new(""));
+                    out.join(dir.file_name().unwrap_or_default()).join(relative)
+                };
+                sources.push(Job {
+                    source: file,
+                    target_dir,
+                    stem: None,
+                    page: None,
+                    index: None,
+                });
+            }
+        }
+    }
+    // Decoders only say they can't read these, which doesn't tell why.
+    sources.retain(|job| {
+        let empty = fs::metadata(&job.source).is_ok_and(|m| m.len() == 0);
+        if empty {
+            log::warn!("{} is an empty file, skipped.", job.source.display());
+        }
+        !empty
+    });
+    if args.exif_date_subfolder {
+        for job in &mut sources {
+            match resize_image::capture_date(&job.source) {
+                Some((year, month, day)) => {
+                    job.target_dir.push(format!("{:04}", year));
+                    job.target_dir.push(format!("{:02}", month));
+                    job.target_dir.push(format!("{:02}", day));
+                }
+                None => job.target_dir.push(UNDATED_DIR),
+            }
+        }
+    }
+    // Sorted before renaming, so that the numbering is stable, too.
+    if args.deterministic || args.number {
+        sources.sort_by(|a, b| a.source.cmp(&b.source));
+    }
+    // An archive has no existing files to warn about, so same-named outputs
+    // are told apart as in a flattened tree, or in a date folder, which
+    // gathers files from anywhere in the tree.
+    if (args.flatten || args.exif_date_subfolder || args.zip.is_some()) && !args.number {
+        rename_collisions(&mut sources);
+    }
+    if args.split_pages {
+        sources = sources.into_iter().flat_map(split_pages).collect();
+    }
+    sources
+}
+
+/// Turns a multi-page TIFF's job into one per page, numbered from 1 after
+/// the name it would have had.
+fn split_pages(job: Job) -> Vec<Job> {
+    let pages = resize_image::inspect(&job.source).map_or(1, |info| info.pages);
+    if pages < 2 {
+        return vec![job];
+    }
+    let stem = job
+        .stem
+        .clone()
+        .unwrap_or_else(|| job.source.file_stem().unwrap_or_default().to_os_string());
+    let width = pages.to_string().len();
+    (0..pages)
+        .map(|page| {
+            let mut stem = stem.clone();
+            stem.push(format!("_{:0width$}", page + 1));
+            Job {
+                stem: Some(stem),
+                page: Some(page),
+                ..job.clone()
+            }
+        })
+        .collect()
+}
+
+/// Writes the outputs held in memory into a zip archive at `path`, in input
+/// order. Images are already compressed, so they are stored as they are.
+/// Returns how many outputs could not be added.
+fn write_zip(
+    path: &Path,
+    results: &[Result<Vec<Report>, Error>],
+    options: &Options,
+) -> Result<usize, String> {
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    let entry_options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let (mut added, mut failures) = (0, 0);
+    for report in results.iter().flatten().flatten() {
+        let Some(data) = &report.data else {
+            continue;
+        };
+        let result = archive
+            .start_file_from_path(&report.target, entry_options)
+            .and_then(|()| Ok(archive.write_all(data)?));
+        match result {
+            Ok(()) => added += 1,
+            Err(e) => {
+                log::error!("{} FAILED due to {}", report.target.display(), e);
+                failures += 1;
+            }
+        }
+    }
+    let data = archive
+        .finish()
+        .map_err(|e| format!("cannot write {}: {}", path.display(), e))?
+        .into_inner();
+    let report = resize_image::write_bytes(&data, path, None, None, options)
+        .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    if report.status == Status::Skipped {
+        log::info!("The archive is skipped: {} exists.", path.display());
+        return Ok(failures);
+    }
+    log::info!(
+        "{} outputs are archived in {}.",
+        added,
+        report.target.display()
+    );
+    Ok(failures)
+}
+
+/// Tiles the thumbnails of the outputs into a contact sheet at `path`, in
+/// input order and captioned with the outputs' file names. The format
+/// follows the extension; the sheet is opaque, so JPEG works too.
+fn write_contact_sheet(
+    path: &Path,
+    results: &[Result<Vec<Report>, Error>],
+    args: &Args,
+) -> Result<(), String> {
+    let cells = results
+        .iter()
+        .flatten()
+        .flatten()
+        .filter_map(|report| {
+            let name = report.target.file_name()?.to_string_lossy().into_owned();
+            Some((name, report.thumbnail.as_ref()?))
+        })
+        .collect::<Vec<_>>();
+    if cells.is_empty() {
+        log::info!("No images for the contact sheet.");
+        return Ok(());
+    }
+    let sheet = resize_image::contact_sheet(&cells, args.contact_columns, args.contact_cell);
+    let format = image::ImageFormat::from_path(path)
+        .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    let mut data = Cursor::new(Vec::new());
+    image::DynamicImage::ImageRgba8(sheet)
+        .into_rgb8()
+        .write_to(&mut data, format)
+        .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    let report = resize_image::write_bytes(data.get_ref(), path, None, None, &args.options)
+        .map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    if report.status == Status::Skipped {
+        log::info!("The contact sheet is skipped: {} exists.", path.display());
+        return Ok(());
+    }
+    log::info!(
+        "Contact sheet of {} images is written to {}.",
+        cells.len(),
+        report.target.display()
+    );
+    Ok(())
+}
+
+/// What `--incremental` remembers about a source, keyed by its canonical
+/// path.
+type Manifest = BTreeMap<PathBuf, ManifestEntry>;
+
+#[derive(Deserialize, Serialize)]
+struct ManifestEntry {
+    size: u64,
+    /// Seconds and nanoseconds since the Unix epoch.
+    mtime: (i64, u32),
+    /// Decides when the modification time changed but the size didn't,
+    /// e.g. after a copy or a `touch`.
+    sha256: String,
+    outputs: Vec<PathBuf>,
+}
+
+/// A missing manifest is an empty one, as on the first run.
+fn read_manifest(path: &Path) -> Result<Manifest, String> {
+    match fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .map_err(|e| format!("cannot parse {}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Manifest::new()),
+        Err(e) => Err(format!("cannot read {}: {}", path.display(), e)),
+    }
+}
+
+/// Written to a temporary file first, so that an interrupted run leaves the
+/// previous manifest intact.
+fn write_manifest(path: &Path, manifest: &Manifest) -> Result<(), String> {
+    let mut temp = path.as_os_str().to_os_string();
+    temp.push(".tmp");
+    let error = |e: &dyn std::fmt::Display| format!("cannot write {}: {}", path.display(), e);
+    let data = serde_json::to_vec_pretty(manifest).map_err(|e| error(&e))?;
+    fs::write(&temp, data)
+        .and_then(|()| fs::rename(&temp, path))
+        .map_err(|e| error(&e))
+}
+
+fn manifest_key(source: &Path) -> PathBuf {
+    fs::canonicalize(source).unwrap_or_else(|_| source.to_path_buf())
+}
+
+fn file_sha256(path: &Path) -> io::Result<String> {
+    let digest = Sha256::digest(fs::read(path)?);
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn file_times(metadata: &fs::Metadata) -> (i64, u32) {
+    let mtime = FileTime::from_last_modification_time(metadata);
+    (mtime.unix_seconds(), mtime.nanoseconds())
+}
+
+/// Returns `true` if `source` has the size and contents recorded in the
+/// manifest and every output recorded for it still exists. The contents are
+/// only hashed when the modification time differs.
+fn is_unchanged(source: &Path, manifest: &Manifest) -> bool {
+    let Some(entry) = manifest.get(&manifest_key(source)) else {
+        return false;
+    };
+    let Ok(metadata) = fs::metadata(source) else {
+        return false;
+    };
+    metadata.len() == entry.size
+        && entry.outputs.iter().all(|output| output.exists())
+        && (file_times(&metadata) == entry.mtime
+            || file_sha256(source).is_ok_and(|hash| hash == entry.sha256))
+}
+
+/// Records every source that succeeded and forgets those that failed, so
+/// that they are retried on the next run.
+fn update_manifest(manifest: &mut Manifest, jobs: &[Job], results: &[Result<Vec<Report>, Error>]) {
+    for (job, result) in jobs.iter().zip(results) {
+        let key = manifest_key(&job.source);
+        let entry = result.as_ref().ok().and_then(|reports| {
+            let metadata = fs::metadata(&job.source).ok()?;
+            Some(ManifestEntry {
+                size: metadata.len(),
+                mtime: file_times(&metadata),
+                sha256: file_sha256(&job.source).ok()?,
+                outputs: reports
+                    .iter()
+                    .filter(|r| r.status != Status::TooSmall)
+                    .map(|r| r.target.clone())
+                    .collect(),
+            })
+        });
+        match entry {
+            Some(entry) => manifest.insert(key, entry),
+            None => manifest.remove(&key),
+        };
+    }
+}
+
+/// Names the jobs' outputs by their position, padded to at least four digits
+/// and to as many as the last number has.
+fn number_jobs(jobs: &mut [Job]) {
+    let width = jobs.len().to_string().len().max(4);
+    for (i, job) in jobs.iter_mut().enumerate() {
+        job.stem = Some(format!("{:0width$}", i + 1, width = width).into());
+    }
+}
+
+/// Gives every job whose file stem is already taken in its target directory
+/// a numbered one, like `cover_2`. Stems are compared case-insensitively
+/// since outputs may differ only in extension or land on a case-insensitive
+/// file system.
+fn rename_collisions(jobs: &mut [Job]) {
+    let mut taken = HashSet::new();
+    for job in jobs.iter() {
+        let stem = job.source.file_stem().unwrap_or_default().to_string_lossy();
+        taken.insert((job.target_dir.clone(), stem.to_lowercase()));
+    }
+    let mut seen = HashSet::new();
+    for job in jobs {
+        let stem = job.source.file_stem().unwrap_or_default().to_os_string();
+        let key = stem.to_string_lossy().to_lowercase();
+        if seen.insert((job.target_dir.clone(), key)) {
+            continue;
+        }
+        let renamed = (2..)
+            .map(|n| {
+                let mut renamed = stem.clone();
+                renamed.push(format!("_{}", n));
+                renamed
+            })
+            .find(|s| taken.insert((job.target_dir.clone(), s.to_string_lossy().to_lowercase())))
+            .unwrap();
+        log::info!(
+            "{} collides with another {} and is written as {}.",
+            job.source.display(),
+            stem.to_string_lossy(),
+            renamed.to_string_lossy()
         );
-        line += 1;
+        job.stem = Some(renamed);
+    }
+}
+
+/// Reads newline-separated paths from `path`, or from stdin for `-`, skipping
+/// blank lines and `#` comments.
+fn read_list(path: &str) -> Result<Vec<String>, String> {
+    let text = if path == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text).map(|_| text)
+    } else {
+        fs::read_to_string(path)
+    }
+    .map_err(|e| format!("cannot read {}: {}", path, e))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// The directory containing `path`, which is empty for a bare file name.
+fn source_dir(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+/// Parses a `WxH` string such as `512x512`. Both sides must be non-zero.
+fn parse_dimensions(s: &str) -> Option<(u32, u32)> {
+    let (w, h) = s.split_once(['x', 'X'])?;
+    match (w.parse::<u32>().ok()?, h.parse::<u32>().ok()?) {
+        (0, _) | (_, 0) => None,
+        dims => Some(dims),
+    }
+}
+
+/// Parses a hex color such as `#ffffff`, `ffffff` or `#fff`.
+fn parse_color(s: &str) -> Option<[u8; 3]> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize, len: usize| {
+        let v = u8::from_str_radix(&hex[i * len..(i + 1) * len], 16).ok()?;
+        Some(if len == 1 { v * 17 } else { v })
+    };
+    let len = match hex.len() {
+        3 => 1,
+        6 => 2,
+        _ => return None,
+    };
+    Some([channel(0, len)?, channel(1, len)?, channel(2, len)?])
+}
+
+fn parse_subsampling(s: &str) -> Option<Subsampling> {
+    match s {
+        "420" => Some(Subsampling::S420),
+        "422" => Some(Subsampling::S422),
+        "444" => Some(Subsampling::S444),
+        _ => None,
+    }
+}
+
+fn parse_format(s: &str) -> Option<OutputFormat> {
+    match s.to_ascii_lowercase().as_str() {
+        "jpeg" | "jpg" => Some(OutputFormat::Jpeg),
+        "png" => Some(OutputFormat::Png),
+        "webp" => Some(OutputFormat::WebP),
+        "gif" => Some(OutputFormat::Gif),
+        "avif" => Some(OutputFormat::Avif),
+        "tiff" | "tif" => Some(OutputFormat::Tiff),
+        "bmp" => Some(OutputFormat::Bmp),
+        "jxl" => Some(OutputFormat::Jxl),
+        _ => None,
+    }
+}
+
+fn parse_filter(s: &str) -> Option<FilterType> {
+    match s.to_ascii_lowercase().as_str() {
+        "nearest" => Some(FilterType::Nearest),
+        "triangle" => Some(FilterType::Triangle),
+        "catmullrom" => Some(FilterType::CatmullRom),
+        "gaussian" => Some(FilterType::Gaussian),
+        "lanczos3" => Some(FilterType::Lanczos3),
+        _ => None,
     }
-    comp.finish_compress();
+}
 
-    let compressed = comp
-        .data_to_vec()
-        .map_err(|_| "data_to_vec failed".to_string())?;
-    Ok(compressed)
+/// Pulls the value following `flag` off the argument list and parses it.
+fn value<T: FromStr>(raw: &mut impl Iterator<Item = OsString>, flag: &str) -> Result<T, String> {
+    let v = raw
+        .next()
+        .ok_or_else(|| format!("{} requires a value", flag))?;
+    let v = v.to_string_lossy();
+    v.parse::<T>()
+        .map_err(|_| format!("invalid value for {}: {}", flag, v))
 }
 
+/// Waits for a key press. A closed or redirected stdin or stdout just ends
+/// the wait, since there is nobody to prompt.
 fn pause() {
     let mut stdin = io::stdin();
     let mut stdout = io::stdout();
 
-    write!(stdout, "Press any key to continue...").unwrap();
-    stdout.flush().unwrap();
+    let _ = write!(stdout, "Press any key to continue...");
+    let _ = stdout.flush();
+
+    let _ = stdin.read(&mut [0u8]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(|&arg| arg.into()))
+    }
+
+    /// An empty directory of its own for a test that writes files.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            env::temp_dir().join(format!("resize-image-main-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A tiny valid PNG.
+    fn write_png(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image::RgbImage::new(2, 2).save(path).unwrap();
+    }
+
+    #[test]
+    fn zip_is_committed_under_the_overwrite_policy() {
+        let dir = scratch_dir("zip");
+        write_png(&dir.join("a.png"));
+        let options = Options {
+            in_memory: true,
+            ..Options::default()
+        };
+        let results = vec![
+            resize_image::process(&dir.join("a.png"), Path::new(""), &options)
+                .map(|report| vec![report]),
+        ];
+        let archive = dir.join("out.zip");
+        fs::write(&archive, "old").unwrap();
+        let zip = |overwrite| {
+            let options = Options {
+                overwrite,
+                ..Options::default()
+            };
+            write_zip(&archive, &results, &options)
+        };
+
+        assert_eq!(zip(Overwrite::Skip), Ok(0));
+        assert_eq!(fs::read(&archive).unwrap(), b"old");
+        assert!(zip(Overwrite::Error).is_err());
+        assert_eq!(zip(Overwrite::Rename), Ok(0));
+        assert!(fs::read(dir.join("out_1.zip")).unwrap().starts_with(b"PK"));
+        assert_eq!(zip(Overwrite::Force), Ok(0));
+        assert!(fs::read(&archive).unwrap().starts_with(b"PK"));
+        let mut names = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a.png", "out.zip", "out_1.zip"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Each job's source and target directory, in source order.
+    fn sources(args: &[&str]) -> Vec<(PathBuf, PathBuf)> {
+        let mut jobs = collect_sources(&parse(args).unwrap())
+            .into_iter()
+            .map(|job| (job.source, job.target_dir))
+            .collect::<Vec<_>>();
+        jobs.sort();
+        jobs
+    }
+
+    #[test]
+    fn directory_input_goes_to_a_sibling_resized_directory() {
+        let root = scratch_dir("directory-input");
+        let tree = root.join("tree");
+        write_png(&tree.join("a.png"));
+        write_png(&tree.join("sub/b.png"));
+        let resized = root.join(RESIZED_DIR);
+        let tree_arg = tree.to_str().unwrap();
+
+        assert_eq!(
+            sources(&[tree_arg]),
+            [(tree.join("a.png"), resized.join("tree"))]
+        );
+        assert_eq!(
+            sources(&["-r", tree_arg]),
+            [
+                (tree.join("a.png"), resized.join("tree")),
+                (tree.join("sub/b.png"), resized.join("tree/sub")),
+            ]
+        );
+        assert_eq!(
+            sources(&["-r", "--flatten", tree_arg]),
+            [
+                (tree.join("a.png"), resized.clone()),
+                (tree.join("sub/b.png"), resized.clone()),
+            ]
+        );
+        let out = root.join("out");
+        assert_eq!(
+            sources(&["--out", out.to_str().unwrap(), tree_arg]),
+            [(tree.join("a.png"), out.join("tree"))]
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn parses_rotate_and_flip() {
+        let args = parse(&["--rotate", "90", "--flip", "h", "a.jpg"]).unwrap();
+        assert_eq!(args.options.rotate, Some(Rotation::R90));
+        assert_eq!(args.options.flip, Some(Flip::Horizontal));
+        let args = parse(&["--rotate", "270", "--flip", "v", "a.jpg"]).unwrap();
+        assert_eq!(args.options.rotate, Some(Rotation::R270));
+        assert_eq!(args.options.flip, Some(Flip::Vertical));
+        let args = parse(&["a.jpg"]).unwrap();
+        assert_eq!((args.options.rotate, args.options.flip), (None, None));
+    }
+
+    #[test]
+    fn rejects_other_rotations_and_flips() {
+        let err = parse(&["--rotate", "45", "a.jpg"]).err().unwrap();
+        assert!(err.contains("expected 90, 180 or 270"), "{}", err);
+        let err = parse(&["--flip", "x", "a.jpg"]).err().unwrap();
+        assert!(err.contains("expected h or v"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_non_utf8_paths_as_given() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let name = OsString::from_vec(b"caf\xe9.png".to_vec());
+        let args = parse_args(["--out".into(), name.clone(), name.clone()].into_iter()).unwrap();
+        assert_eq!(args.paths, std::slice::from_ref(&name));
+        assert_eq!(args.out.as_deref(), Some(Path::new(&name)));
+    }
+
+    #[test]
+    fn empty_files_are_skipped() {
+        let root = scratch_dir("empty-files");
+        let empty = root.join("empty.jpg");
+        fs::write(&empty, "").unwrap();
+        write_png(&root.join("full.png"));
+        let (empty_arg, dir_arg) = (empty.to_str().unwrap(), root.to_str().unwrap());
+
+        assert_eq!(sources(&[empty_arg]), []);
+        let resized = root.parent().unwrap().join(RESIZED_DIR);
+        let dir_name = root.file_name().unwrap();
+        assert_eq!(
+            sources(&[dir_arg]),
+            [(root.join("full.png"), resized.join(dir_name))]
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn bare_file_name_is_in_the_working_directory() {
+        assert_eq!(source_dir(Path::new("small.png")), Path::new(""));
+        assert_eq!(source_dir(Path::new("a/small.png")), Path::new("a"));
+        assert_eq!(source_dir(Path::new("/")), Path::new(""));
+    }
+
+    #[test]
+    fn rejects_trellis() {
+        for flag in ["--trellis", "--no-trellis"] {
+            let err = parse(&[flag, "a.jpg"]).err().unwrap();
+            assert!(err.contains("always on"), "{}", err);
+        }
+    }
 
-    let _ = stdin.read(&mut [0u8]).unwrap();
+    #[test]
+    fn cmyk_conversion_is_opt_in() {
+        assert!(!parse(&["a.jpg"]).unwrap().options.strip_to_baseline);
+        let args = parse(&["--strip-to-baseline", "a.jpg"]).unwrap();
+        assert!(args.options.strip_to_baseline);
+    }
+
+    #[test]
+    fn parses_dpi() {
+        assert_eq!(
+            parse(&["--dpi", "300", "a.jpg"]).unwrap().options.dpi,
+            Some(300)
+        );
+        assert_eq!(parse(&["a.jpg"]).unwrap().options.dpi, None);
+        assert!(parse(&["--dpi", "0", "a.jpg"]).is_err());
+        assert!(parse(&["--dpi", "70000", "a.jpg"]).is_err());
+    }
 }
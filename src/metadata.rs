@@ -0,0 +1,161 @@
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use exif::experimental::Writer;
+use exif::{Context, DateTime, In, Tag, Value};
+
+/// Identifies EXIF data in a JPEG APP1 segment.
+const JPEG_SIGNATURE: &[u8] = b"Exif\0\0";
+/// The most data one JPEG segment can hold after its length field.
+const JPEG_SEGMENT_SIZE: usize = 65533;
+
+/// Reads the EXIF data of an encoded image and rebuilds it as the body of a
+/// JPEG APP1 segment for the resized output. GPS tags are dropped unless
+/// `keep_gps` is set. Returns `None` when the source has no EXIF data, or
+/// when it can't be rewritten or doesn't fit in one segment.
+///
+/// The thumbnail, the orientation and the pixel dimensions describe the
+/// source rather than the output, which is already upright and resized, so
+/// they are left out.
+pub fn jpeg_exif_segment(data: &[u8], keep_gps: bool) -> Option<Vec<u8>> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()?;
+    let mut writer = Writer::new();
+    let mut fields = 0;
+    for field in exif.fields() {
+        let stale = matches!(
+            field.tag,
+            Tag::Orientation | Tag::PixelXDimension | Tag::PixelYDimension
+        );
+        let gps = field.tag.context() == Context::Gps;
+        if field.ifd_num == In::PRIMARY && !stale && (keep_gps || !gps) {
+            writer.push_field(field);
+            fields += 1;
+        }
+    }
+    if fields == 0 {
+        return None;
+    }
+    let mut tiff = Cursor::new(Vec::new());
+    writer.write(&mut tiff, exif.little_endian()).ok()?;
+    let segment = [JPEG_SIGNATURE, tiff.get_ref()].concat();
+    (segment.len() <= JPEG_SEGMENT_SIZE).then_some(segment)
+}
+
+/// Reads the day the image at `path` was taken from its EXIF
+/// DateTimeOriginal, as year, month and day. Returns `None` when it has no
+/// such tag, or only a blank placeholder like `0000:00:00 00:00:00`.
+pub fn capture_date(path: &Path) -> Option<(u16, u8, u8)> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+    let field = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)?;
+    let Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    let date = DateTime::from_ascii(values.first()?).ok()?;
+    let valid = date.year > 0 && (1..=12).contains(&date.month) && (1..=31).contains(&date.day);
+    valid.then_some((date.year, date.month, date.day))
+}
+
+#[cfg(test)]
+mod tests {
+    use exif::{Field, Rational, Value};
+
+    use super::*;
+    use crate::Options;
+
+    const TAKEN: &[u8] = b"2021:06:15 10:30:00";
+
+    /// A JPEG whose EXIF data holds a camera make, capture date, orientation
+    /// and GPS latitude.
+    fn photo() -> Vec<u8> {
+        let ascii = |tag, value: &[u8]| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![value.to_vec()]),
+        };
+        let fields = [
+            ascii(Tag::Make, b"Camera"),
+            ascii(Tag::DateTimeOriginal, TAKEN),
+            Field {
+                tag: Tag::Orientation,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![1]),
+            },
+            ascii(Tag::GPSLatitudeRef, b"N"),
+            Field {
+                tag: Tag::GPSLatitude,
+                ifd_num: In::PRIMARY,
+                value: Value::Rational(vec![Rational::from((35, 1)); 3]),
+            },
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        let segment = [JPEG_SIGNATURE, tiff.get_ref()].concat();
+
+        let jpeg = crate::compress(&[128; 8 * 8 * 3], 8, 8, 90.0).unwrap();
+        let mut out = jpeg[..2].to_vec();
+        out.extend_from_slice(&[0xFF, 0xE1]);
+        out.extend_from_slice(&(segment.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(&segment);
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    fn exif_of(jpeg: &[u8]) -> Option<exif::Exif> {
+        exif::Reader::new()
+            .read_from_container(&mut Cursor::new(jpeg))
+            .ok()
+    }
+
+    #[test]
+    fn keep_exif_carries_the_capture_date_into_the_output() {
+        let options = Options {
+            target_size: 4,
+            keep_exif: true,
+            ..Options::default()
+        };
+        let output = crate::process_bytes(&photo(), &options).unwrap();
+        let exif = exif_of(&output).unwrap();
+        let taken = exif.get_field(Tag::DateTimeOriginal, In::PRIMARY).unwrap();
+        assert!(matches!(&taken.value, Value::Ascii(v) if v == &[TAKEN]));
+        assert!(exif.get_field(Tag::Make, In::PRIMARY).is_some());
+        assert!(exif.get_field(Tag::Orientation, In::PRIMARY).is_none());
+        assert!(exif.get_field(Tag::GPSLatitude, In::PRIMARY).is_none());
+    }
+
+    #[test]
+    fn keep_gps_keeps_the_location() {
+        let options = Options {
+            target_size: 4,
+            keep_exif: true,
+            keep_gps: true,
+            ..Options::default()
+        };
+        let output = crate::process_bytes(&photo(), &options).unwrap();
+        let exif = exif_of(&output).unwrap();
+        assert!(exif.get_field(Tag::GPSLatitude, In::PRIMARY).is_some());
+    }
+
+    #[test]
+    fn exif_is_dropped_by_default() {
+        let options = Options {
+            target_size: 4,
+            ..Options::default()
+        };
+        let output = crate::process_bytes(&photo(), &options).unwrap();
+        assert!(exif_of(&output).is_none());
+    }
+
+    #[test]
+    fn nothing_to_keep_gives_no_segment() {
+        let jpeg = crate::compress(&[128; 8 * 8 * 3], 8, 8, 90.0).unwrap();
+        assert_eq!(jpeg_exif_segment(&jpeg, true), None);
+    }
+}
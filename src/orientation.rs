@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek};
+use std::path::Path;
+
+use exif::{In, Tag};
+use image::DynamicImage;
+
+/// Reads the EXIF orientation tag of `path`. Returns 1 (no transform) when
+/// the file has no EXIF data or the tag is missing or unreadable.
+pub fn read_orientation(path: &Path) -> u32 {
+    match File::open(path) {
+        Ok(file) => read_orientation_from(&mut BufReader::new(file)),
+        Err(_) => 1,
+    }
+}
+
+/// Like [`read_orientation`], but reads the EXIF data from an in-memory or
+/// already opened image.
+pub fn read_orientation_from<R: BufRead + Seek>(reader: &mut R) -> u32 {
+    let exif = match exif::Reader::new().read_from_container(reader) {
+        Ok(v) => v,
+        Err(_) => return 1,
+    };
+    exif.get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0))
+        .unwrap_or(1)
+}
+
+/// Rotates and flips `img` so that it displays upright for the given EXIF
+/// orientation value.
+pub fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// A rotation requested by the user, applied after the EXIF orientation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    R90,
+    R180,
+    R270,
+}
+
+impl Rotation {
+    /// Returns `true` if the rotation exchanges width and height.
+    pub fn swaps_dimensions(self) -> bool {
+        self != Rotation::R180
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flip {
+    Horizontal,
+    Vertical,
+}
+
+/// Rotates `img` clockwise and then mirrors it.
+pub fn apply_transform(
+    img: DynamicImage,
+    rotation: Option<Rotation>,
+    flip: Option<Flip>,
+) -> DynamicImage {
+    let img = match rotation {
+        Some(Rotation::R90) => img.rotate90(),
+        Some(Rotation::R180) => img.rotate180(),
+        Some(Rotation::R270) => img.rotate270(),
+        None => img,
+    };
+    match flip {
+        Some(Flip::Horizontal) => img.fliph(),
+        Some(Flip::Vertical) => img.flipv(),
+        None => img,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs;
+
+    use image::{GrayImage, Luma};
+
+    use super::*;
+
+    /// A 3x2 image whose top-left pixel alone is white.
+    fn marked() -> DynamicImage {
+        let mut img = GrayImage::new(3, 2);
+        img.put_pixel(0, 0, Luma([255]));
+        DynamicImage::ImageLuma8(img)
+    }
+
+    /// Where the marked pixel ends up, and the image's dimensions.
+    fn mark_after(orientation: u32) -> ((u32, u32), (u32, u32)) {
+        let img = apply_orientation(marked(), orientation).into_luma8();
+        let mark = img
+            .enumerate_pixels()
+            .find(|(_, _, px)| px[0] == 255)
+            .map(|(x, y, _)| (x, y))
+            .unwrap();
+        (mark, img.dimensions())
+    }
+
+    /// A JPEG whose EXIF data holds only an orientation tag.
+    pub(crate) fn jpeg_with_orientation(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II*\0\x08\0\0\0\x01\0".to_vec();
+        tiff.extend_from_slice(&0x0112u16.to_le_bytes());
+        tiff.extend_from_slice(&3u16.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&(orientation as u32).to_le_bytes());
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        let segment = [&b"Exif\0\0"[..], &tiff].concat();
+        let jpeg = crate::compress(&[128; 4 * 2 * 3], 4, 2, 90.0).unwrap();
+        let mut out = jpeg[..2].to_vec();
+        out.extend_from_slice(&[0xFF, 0xE1]);
+        out.extend_from_slice(&(segment.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(&segment);
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    #[test]
+    fn every_orientation_turns_the_image_upright() {
+        let expected = [
+            (1, (0, 0), (3, 2)),
+            (2, (2, 0), (3, 2)),
+            (3, (2, 1), (3, 2)),
+            (4, (0, 1), (3, 2)),
+            (5, (0, 0), (2, 3)),
+            (6, (1, 0), (2, 3)),
+            (7, (1, 2), (2, 3)),
+            (8, (0, 2), (2, 3)),
+        ];
+        for (orientation, mark, dimensions) in expected {
+            assert_eq!(
+                mark_after(orientation),
+                (mark, dimensions),
+                "orientation {}",
+                orientation
+            );
+        }
+    }
+
+    #[test]
+    fn transform_rotates_clockwise_then_mirrors() {
+        let mark = |rotation, flip| {
+            let img = apply_transform(marked(), rotation, flip).into_luma8();
+            let (x, y, _) = img
+                .enumerate_pixels()
+                .find(|(_, _, px)| px[0] == 255)
+                .unwrap();
+            ((x, y), img.dimensions())
+        };
+        assert_eq!(mark(Some(Rotation::R90), None), ((1, 0), (2, 3)));
+        assert_eq!(mark(Some(Rotation::R180), None), ((2, 1), (3, 2)));
+        assert_eq!(mark(Some(Rotation::R270), None), ((0, 2), (2, 3)));
+        assert_eq!(mark(None, Some(Flip::Horizontal)), ((2, 0), (3, 2)));
+        assert_eq!(mark(None, Some(Flip::Vertical)), ((0, 1), (3, 2)));
+        assert_eq!(
+            mark(Some(Rotation::R90), Some(Flip::Horizontal)),
+            ((0, 0), (2, 3))
+        );
+        assert!(Rotation::R90.swaps_dimensions());
+        assert!(!Rotation::R180.swaps_dimensions());
+        assert!(Rotation::R270.swaps_dimensions());
+    }
+
+    #[test]
+    fn unknown_orientation_leaves_the_image_alone() {
+        assert_eq!(mark_after(0), ((0, 0), (3, 2)));
+        assert_eq!(mark_after(9), ((0, 0), (3, 2)));
+    }
+
+    /// The orientation read back from `data` written to a file named `name`.
+    fn orientation_of(name: &str, data: &[u8]) -> u32 {
+        let path =
+            std::env::temp_dir().join(format!("resize-image-test-{}-{}", std::process::id(), name));
+        fs::write(&path, data).unwrap();
+        let orientation = read_orientation(&path);
+        fs::remove_file(&path).unwrap();
+        orientation
+    }
+
+    #[test]
+    fn reads_the_orientation_tag() {
+        for orientation in 1..=8 {
+            let jpeg = jpeg_with_orientation(orientation);
+            assert_eq!(
+                orientation_of(&format!("tagged-{}.jpg", orientation), &jpeg),
+                orientation as u32
+            );
+        }
+    }
+
+    #[test]
+    fn missing_exif_reads_as_upright() {
+        let jpeg = crate::compress(&[128; 4 * 2 * 3], 4, 2, 90.0).unwrap();
+        assert_eq!(orientation_of("plain.jpg", &jpeg), 1);
+        assert_eq!(orientation_of("garbage.jpg", b"not an image"), 1);
+        assert_eq!(read_orientation(Path::new("/nonexistent/photo.jpg")), 1);
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::{Cursor, Read, Seek};
+
+use image::error::{DecodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind};
+use image::{DynamicImage, ImageBuffer, ImageError, ImageFormat};
+use tiff::decoder::ifd::Value;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+use tiff::ColorType;
+
+use crate::encode::{encode_tiff_pages, TiffResolution};
+use crate::{Error, Options};
+
+/// How many pages a TIFF has; 1 for anything that can't be read as one.
+pub(crate) fn page_count<R: Read + Seek>(reader: R) -> usize {
+    let Ok(mut decoder) = Decoder::new(reader) else {
+        return 1;
+    };
+    let mut count = 1;
+    while decoder.more_images() && decoder.next_image().is_ok() {
+        count += 1;
+    }
+    count
+}
+
+/// The width and height of page `index` of a TIFF, from its header.
+pub(crate) fn page_dimensions<R: Read + Seek>(
+    reader: R,
+    index: usize,
+) -> Result<(u32, u32), Error> {
+    let mut decoder = Decoder::new(reader).map_err(decoding_error)?;
+    decoder.seek_to_image(index).map_err(decoding_error)?;
+    decoder.dimensions().map_err(decoding_error)
+}
+
+/// Decodes page `index` of a TIFF, in the color types `image` decodes a
+/// TIFF's first page in, along with its resolution when it has one.
+pub(crate) fn decode_page(
+    data: &[u8],
+    index: usize,
+) -> Result<(DynamicImage, Option<TiffResolution>), Error> {
+    let mut decoder = Decoder::new(Cursor::new(data)).map_err(decoding_error)?;
+    decoder.seek_to_image(index).map_err(decoding_error)?;
+    let (width, height) = decoder.dimensions().map_err(decoding_error)?;
+    let resolution = read_resolution(&mut decoder);
+    let color = decoder.colortype().map_err(decoding_error)?;
+    let img = match (color, decoder.read_image().map_err(decoding_error)?) {
+        (ColorType::Gray(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::GrayA(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+        }
+        (ColorType::RGB(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+        }
+        (ColorType::Gray(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+        }
+        (ColorType::GrayA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA16)
+        }
+        (ColorType::RGB(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16)
+        }
+        (ColorType::RGBA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16)
+        }
+        (color, _) => {
+            return Err(Error::Unsupported(ImageError::Unsupported(
+                UnsupportedError::from_format_and_kind(
+                    ImageFormatHint::Exact(ImageFormat::Tiff),
+                    UnsupportedErrorKind::GenericFeature(format!("{:?} pages", color)),
+                ),
+            )))
+        }
+    };
+    let img = img.ok_or_else(|| decoding_error("page data is cut short"))?;
+    Ok((img, resolution))
+}
+
+/// Resizes every page of a TIFF and encodes them as the pages of one TIFF.
+/// Each page keeps its physical size, so its resolution is scaled along
+/// with its pixels. Returns the encoded data and the first page's output
+/// width and height.
+pub(crate) fn resize_tiff(
+    data: &[u8],
+    options: &Options,
+) -> Result<(Vec<u8>, usize, usize), Error> {
+    let mut first = None;
+    let pages = (0..page_count(Cursor::new(data))).map(|index| {
+        let (img, resolution) = decode_page(data, index)?;
+        let (width, height) = (img.width() as f64, img.height() as f64);
+        let resized = crate::scale(img, options);
+        first.get_or_insert((resized.width, resized.height));
+        let resolution = resolution.map(|r| TiffResolution {
+            x: r.x * resized.width as f64 / width,
+            y: r.y * resized.height as f64 / height,
+            unit: r.unit,
+        });
+        Ok((resized, resolution))
+    });
+    let data = encode_tiff_pages(pages, options.tiff_compression)?;
+    let (width, height) = first.unwrap_or_default();
+    Ok((data, width, height))
+}
+
+/// Reads the page's X and Y resolution, which count per inch unless its
+/// resolution unit says otherwise.
+fn read_resolution<R: Read + Seek>(decoder: &mut Decoder<R>) -> Option<TiffResolution> {
+    let mut rational = |tag| match decoder.find_tag(tag) {
+        Ok(Some(Value::Rational(n, d))) if n > 0 && d > 0 => Some(n as f64 / d as f64),
+        _ => None,
+    };
+    let (x, y) = (rational(Tag::XResolution)?, rational(Tag::YResolution)?);
+    let unit = decoder
+        .find_tag_unsigned(Tag::ResolutionUnit)
+        .ok()
+        .flatten()
+        .unwrap_or(2);
+    Some(TiffResolution { x, y, unit })
+}
+
+fn decoding_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Error {
+    Error::Corrupt(ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Tiff),
+        e,
+    )))
+}
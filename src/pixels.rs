@@ -0,0 +1,111 @@
+/// Composites RGBA pixels onto a solid `background`, producing RGB pixels.
+pub fn flatten(rgba: &[u8], background: [u8; 3]) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(rgba.len() / 4 * 3);
+    for px in rgba.chunks_exact(4) {
+        let alpha = px[3] as u32;
+        for c in 0..3 {
+            let v = (px[c] as u32 * alpha + background[c] as u32 * (255 - alpha) + 127) / 255;
+            rgb.push(v as u8);
+        }
+    }
+    rgb
+}
+
+/// Expands grayscale pixels to RGB ones.
+pub fn gray_to_rgb(gray: &[u8]) -> Vec<u8> {
+    gray.iter().flat_map(|&v| [v, v, v]).collect()
+}
+
+/// Converts RGB or grayscale pixels to CMYK with the channels inverted, as
+/// Adobe writes CMYK JPEGs and decoders expect them. Inverted, each channel
+/// is the RGB one scaled by the brightest, and black is the brightest.
+pub(crate) fn to_inverted_cmyk(data: &[u8], channels: usize) -> Vec<u8> {
+    let mut cmyk = Vec::with_capacity(data.len() / channels * 4);
+    for px in data.chunks_exact(channels) {
+        let (r, g, b) = match *px {
+            [v] => (v, v, v),
+            [r, g, b, ..] => (r, g, b),
+            _ => unreachable!(),
+        };
+        let w = r.max(g).max(b) as u32;
+        let scale = |v: u8| (v as u32 * 255 + w / 2).checked_div(w).unwrap_or(255) as u8;
+        cmyk.extend([scale(r), scale(g), scale(b), w as u8]);
+    }
+    cmyk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flatten_blends_by_alpha_onto_the_background() {
+        let rgba = [
+            10, 20, 30, 255, // opaque
+            10, 20, 30, 0, // transparent
+            200, 0, 255, 128, // half
+        ];
+        assert_eq!(
+            flatten(&rgba, [0, 100, 255]),
+            [10, 20, 30, 0, 100, 255, 100, 50, 255]
+        );
+    }
+
+    #[test]
+    fn flatten_ignores_a_trailing_partial_pixel() {
+        assert_eq!(flatten(&[1, 2, 3, 255, 9, 9], [0, 0, 0]), [1, 2, 3]);
+        assert!(flatten(&[], [0, 0, 0]).is_empty());
+    }
+
+    #[test]
+    fn gray_to_rgb_repeats_each_value() {
+        assert_eq!(
+            gray_to_rgb(&[0, 128, 255]),
+            [0, 0, 0, 128, 128, 128, 255, 255, 255]
+        );
+        assert!(gray_to_rgb(&[]).is_empty());
+    }
+
+    #[test]
+    fn inverted_cmyk_scales_by_the_brightest_channel() {
+        let rgb = [
+            255, 255, 255, // white
+            0, 0, 0, // black
+            255, 0, 0, // red
+            100, 50, 0,
+        ];
+        assert_eq!(
+            to_inverted_cmyk(&rgb, 3),
+            [
+                255, 255, 255, 255, // white
+                255, 255, 255, 0, // black
+                255, 0, 0, 255, // red
+                255, 128, 0, 100,
+            ]
+        );
+    }
+
+    #[test]
+    fn inverted_cmyk_accepts_gray_and_rgba() {
+        assert_eq!(
+            to_inverted_cmyk(&[128, 0], 1),
+            [255, 255, 255, 128, 255, 255, 255, 0]
+        );
+        assert_eq!(to_inverted_cmyk(&[100, 50, 0, 7], 4), [255, 128, 0, 100]);
+    }
+
+    #[test]
+    fn inverted_cmyk_decodes_back_to_the_source() {
+        let rgb: Vec<u8> = (0..=255u8)
+            .step_by(15)
+            .flat_map(|v| [v, 255 - v, v / 2])
+            .collect();
+        let cmyk = to_inverted_cmyk(&rgb, 3);
+        for (px, ink) in rgb.chunks_exact(3).zip(cmyk.chunks_exact(4)) {
+            for c in 0..3 {
+                let decoded = ink[c] as i32 * ink[3] as i32 / 255;
+                assert!((decoded - px[c] as i32).abs() <= 1, "{px:?} -> {ink:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+/// Removes every APP1 to APP15 and comment segment from a JPEG, which is
+/// where EXIF, GPS, XMP and ICC data live. The JFIF APP0 header and all
+/// image data are kept. Data that doesn't parse as a JPEG is returned as is.
+pub fn strip_jpeg(data: &[u8]) -> Vec<u8> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return data.to_vec();
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        // From the first scan on only image data follows.
+        if marker == 0xDA {
+            break;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = (pos + 2 + len).min(data.len());
+        if !matches!(marker, 0xE1..=0xEF | 0xFE) {
+            out.extend_from_slice(&data[pos..end]);
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&data[pos..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The markers of the segments before the first scan.
+    fn markers(jpeg: &[u8]) -> Vec<u8> {
+        let mut markers = Vec::new();
+        let mut pos = 2;
+        while pos + 4 <= jpeg.len() && jpeg[pos + 1] != 0xDA {
+            markers.push(jpeg[pos + 1]);
+            pos += 2 + u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        }
+        markers
+    }
+
+    fn with_segment(jpeg: &[u8], marker: u8, body: &[u8]) -> Vec<u8> {
+        let mut out = jpeg[..2].to_vec();
+        out.extend_from_slice(&[0xFF, marker]);
+        out.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(body);
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    #[test]
+    fn removes_exif_xmp_icc_and_comments() {
+        let jpeg = crate::orientation::tests::jpeg_with_orientation(6);
+        let jpeg = with_segment(&jpeg, 0xE2, b"ICC_PROFILE\0\x01\x01profile");
+        let jpeg = with_segment(&jpeg, 0xE1, b"http://ns.adobe.com/xap/1.0/\0<x/>");
+        let jpeg = with_segment(&jpeg, 0xFE, b"a comment");
+        assert!(markers(&jpeg).contains(&0xE1));
+
+        let stripped = strip_jpeg(&jpeg);
+        assert!(markers(&stripped)
+            .iter()
+            .all(|m| !matches!(m, 0xE1..=0xEF | 0xFE)));
+        assert!(!stripped.windows(6).any(|w| w == b"Exif\0\0"));
+        let decoded = image::load_from_memory(&stripped).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (4, 2));
+    }
+
+    #[test]
+    fn strip_output_has_no_exif_even_when_asked_to_keep_it() {
+        let jpeg = crate::orientation::tests::jpeg_with_orientation(1);
+        let options = crate::Options {
+            keep_exif: true,
+            strip: true,
+            ..crate::Options::default()
+        };
+        let output = crate::process_bytes(&jpeg, &options).unwrap();
+        assert!(markers(&output).iter().all(|m| !matches!(m, 0xE1..=0xEF)));
+    }
+
+    #[test]
+    fn leaves_other_data_alone() {
+        assert_eq!(strip_jpeg(b"not a jpeg"), b"not a jpeg");
+        let jpeg = crate::compress(&[0; 4 * 4 * 3], 4, 4, 80.0).unwrap();
+        assert_eq!(strip_jpeg(&jpeg), jpeg);
+    }
+}
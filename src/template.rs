@@ -0,0 +1,126 @@
+use std::ffi::{OsStr, OsString};
+use std::str::FromStr;
+
+/// The placeholders a [`NameTemplate`] knows, in the order they are listed
+/// in its errors.
+const PLACEHOLDERS: [&str; 6] = ["name", "ext", "width", "height", "parent", "index"];
+
+/// Names outputs from a pattern like `{name}-{width}x{height}.{ext}`, in
+/// place of the prefix, stem, suffix and extension.
+///
+/// `{name}` is the source's file stem, or the job's own stem when it has
+/// one. `{ext}` is the output's extension, `{width}` and `{height}` its
+/// dimensions, `{parent}` the name of the source's directory and `{index}`
+/// the job's number, which is empty for a job without one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NameTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(&'static str),
+}
+
+/// What a [`NameTemplate`] is expanded with.
+pub(crate) struct Fields<'a> {
+    pub(crate) name: &'a OsStr,
+    pub(crate) ext: &'a OsStr,
+    pub(crate) dimensions: Option<(u32, u32)>,
+    pub(crate) parent: &'a OsStr,
+    pub(crate) index: Option<usize>,
+}
+
+impl NameTemplate {
+    /// Whether the template has `{width}` or `{height}`, which need the
+    /// output's size before it is known.
+    pub(crate) fn needs_dimensions(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Placeholder("width" | "height")))
+    }
+
+    /// Builds the file name, keeping names that aren't valid UTF-8 byte for
+    /// byte.
+    pub(crate) fn expand(&self, fields: &Fields) -> OsString {
+        let mut name = OsString::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => name.push(text),
+                Part::Placeholder("name") => name.push(fields.name),
+                Part::Placeholder("ext") => name.push(fields.ext),
+                Part::Placeholder("parent") => name.push(fields.parent),
+                Part::Placeholder(field) => {
+                    let value = match *field {
+                        "width" => fields.dimensions.map(|(width, _)| width as usize),
+                        "height" => fields.dimensions.map(|(_, height)| height as usize),
+                        _ => fields.index,
+                    };
+                    if let Some(value) = value {
+                        name.push(value.to_string());
+                    }
+                }
+            }
+        }
+        name
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    /// Accepts `{{` and `}}` for literal braces. Rejects unknown placeholders
+    /// and path separators, since outputs are named within their target
+    /// directory.
+    fn from_str(template: &str) -> Result<Self, String> {
+        if template.contains(['/', '\\']) {
+            return Err("it cannot contain path separators".to_string());
+        }
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| "it has an unclosed {".to_string())?;
+                    let field = PLACEHOLDERS
+                        .into_iter()
+                        .find(|&p| p == &rest[..end])
+                        .ok_or_else(|| {
+                            format!(
+                                "{{{}}} is not a placeholder (expected {})",
+                                &rest[..end],
+                                PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+                            )
+                        })?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(field));
+                    chars = rest[end + 1..].chars();
+                }
+                '}' => return Err("it has an unmatched }".to_string()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        if parts.is_empty() {
+            return Err("it is empty".to_string());
+        }
+        Ok(NameTemplate { parts })
+    }
+}
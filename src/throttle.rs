@@ -0,0 +1,91 @@
+use std::sync::{Condvar, Mutex};
+
+/// Caps how many threads read or write files at once, independently of how
+/// many are resizing and encoding. On spinning disks, many concurrent reads
+/// of large files seek back and forth and end up slower than reading them
+/// one after another.
+pub struct IoLimit {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl IoLimit {
+    /// Allows `permits` concurrent I/O sections. Zero is treated as one.
+    pub fn new(permits: usize) -> Self {
+        IoLimit {
+            available: Mutex::new(permits.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Runs `f` once a permit is free, blocking the current thread until then.
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        {
+            let mut available = self.available.lock().unwrap();
+            while *available == 0 {
+                available = self.released.wait(available).unwrap();
+            }
+            *available -= 1;
+        }
+        let _permit = Permit(self);
+        f()
+    }
+}
+
+/// Caps the memory that files being decoded, resized and encoded at once may
+/// take, as estimated from their dimensions. Files wait for room to free up
+/// before they are decoded.
+pub struct MemoryLimit {
+    limit: u64,
+    available: Mutex<u64>,
+    released: Condvar,
+}
+
+impl MemoryLimit {
+    pub fn new(bytes: u64) -> Self {
+        MemoryLimit {
+            limit: bytes,
+            available: Mutex::new(bytes),
+            released: Condvar::new(),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Blocks until `bytes` are free and holds them until the reservation is
+    /// dropped. Returns `None` without waiting when `bytes` exceed the whole
+    /// limit and could never be granted.
+    pub(crate) fn reserve(&self, bytes: u64) -> Option<Reservation<'_>> {
+        if bytes > self.limit {
+            return None;
+        }
+        let mut available = self.available.lock().unwrap();
+        while *available < bytes {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= bytes;
+        Some(Reservation(self, bytes))
+    }
+}
+
+pub(crate) struct Reservation<'a>(&'a MemoryLimit, u64);
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap() += self.1;
+        // Waiters need different amounts, so each checks for itself.
+        self.0.released.notify_all();
+    }
+}
+
+/// Returns the permit even if `f` panics.
+struct Permit<'a>(&'a IoLimit);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.available.lock().unwrap() += 1;
+        self.0.released.notify_one();
+    }
+}
@@ -0,0 +1,365 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageBuffer, Pixel};
+use rayon::prelude::*;
+
+/// A channel value that can be accumulated as `f32`.
+trait Sample: Copy + Send + Sync {
+    fn to_f32(self) -> f32;
+    /// Rounds and clamps to the type's range, as `image` does.
+    fn from_f32(v: f32) -> Self;
+}
+
+impl Sample for u8 {
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn from_f32(v: f32) -> Self {
+        v.round().clamp(0.0, u8::MAX as f32) as u8
+    }
+}
+
+impl Sample for u16 {
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn from_f32(v: f32) -> Self {
+        v.round().clamp(0.0, u16::MAX as f32) as u16
+    }
+}
+
+impl Sample for f32 {
+    fn to_f32(self) -> f32 {
+        self
+    }
+    fn from_f32(v: f32) -> Self {
+        v.clamp(0.0, 1.0)
+    }
+}
+
+/// Resizes `img` to exactly `width` x `height` like
+/// [`DynamicImage::resize_exact`], but resamples up to `bands` bands of
+/// output rows in parallel in each of the two passes. Every output pixel
+/// still reads the filter's whole support from the full source, so bands
+/// overlap in what they sample and leave no seams between them.
+pub fn resize_exact(
+    img: &DynamicImage,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+    bands: usize,
+) -> DynamicImage {
+    match img {
+        DynamicImage::ImageLuma8(buf) => {
+            DynamicImage::ImageLuma8(resize(buf, width, height, filter, bands))
+        }
+        DynamicImage::ImageLumaA8(buf) => {
+            DynamicImage::ImageLumaA8(resize(buf, width, height, filter, bands))
+        }
+        DynamicImage::ImageRgb8(buf) => {
+            DynamicImage::ImageRgb8(resize(buf, width, height, filter, bands))
+        }
+        DynamicImage::ImageRgba8(buf) => {
+            DynamicImage::ImageRgba8(resize(buf, width, height, filter, bands))
+        }
+        DynamicImage::ImageLuma16(buf) => {
+            DynamicImage::ImageLuma16(resize(buf, width, height, filter, bands))
+        }
+        DynamicImage::ImageLumaA16(buf) => {
+            DynamicImage::ImageLumaA16(resize(buf, width, height, filter, bands))
+        }
+        DynamicImage::ImageRgb16(buf) => {
+            DynamicImage::ImageRgb16(resize(buf, width, height, filter, bands))
+        }
+        DynamicImage::ImageRgba16(buf) => {
+            DynamicImage::ImageRgba16(resize(buf, width, height, filter, bands))
+        }
+        DynamicImage::ImageRgb32F(buf) => {
+            DynamicImage::ImageRgb32F(resize(buf, width, height, filter, bands))
+        }
+        DynamicImage::ImageRgba32F(buf) => {
+            DynamicImage::ImageRgba32F(resize(buf, width, height, filter, bands))
+        }
+        img => img.resize_exact(width, height, filter),
+    }
+}
+
+fn resize<P>(
+    buf: &ImageBuffer<P, Vec<P::Subpixel>>,
+    width: u32,
+    height: u32,
+    filter: FilterType,
+    bands: usize,
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel,
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let (src_width, src_height) = (buf.width() as usize, buf.height() as usize);
+    let (width, height) = (width as usize, height as usize);
+    let bands = bands.max(1);
+    // Only the samples are shared with the workers, not `P` itself.
+    let samples = buf.as_raw().as_slice();
+
+    // Vertical pass first, as in `image`, into unrounded samples.
+    let weights = weights(src_height, height, filter);
+    let row = src_width * channels;
+    let mut columns = vec![0.0f32; row * height];
+    let band_rows = height.div_ceil(bands).max(1);
+    columns
+        .par_chunks_mut(row * band_rows)
+        .enumerate()
+        .for_each(|(band, out)| {
+            for (i, out_row) in out.chunks_exact_mut(row).enumerate() {
+                let (start, taps) = &weights[band * band_rows + i];
+                for (k, &w) in taps.iter().enumerate() {
+                    let src = &samples[(start + k) * row..][..row];
+                    for (o, &s) in out_row.iter_mut().zip(src) {
+                        *o += s.to_f32() * w;
+                    }
+                }
+            }
+        });
+
+    let weights = self::weights(src_width, width, filter);
+    let out_row_len = width * channels;
+    let mut data = vec![P::Subpixel::from_f32(0.0); out_row_len * height];
+    data.par_chunks_mut(out_row_len * band_rows)
+        .enumerate()
+        .for_each(|(band, out)| {
+            for (i, out_row) in out.chunks_exact_mut(out_row_len).enumerate() {
+                let src = &columns[(band * band_rows + i) * row..][..row];
+                for (x, px) in out_row.chunks_exact_mut(channels).enumerate() {
+                    let (start, taps) = &weights[x];
+                    for (c, v) in px.iter_mut().enumerate() {
+                        let sum = taps
+                            .iter()
+                            .enumerate()
+                            .map(|(k, &w)| src[(start + k) * channels + c] * w)
+                            .sum::<f32>();
+                        *v = P::Subpixel::from_f32(sum);
+                    }
+                }
+            }
+        });
+    ImageBuffer::from_raw(width as u32, height as u32, data).unwrap()
+}
+
+/// The first source index and the normalized weights of the taps that make
+/// up each of `dst` output samples along one axis, placed as `image` places
+/// them.
+fn weights(src: usize, dst: usize, filter: FilterType) -> Vec<(usize, Vec<f32>)> {
+    let (kernel, support): (fn(f32) -> f32, f32) = match filter {
+        FilterType::Nearest => (|x| if x.abs() <= 0.5 { 1.0 } else { 0.0 }, 0.0),
+        FilterType::Triangle => (|x| (1.0 - x.abs()).max(0.0), 1.0),
+        FilterType::CatmullRom => (|x| cubic(x, 0.0, 0.5), 2.0),
+        FilterType::Gaussian => (|x| gaussian(x, 0.5), 3.0),
+        FilterType::Lanczos3 => (|x| lanczos(x, 3.0), 3.0),
+    };
+    let ratio = src as f32 / dst as f32;
+    // Downscaling widens the filter to cover every source sample.
+    let scale = ratio.max(1.0);
+    let support = support * scale;
+    (0..dst)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * ratio;
+            let left = ((center - support).floor() as i64).clamp(0, src as i64 - 1) as usize;
+            let right =
+                ((center + support).ceil() as i64).clamp(left as i64 + 1, src as i64) as usize;
+            let center = center - 0.5;
+            let mut taps = (left..right)
+                .map(|j| kernel((j as f32 - center) / scale))
+                .collect::<Vec<_>>();
+            let sum = taps.iter().sum::<f32>();
+            if sum != 0.0 {
+                taps.iter_mut().for_each(|w| *w /= sum);
+            }
+            (left, taps)
+        })
+        .collect()
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * std::f32::consts::PI;
+        x.sin() / x
+    }
+}
+
+fn lanczos(x: f32, t: f32) -> f32 {
+    if x.abs() < t {
+        sinc(x) * sinc(x / t)
+    } else {
+        0.0
+    }
+}
+
+fn gaussian(x: f32, r: f32) -> f32 {
+    ((2.0 * std::f32::consts::PI).sqrt() * r).recip() * (-x.powi(2) / (2.0 * r.powi(2))).exp()
+}
+
+/// The BC-spline family of cubic filters.
+fn cubic(x: f32, b: f32, c: f32) -> f32 {
+    let a = x.abs();
+    let k = if a < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * a.powi(3)
+            + (-18.0 + 12.0 * b + 6.0 * c) * a.powi(2)
+            + (6.0 - 2.0 * b)
+    } else if a < 2.0 {
+        (-b - 6.0 * c) * a.powi(3)
+            + (6.0 * b + 30.0 * c) * a.powi(2)
+            + (-12.0 * b - 48.0 * c) * a
+            + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+    k / 6.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, GrayImage, Rgb, Rgb32FImage, RgbImage, RgbaImage};
+
+    const FILTERS: [FilterType; 5] = [
+        FilterType::Nearest,
+        FilterType::Triangle,
+        FilterType::CatmullRom,
+        FilterType::Gaussian,
+        FilterType::Lanczos3,
+    ];
+
+    /// Band counts up to and past every output height used below.
+    const BANDS: [usize; 6] = [1, 2, 3, 7, 29, 200];
+
+    /// A brightness in 0..=1 with hard edges, which Lanczos rings around,
+    /// over a gradient.
+    fn level(x: u32, y: u32, c: u32) -> f32 {
+        let checker = if (x / 5 + y / 7) & 1 == 0 { 0.8 } else { 0.0 };
+        checker + ((x + 2 * y + 9 * c) % 50) as f32 / 250.0
+    }
+
+    fn rgb8(width: u32, height: u32) -> DynamicImage {
+        let img = RgbImage::from_fn(width, height, |x, y| {
+            Rgb([0, 1, 2].map(|c| (level(x, y, c) * 255.0) as u8))
+        });
+        DynamicImage::ImageRgb8(img)
+    }
+
+    fn rgb16(width: u32, height: u32) -> DynamicImage {
+        let img = ImageBuffer::from_fn(width, height, |x, y| {
+            Rgb([0, 1, 2].map(|c| (level(x, y, c) * 65535.0) as u16))
+        });
+        DynamicImage::ImageRgb16(img)
+    }
+
+    fn rgb32f(width: u32, height: u32) -> DynamicImage {
+        let img =
+            Rgb32FImage::from_fn(width, height, |x, y| Rgb([0, 1, 2].map(|c| level(x, y, c))));
+        DynamicImage::ImageRgb32F(img)
+    }
+
+    /// The largest difference between two images' samples, which must be
+    /// of the same type and size.
+    fn max_diff(a: &DynamicImage, b: &DynamicImage) -> f64 {
+        fn diff<T: Copy + Into<f64>>(a: &[T], b: &[T]) -> f64 {
+            assert_eq!(a.len(), b.len());
+            a.iter()
+                .zip(b)
+                .map(|(&a, &b)| (a.into() - b.into()).abs())
+                .fold(0.0, f64::max)
+        }
+        assert_eq!(a.dimensions(), b.dimensions());
+        match (a, b) {
+            (DynamicImage::ImageRgb16(a), DynamicImage::ImageRgb16(b)) => diff(a, b),
+            (DynamicImage::ImageRgb32F(a), DynamicImage::ImageRgb32F(b)) => diff(a, b),
+            _ => {
+                assert_eq!(a.color(), b.color());
+                diff(a.as_bytes(), b.as_bytes())
+            }
+        }
+    }
+
+    /// Checks `resize_exact` against `image` for shrinking and enlarging
+    /// `img`, with every band count.
+    fn assert_matches_image(img: &DynamicImage, filter: FilterType, tolerance: f64) {
+        for (width, height) in [(20, 29), (61, 80)] {
+            let expected = img.resize_exact(width, height, filter);
+            for bands in BANDS {
+                let resized = resize_exact(img, width, height, filter, bands);
+                let diff = max_diff(&resized, &expected);
+                assert!(
+                    diff <= tolerance,
+                    "{:?} to {}x{} in {} bands is off by {}",
+                    filter,
+                    width,
+                    height,
+                    bands,
+                    diff
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn matches_image_for_every_filter() {
+        let img = rgb8(37, 53);
+        for filter in FILTERS {
+            assert_matches_image(&img, filter, 1.0);
+        }
+    }
+
+    #[test]
+    fn matches_image_for_8_bit_layouts() {
+        let gray = GrayImage::from_fn(37, 53, |x, y| image::Luma([(level(x, y, 0) * 255.0) as u8]));
+        let rgba = RgbaImage::from_fn(37, 53, |x, y| {
+            image::Rgba([0, 1, 2, 3].map(|c| (level(x, y, c) * 255.0) as u8))
+        });
+        for img in [
+            DynamicImage::ImageLuma8(gray),
+            DynamicImage::ImageRgba8(rgba),
+        ] {
+            assert_matches_image(&img, FilterType::Lanczos3, 1.0);
+        }
+    }
+
+    #[test]
+    fn matches_image_for_16_bit_samples() {
+        assert_matches_image(&rgb16(37, 53), FilterType::Lanczos3, 1.0);
+    }
+
+    #[test]
+    fn matches_image_for_float_samples() {
+        assert_matches_image(&rgb32f(37, 53), FilterType::Lanczos3, 1.0 / 65535.0);
+    }
+
+    #[test]
+    fn band_boundaries_leave_no_seams() {
+        let img = rgb8(37, 53);
+        let (width, height) = (20, 29);
+        let whole = resize_exact(&img, width, height, FilterType::Lanczos3, 1);
+        let whole = whole.as_rgb8().unwrap();
+        for bands in BANDS {
+            let banded = resize_exact(&img, width, height, FilterType::Lanczos3, bands);
+            let banded = banded.as_rgb8().unwrap();
+            let band_rows = (height as usize).div_ceil(bands) as u32;
+            for boundary in (band_rows..height).step_by(band_rows as usize) {
+                for y in [boundary - 1, boundary] {
+                    for x in 0..width {
+                        assert_eq!(
+                            banded.get_pixel(x, y),
+                            whole.get_pixel(x, y),
+                            "{} bands, pixel {},{}",
+                            bands,
+                            x,
+                            y
+                        );
+                    }
+                }
+            }
+            assert_eq!(banded, whole, "{} bands", bands);
+        }
+    }
+}
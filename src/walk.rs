@@ -0,0 +1,67 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::ImageFormat;
+
+/// Recursively collects every file under `dir` whose extension is a
+/// supported image format. Each directory is visited at most once, so
+/// symlink loops can't make the walk spin forever.
+pub fn walk_dir(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    walk_dir_filtered(dir, is_image)
+}
+
+/// Like [`walk_dir`], but collects the files `keep` accepts.
+pub fn walk_dir_filtered(dir: &Path, keep: impl Fn(&Path) -> bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    walk(dir, &keep, &mut visited, &mut files)?;
+    Ok(files)
+}
+
+/// Like [`walk_dir_filtered`], but only collects the files directly in
+/// `dir`, in name order.
+pub fn read_dir_filtered(dir: &Path, keep: impl Fn(&Path) -> bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && keep(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn walk(
+    dir: &Path,
+    keep: &dyn Fn(&Path) -> bool,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    if !visited.insert(fs::canonicalize(dir)?) {
+        return Ok(());
+    }
+
+    let mut entries = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+
+    for path in entries {
+        if path.is_dir() {
+            walk(&path, keep, visited, files)?;
+        } else if path.is_file() && keep(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` if the extension of `path` is one `image` can decode.
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(ImageFormat::from_extension)
+        .is_some()
+}
@@ -0,0 +1,45 @@
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
+
+/// The watermark's longest edge as a fraction of the output's shortest edge.
+const SIZE_FRACTION: f32 = 0.25;
+/// Gap between the watermark and the image edges, as a fraction of the
+/// output's shortest edge.
+const MARGIN_FRACTION: f32 = 0.02;
+
+/// Where the watermark is placed on the output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatermarkPosition {
+    TopLeft,
+    BottomRight,
+    Center,
+}
+
+/// Scales `mark` to the size of `img` and composites it at `position`,
+/// multiplying its alpha by `opacity`.
+pub fn apply(img: &mut DynamicImage, mark: &RgbaImage, position: WatermarkPosition, opacity: f32) {
+    let short_edge = img.width().min(img.height()) as f32;
+    let ratio = short_edge * SIZE_FRACTION / mark.width().max(mark.height()) as f32;
+    let (width, height) = (
+        ((mark.width() as f32 * ratio) as u32).max(1),
+        ((mark.height() as f32 * ratio) as u32).max(1),
+    );
+    let mut mark = imageops::resize(mark, width, height, FilterType::Lanczos3);
+    if opacity < 1.0 {
+        for px in mark.pixels_mut() {
+            px[3] = (px[3] as f32 * opacity).round() as u8;
+        }
+    }
+
+    let margin = (short_edge * MARGIN_FRACTION) as i64;
+    let (free_x, free_y) = (
+        img.width() as i64 - mark.width() as i64,
+        img.height() as i64 - mark.height() as i64,
+    );
+    let (x, y) = match position {
+        WatermarkPosition::TopLeft => (margin, margin),
+        WatermarkPosition::BottomRight => (free_x - margin, free_y - margin),
+        WatermarkPosition::Center => (free_x / 2, free_y / 2),
+    };
+    imageops::overlay(img, &DynamicImage::ImageRgba8(mark), x, y);
+}